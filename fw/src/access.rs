@@ -0,0 +1,100 @@
+//! Accessibility filters, between the debouncer and the layout.
+//!
+//! Slow keys accept a press only once the key has been held for a while, so
+//! that brushing a key doesn't type it. Bounce keys ignore a press that comes
+//! soon after the same key was released, so that a tremor doesn't type a key
+//! twice. Each is off when its time is 0, and then presses and releases pass
+//! through in the same scan that the debouncer emits them.
+
+use crate::scan::Event;
+
+#[derive(Clone, Copy, Default)]
+struct Key {
+    /// Is the key pressed, as far as the layout is concerned?
+    accepted: bool,
+    /// When the debouncer emitted the press of the key, if it's held.
+    pressed: Option<u32>,
+    /// When the debouncer last emitted a release of the key.
+    released: Option<u32>,
+    /// Is the press of the key being ignored by bounce keys?
+    bounced: bool,
+}
+
+/// The keys accepted by the accessibility filters.
+pub struct Access<const R: usize, const C: usize> {
+    keys: [[Key; R]; C],
+}
+
+impl<const R: usize, const C: usize> Access<R, C> {
+    /// Start out accepting the keys that start out held, such as those
+    /// restored from a snapshot, without emitting their presses again.
+    ///
+    /// `held` has a bit per row for each column, set when that key is held.
+    pub fn new(held: &[u16; C], now: u32) -> Self {
+        let mut keys = [[Key::default(); R]; C];
+        for (key_row, bits) in keys.iter_mut().zip(held.iter()) {
+            for (row, key) in key_row.iter_mut().enumerate() {
+                if bits & (1 << row) != 0 {
+                    key.accepted = true;
+                    key.pressed = Some(now);
+                }
+            }
+        }
+        Self { keys }
+    }
+
+    /// Filter the keys held according to the debouncer, returning a bit per
+    /// row for each column, set when the key is accepted as pressed.
+    ///
+    /// `slow` is the number of scans a key must be held before its press is
+    /// accepted, and `bounce` is the number of scans after a key is released
+    /// during which a new press of it is ignored. `on_event` is called for every
+    /// press and release that's accepted.
+    pub fn filter(
+        &mut self,
+        held: &[u16; C],
+        now: u32,
+        slow: u32,
+        bounce: u32,
+        mut on_event: impl FnMut(Event),
+    ) -> [u16; C] {
+        let mut pressed = [0; C];
+        for (col, (key_row, bits)) in self.keys.iter_mut().zip(held.iter()).enumerate() {
+            for (row, key) in key_row.iter_mut().enumerate() {
+                match (bits & (1 << row) != 0, key.pressed) {
+                    (true, None) => {
+                        key.bounced = key
+                            .released
+                            .map_or(false, |released| now.wrapping_sub(released) < bounce);
+                        key.pressed = Some(now);
+                    }
+                    (false, Some(_)) => {
+                        key.pressed = None;
+                        key.released = Some(now);
+                    }
+                    _ => (),
+                }
+                // Once a press is accepted, it stays accepted until the key is
+                // released, even if the settings change while it's held.
+                let accepted = match key.pressed {
+                    Some(since) => {
+                        key.accepted || (!key.bounced && now.wrapping_sub(since) >= slow)
+                    }
+                    None => false,
+                };
+                if accepted != key.accepted {
+                    key.accepted = accepted;
+                    on_event(if accepted {
+                        Event::Press(row as u8, col as u8)
+                    } else {
+                        Event::Release(row as u8, col as u8)
+                    });
+                }
+                if accepted {
+                    pressed[col] |= 1 << row;
+                }
+            }
+        }
+        pressed
+    }
+}
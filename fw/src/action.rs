@@ -0,0 +1,220 @@
+//! Actions that a key may perform, and the layouts that map keys to them.
+
+use crate::composite::Consumer;
+use crate::host::HostOs;
+use crate::key_code::KeyCode;
+
+/// What a key does when it's pressed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Report the key code to the host for as long as the key is held.
+    KeyCode(KeyCode),
+    /// Use the layer with the given index, within the active keymap, for as
+    /// long as the key is held. This only has an effect in the first layer.
+    Layer(u8),
+    /// Toggle between the gaming profile and whatever profile was active
+    /// before it.
+    GamingMode,
+    /// Make the profile with the given id active.
+    Profile(u8),
+    /// Adapt to the given host OS rather than the one detected during
+    /// enumeration, or, with `None`, go back to the detected OS.
+    Host(Option<HostOs>),
+    /// Move the pointer to the cell at the given column and row of the warp
+    /// grid. See [`crate::warp`].
+    Warp(u8, u8),
+    /// Turn the typing tutor, which stops some key codes from being
+    /// reported, on or off. See [`crate::tutor`].
+    Tutor,
+    /// Turn chorded entry on or off. See [`crate::chord`].
+    ChordMode,
+    /// Toggle the given remappings, from the `Remap` constants, in the active
+    /// profile.
+    Remap(u8),
+    /// Switch the debouncer between eager and deferred debouncing, to compare
+    /// how they feel. See [`crate::trigger::Mode`].
+    DebounceMode,
+    /// Type the firmware version and some settings. See [`crate::status`].
+    Status,
+    /// Write a marker with the given id into the debouncer's log, and the
+    /// feature report, to find the moment a key misbehaved in a capture.
+    LogMarker(u8),
+    /// Call the custom action with the given id. See [`crate::custom`].
+    Custom(u8),
+    /// Report the consumer control to the host for as long as the key is
+    /// held, whatever the host OS. Unlike the media key codes, this covers
+    /// controls such as display brightness.
+    Consumer(Consumer),
+}
+
+impl Action {
+    /// Size of an `Action` when converted to bytes.
+    pub const SIZE: usize = 2;
+
+    /// Convert an action to bytes, for the host: a byte for the kind of
+    /// action, then a byte for its argument, or 0 if it has none.
+    ///
+    /// Byte 0 | Action         | Byte 1
+    /// -------|----------------|---------------------------------------------
+    /// 1      | `KeyCode`      | the HID usage of the key code
+    /// 2      | `Layer`        | the layer
+    /// 3      | `GamingMode`   | 0
+    /// 4      | `Profile`      | the profile id
+    /// 5      | `Host`         | the host OS, plus 1, or 0 for the detected OS
+    /// 6      | `Warp`         | the column, times 16, plus the row
+    /// 7      | `Tutor`        | 0
+    /// 8      | `ChordMode`    | 0
+    /// 9      | `Remap`        | the remappings
+    /// 10     | `DebounceMode` | 0
+    /// 11     | `Status`       | 0
+    /// 12     | `LogMarker`    | the marker id
+    /// 13     | `Custom`       | the custom action id
+    /// 14     | `Consumer`     | the usage of the control, in the consumer page
+    ///
+    /// Every consumer control's usage is checked to fit in its byte when the
+    /// firmware is built.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        match *self {
+            Action::KeyCode(kc) => [1, kc.usage()],
+            Action::Layer(layer) => [2, layer],
+            Action::GamingMode => [3, 0],
+            Action::Profile(id) => [4, id],
+            Action::Host(os) => [5, os.map_or(0, |os| os as u8 + 1)],
+            Action::Warp(col, row) => [6, (col << 4) | row],
+            Action::Tutor => [7, 0],
+            Action::ChordMode => [8, 0],
+            Action::Remap(flags) => [9, flags],
+            Action::DebounceMode => [10, 0],
+            Action::Status => [11, 0],
+            Action::LogMarker(id) => [12, id],
+            Action::Custom(id) => [13, id],
+            Action::Consumer(control) => [14, control.usage() as u8],
+        }
+    }
+
+    /// Parse an action produced by `to_bytes`, returning `None` if it's
+    /// malformed.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let action = match *bytes {
+            [1, usage, ..] => Action::KeyCode(KeyCode::from_usage(usage)?),
+            [2, layer, ..] => Action::Layer(layer),
+            [3, 0, ..] => Action::GamingMode,
+            [4, id, ..] => Action::Profile(id),
+            [5, 0, ..] => Action::Host(None),
+            [5, 1, ..] => Action::Host(Some(HostOs::Unknown)),
+            [5, 2, ..] => Action::Host(Some(HostOs::Linux)),
+            [5, 3, ..] => Action::Host(Some(HostOs::Windows)),
+            [5, 4, ..] => Action::Host(Some(HostOs::MacOs)),
+            [6, cell, ..] => Action::Warp(cell >> 4, cell & 0xf),
+            [7, 0, ..] => Action::Tutor,
+            [8, 0, ..] => Action::ChordMode,
+            [9, flags, ..] => Action::Remap(flags),
+            [10, 0, ..] => Action::DebounceMode,
+            [11, 0, ..] => Action::Status,
+            [12, id, ..] => Action::LogMarker(id),
+            [13, id, ..] => Action::Custom(id),
+            [14, usage, ..] => Action::Consumer(Consumer::from_usage(usage as u16)?),
+            _ => return None,
+        };
+        Some(action)
+    }
+}
+
+/// Whether the usage of each of `controls` fits in the byte that
+/// [`Action::to_bytes`] has for it.
+const fn usages_fit_in_a_byte(controls: &[Consumer]) -> bool {
+    let mut i = 0;
+    while i < controls.len() {
+        if controls[i].usage() > 0xff {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// A usage past 0xff would be sent as some other control.
+const _: () = assert!(usages_fit_in_a_byte(&Consumer::ALL));
+
+/// Mapping from switch positions to actions.
+pub type Layout<const ROW: usize, const COL: usize> = [[Action; COL]; ROW];
+
+/// Find the action at a particular position within a layout.
+pub fn action<const COL: usize, const ROW: usize>(
+    layout: &Layout<ROW, COL>,
+    row: usize,
+    col: usize,
+) -> Option<&Action> {
+    layout.get(row).and_then(|l| l.get(col))
+}
+
+/// Build a [`Layout`] from a table of key code names.
+///
+/// Each element of the table is either the name of a `KeyCode`, such as `A` or
+/// `LShift`, or any other `Action` wrapped in braces, like `{Action::GamingMode}`.
+/// This keeps the tables in `main.rs` about as terse as they were when a layout
+/// could only contain key codes.
+macro_rules! layout {
+    (@action {$action:expr}) => {
+        $action
+    };
+    (@action $kc:ident) => {
+        $crate::action::Action::KeyCode($crate::key_code::KeyCode::$kc)
+    };
+    ($([$($key:tt),* $(,)?]),* $(,)?) => {
+        [$([$(layout!(@action $key)),*]),*]
+    };
+}
+
+/// Build an array of actions, written as the elements of [`layout!`] are.
+#[cfg_attr(not(feature = "dmote"), allow(unused_macros))]
+macro_rules! keys {
+    ($($key:tt),* $(,)?) => {
+        [$(layout!(@action $key)),*]
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consumer_controls_round_trip() {
+        for &control in Consumer::ALL.iter() {
+            let action = Action::Consumer(control);
+            assert_eq!(Action::from_bytes(&action.to_bytes()), Some(action));
+        }
+    }
+
+    #[test]
+    fn actions_round_trip() {
+        let actions = [
+            Action::KeyCode(KeyCode::A),
+            Action::Layer(2),
+            Action::GamingMode,
+            Action::Profile(3),
+            Action::Host(None),
+            Action::Host(Some(HostOs::MacOs)),
+            Action::Warp(5, 7),
+            Action::Tutor,
+            Action::ChordMode,
+            Action::Remap(0b101),
+            Action::DebounceMode,
+            Action::Status,
+            Action::LogMarker(9),
+            Action::Custom(4),
+        ];
+        for &action in actions.iter() {
+            assert_eq!(Action::from_bytes(&action.to_bytes()), Some(action));
+        }
+    }
+
+    #[test]
+    fn unknown_bytes_are_no_action() {
+        assert_eq!(Action::from_bytes(&[0, 0]), None);
+        assert_eq!(Action::from_bytes(&[14, 0]), None);
+        assert_eq!(Action::from_bytes(&[15, 0]), None);
+        assert_eq!(Action::from_bytes(&[1]), None);
+    }
+}
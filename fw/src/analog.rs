@@ -0,0 +1,293 @@
+//! Experimental analog keys, read from hall effect or force sensors.
+//!
+//! ADC1 converts the sensors on PA6, PA7, PB0 and PB1 over and over, and DMA1
+//! channel 1 copies each conversion into a buffer, so reading them costs the
+//! CPU nothing. Each sensor acts as the key at its position in [`KEYS`], on top
+//! of any switch wired there, so its presses go through the same filters and
+//! layout as those of any other key.
+//!
+//! Until the host calibrates a key, see [`crate::calibrate`], its sensor
+//! calibrates itself: it takes its reading at power on as its rest position,
+//! and learns how far it travels as it's pressed, whichever way its reading
+//! moves. A key is pressed once it's deeper than its actuation point, and
+//! released once it rises above its release point. With rapid trigger on, it's
+//! also released as soon as it rises the distance in the settings from its
+//! deepest point, and pressed again as soon as it falls as far from its highest
+//! point, without going back past either point.
+
+#[cfg(any(feature = "split-cols", feature = "debug-signals"))]
+compile_error!("`analog-keys` uses PA6, PA7, PB0 and PB1, as do `split-cols` and `debug-signals`");
+
+use core::ptr;
+
+use cortex_m::singleton;
+use stm32f1xx_hal::adc::{Adc, ChannelTimeSequence, SampleTime};
+use stm32f1xx_hal::gpio::gpioa::{PA6, PA7};
+use stm32f1xx_hal::gpio::gpiob::{PB0, PB1};
+use stm32f1xx_hal::gpio::Analog;
+use stm32f1xx_hal::pac::{ADC1, DMA1};
+
+use crate::calibrate::{self, Calibration};
+use crate::error::Error;
+
+/// Number of sensors.
+const COUNT: usize = calibrate::KEYS;
+
+/// ADC channels of the sensors, in the order they're converted.
+const CHANNELS: [u8; COUNT] = [6, 7, 8, 9];
+
+/// Position in the matrix, as (row, col), of the key that each sensor acts as:
+/// the four keys of the thumb cluster of the right hand that are nearest the
+/// thumb.
+pub const KEYS: [(usize, usize); COUNT] = [(5, 0), (5, 1), (6, 0), (6, 1)];
+
+/// Travel, in ADC counts of 4096, that a sensor must have been seen to move
+/// before its readings are trusted, so that noise on an unconnected or
+/// untouched sensor doesn't press its key.
+const MIN_RANGE: i32 = 100;
+
+/// Depth, in 256ths of the travel, that a key must rise past the actuation
+/// point before it's released, unless it has a release point of its own, so
+/// that noise at the actuation point doesn't chatter.
+const HYSTERESIS: u8 = 8;
+
+/// The pins of the sensors, set up as analog inputs.
+pub struct Pins(
+    pub PA6<Analog>,
+    pub PA7<Analog>,
+    pub PB0<Analog>,
+    pub PB1<Analog>,
+);
+
+#[derive(Clone, Copy, Default)]
+struct Key {
+    /// The calibration set by the host
+    calibration: calibrate::Key,
+    /// The reading at rest, once one has been taken
+    rest: Option<u16>,
+    /// The travel from rest, of either sign, farthest from it seen so far
+    extreme: i32,
+    /// Is the key pressed?
+    pressed: bool,
+    /// The deepest depth since the key was pressed, or the highest since it
+    /// was released
+    peak: u8,
+}
+
+impl Key {
+    /// Depth of the key, from 0 at rest to 255 at the bottom, or without a
+    /// calibration, at the farthest travel seen.
+    fn depth(&mut self, reading: u16) -> u8 {
+        let rest = self.calibration.rest as i32;
+        let full = self.calibration.bottom as i32 - rest;
+        if full != 0 {
+            let travel = reading as i32 - rest;
+            return (travel * 255 / full).max(0).min(255) as u8;
+        }
+        let rest = *self.rest.get_or_insert(reading);
+        let travel = reading as i32 - rest as i32;
+        if travel.abs() > self.extreme.abs() {
+            self.extreme = travel;
+        }
+        if self.extreme.abs() < MIN_RANGE {
+            return 0;
+        }
+        (travel * 255 / self.extreme).max(0).min(255) as u8
+    }
+
+    /// Move the key to the given depth, pressing it once it's past its
+    /// actuation point, or `actuation` if it has none, and releasing it once
+    /// it's above its release point. With rapid trigger, when `rapid` isn't 0,
+    /// it's also pressed or released once it moves that far from its peak.
+    fn update(&mut self, depth: u8, actuation: u8, rapid: u8) {
+        let actuation = match self.calibration.actuation {
+            0 => actuation,
+            own => own,
+        };
+        let release = match self.calibration.release {
+            0 => actuation.saturating_sub(HYSTERESIS),
+            own => own,
+        };
+        if self.pressed {
+            self.peak = self.peak.max(depth);
+            let rose = rapid != 0 && depth.saturating_add(rapid) <= self.peak;
+            if rose || depth < release {
+                self.pressed = false;
+                self.peak = depth;
+            }
+        } else {
+            self.peak = self.peak.min(depth);
+            if depth >= actuation && depth >= self.peak.saturating_add(rapid) {
+                self.pressed = true;
+                self.peak = depth;
+            }
+        }
+    }
+}
+
+/// The readings of a sensor seen while calibrating.
+#[derive(Clone, Copy)]
+struct Range {
+    /// The reading when calibration started, with the key at rest
+    rest: u16,
+    min: u16,
+    max: u16,
+}
+
+/// The analog keys, and the conversions of their sensors.
+pub struct Sensors {
+    _adc: Adc<ADC1>,
+    _pins: Pins,
+    /// The latest conversion of each sensor, written by DMA
+    samples: &'static [u16; COUNT],
+    keys: [Key; COUNT],
+    /// The readings seen since calibration started, if it's in progress
+    recording: Option<[Range; COUNT]>,
+}
+
+impl Sensors {
+    /// Start converting the sensors, with the given calibration.
+    ///
+    /// This must be called after `scan::dma_key_scan`, which enables DMA1.
+    ///
+    /// # Errors
+    ///
+    /// This returns [`Error::Sensors`] if called more than once.
+    pub fn start(
+        mut adc: Adc<ADC1>,
+        pins: Pins,
+        calibration: &Calibration,
+    ) -> Result<Self, Error> {
+        let samples = singleton!(: [u16; COUNT] = [0; COUNT]).ok_or(Error::Sensors)?;
+        // NOTE: these are called through the trait, as `Adc` has private
+        // methods of the same names.
+        for &channel in CHANNELS.iter() {
+            ChannelTimeSequence::set_channel_sample_time(&mut adc, channel, SampleTime::T_71);
+        }
+        ChannelTimeSequence::set_regular_sequence(&mut adc, &CHANNELS);
+        ChannelTimeSequence::set_discontinuous_mode(&mut adc, None);
+        ChannelTimeSequence::set_continuous_mode(&mut adc, true);
+
+        // Safety: the HAL has no way to scan the channels with DMA without
+        // taking DMA1 channel 1, which `dma_key_scan` doesn't use, as part of
+        // DMA1. These only touch that channel, and the ADC that `adc` owns.
+        let (adc1, dma1) = unsafe { (&*ADC1::ptr(), &*DMA1::ptr()) };
+        dma1.ch1
+            .par
+            .write(|w| unsafe { w.bits(adc1.dr.as_ptr() as u32) });
+        dma1.ch1
+            .mar
+            .write(|w| unsafe { w.bits(samples.as_mut_ptr() as u32) });
+        dma1.ch1.ndtr.write(|w| unsafe { w.bits(COUNT as u32) });
+        #[rustfmt::skip]
+        dma1.ch1.cr.modify(|_read, write| {
+            write
+                .circ().enabled()
+                .dir().from_peripheral()
+                .minc().enabled()
+                .psize().bits16()
+                .msize().bits16()
+                .en().enabled()
+        });
+        adc1.cr1.modify(|_, w| w.scan().set_bit());
+        adc1.cr2.modify(|_, w| w.dma().set_bit());
+        adc1.cr2.modify(|_, w| w.swstart().set_bit());
+
+        let mut sensors = Self {
+            _adc: adc,
+            _pins: pins,
+            samples,
+            keys: [Key::default(); COUNT],
+            recording: None,
+        };
+        sensors.calibrate(calibration);
+        Ok(sensors)
+    }
+
+    /// Use the given calibration from now on.
+    pub fn calibrate(&mut self, calibration: &Calibration) {
+        for (key, calibration) in self.keys.iter_mut().zip(calibration.0.iter()) {
+            key.calibration = *calibration;
+        }
+    }
+
+    /// The latest reading of each sensor.
+    pub fn readings(&self) -> [u16; COUNT] {
+        let mut readings = [0; COUNT];
+        for (reading, sample) in readings.iter_mut().zip(self.samples.iter()) {
+            // Safety: the buffer is only written by DMA, a u16 at a time.
+            *reading = unsafe { ptr::read_volatile(sample) };
+        }
+        readings
+    }
+
+    /// Is calibration in progress?
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Start calibrating, with every key at rest, recording how far each
+    /// sensor's reading moves until [`Sensors::finish_recording`].
+    pub fn start_recording(&mut self) {
+        let mut ranges = [Range {
+            rest: 0,
+            min: 0,
+            max: 0,
+        }; COUNT];
+        for (range, reading) in ranges.iter_mut().zip(self.readings().iter()) {
+            *range = Range {
+                rest: *reading,
+                min: *reading,
+                max: *reading,
+            };
+        }
+        self.recording = Some(ranges);
+    }
+
+    /// Finish calibrating, setting the rest and bottom of each key in
+    /// `calibration` from the readings recorded, and using it from now on.
+    ///
+    /// A key whose reading moved too little, as if it was never pressed, keeps
+    /// the calibration it had.
+    pub fn finish_recording(&mut self, calibration: &mut Calibration) {
+        let ranges = match self.recording.take() {
+            Some(ranges) => ranges,
+            None => return,
+        };
+        for (key, range) in calibration.0.iter_mut().zip(ranges.iter()) {
+            let below = range.rest - range.min;
+            let above = range.max - range.rest;
+            if below.max(above) as i32 >= MIN_RANGE {
+                key.rest = range.rest;
+                key.bottom = if above >= below { range.max } else { range.min };
+            }
+        }
+        self.calibrate(calibration);
+    }
+
+    /// Update the keys from the latest conversions, returning the keys that
+    /// are pressed, with a bit per row for each column.
+    ///
+    /// `actuation` is the depth, in 256ths of the travel, past which a key
+    /// without an actuation point of its own is pressed, and `rapid` is the
+    /// distance, likewise, for rapid trigger, or 0 to turn it off.
+    pub fn update(&mut self, actuation: u8, rapid: u8) -> [u16; 6] {
+        let readings = self.readings();
+        if let Some(ranges) = self.recording.as_mut() {
+            for (range, &reading) in ranges.iter_mut().zip(readings.iter()) {
+                range.min = range.min.min(reading);
+                range.max = range.max.max(reading);
+            }
+        }
+        let mut held = [0; 6];
+        let keys = self.keys.iter_mut().zip(KEYS.iter());
+        for ((key, &(row, col)), &reading) in keys.zip(readings.iter()) {
+            let depth = key.depth(reading);
+            key.update(depth, actuation, rapid);
+            if key.pressed {
+                held[col] |= 1 << row;
+            }
+        }
+        held
+    }
+}
@@ -0,0 +1,104 @@
+//! Checking a new layout without it typing.
+//!
+//! While auditing, which the host turns on and off with `Command::Audit`, the
+//! keyboard sends no key codes or consumer controls, and performs no other
+//! actions, such as switching profiles. Instead, each press and release is put
+//! into the feature report, with the layer and action that it resolved to, for
+//! a host polling the report to show. Layer keys still select layers, so that
+//! every layer can be checked. Auditing isn't stored, so a reset always ends
+//! it.
+
+use crate::action::Action;
+
+/// Number of the latest events kept for the host.
+pub const EVENTS: usize = 4;
+
+/// A key pressed or released while auditing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Event {
+    row: u8,
+    col: u8,
+    pressed: bool,
+    layer: u8,
+    /// The action, as bytes, or 0 and 0 for none
+    action: [u8; Action::SIZE],
+    /// When the key was pressed or released, in scans
+    time: u32,
+}
+
+impl Event {
+    const SIZE: usize = 6 + Action::SIZE;
+
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        let [t0, t1, t2, t3] = self.time.to_le_bytes();
+        let [kind, arg] = self.action;
+        let pressed = self.pressed as u8;
+        [
+            self.row, self.col, pressed, self.layer, kind, arg, t0, t1, t2, t3,
+        ]
+    }
+}
+
+/// Whether the keyboard is auditing, and the latest events if so.
+#[derive(Debug, Default)]
+pub struct Audit {
+    on: bool,
+    /// Number of events recorded, wrapping around
+    count: u8,
+    /// The latest events, newest first
+    events: [Event; EVENTS],
+}
+
+impl Audit {
+    /// Size of `Audit` when converted to bytes.
+    pub const SIZE: usize = 2 + EVENTS * Event::SIZE;
+
+    /// Start or stop auditing, forgetting the events of the last audit.
+    pub fn set(&mut self, on: bool) {
+        *self = Self {
+            on,
+            ..Self::default()
+        };
+    }
+
+    pub fn on(&self) -> bool {
+        self.on
+    }
+
+    /// Note that the key at `(row, col)` was pressed or released at `now`, in
+    /// `layer`, where it does `action`.
+    pub fn record(
+        &mut self,
+        (row, col): (usize, usize),
+        pressed: bool,
+        layer: usize,
+        action: Option<Action>,
+        now: u32,
+    ) {
+        self.events.rotate_right(1);
+        self.events[0] = Event {
+            row: row as u8,
+            col: col as u8,
+            pressed,
+            layer: layer as u8,
+            action: action.map_or([0; Action::SIZE], |a| a.to_bytes()),
+            time: now,
+        };
+        self.count = self.count.wrapping_add(1);
+    }
+
+    /// Convert to bytes: 1 while auditing, or 0, then the number of events
+    /// recorded, then the latest events, newest first. Each is the row, the
+    /// column, 1 for a press or 0 for a release, the layer, the action as in
+    /// [`Action::to_bytes`], and the time in scans, little endian.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0] = self.on as u8;
+        bytes[1] = self.count;
+        let chunks = bytes[2..].chunks_exact_mut(Event::SIZE);
+        for (chunk, event) in chunks.zip(self.events.iter()) {
+            chunk.copy_from_slice(&event.to_bytes());
+        }
+        bytes
+    }
+}
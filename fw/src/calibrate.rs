@@ -0,0 +1,75 @@
+//! Calibration of the analog keys, set by the host and stored in flash.
+//!
+//! To calibrate, the host sends `CalibrateAnalog` to start, with every analog
+//! key at rest, then has each key pressed all the way down, and sends
+//! `CalibrateAnalog` again to finish. Each key then has its travel from rest to
+//! bottom, rather than learning it as it's pressed. The host may also set the
+//! depths at which each key is pressed and released. Until it has, a key uses
+//! its own travel and the actuation point of the active profile; see
+//! [`crate::analog`].
+
+/// Number of analog keys.
+pub const KEYS: usize = 4;
+
+/// The calibration of an analog key.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    /// The reading of the sensor at rest
+    pub rest: u16,
+    /// The reading of the sensor with the key pressed all the way down, or the
+    /// same as `rest` when the key isn't calibrated
+    pub bottom: u16,
+    /// Depth, in 256ths of the travel, past which the key is pressed, or 0
+    /// for the actuation point of the active profile
+    pub actuation: u8,
+    /// Depth, likewise, that the key must rise above to be released, or 0
+    /// for a little above the actuation point
+    pub release: u8,
+}
+
+/// The calibration of every analog key.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration(pub [Key; KEYS]);
+
+impl Calibration {
+    /// Size of `Calibration` when converted to bytes.
+    pub const SIZE: usize = KEYS * 6;
+
+    /// Set the depths at which the key with the given index is pressed and
+    /// released, as for [`Key::actuation`] and [`Key::release`].
+    pub fn set_points(&mut self, key: u8, actuation: u8, release: u8) {
+        if let Some(key) = self.0.get_mut(key as usize) {
+            key.actuation = actuation;
+            key.release = release;
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        for (chunk, key) in bytes.chunks_exact_mut(6).zip(self.0.iter()) {
+            chunk[0..2].copy_from_slice(&key.rest.to_le_bytes());
+            chunk[2..4].copy_from_slice(&key.bottom.to_le_bytes());
+            chunk[4] = key.actuation;
+            chunk[5] = key.release;
+        }
+        bytes
+    }
+
+    /// Parse a calibration produced by `to_bytes`, returning `None` if there
+    /// are too few bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE {
+            return None;
+        }
+        let mut calibration = Self::default();
+        for (chunk, key) in bytes.chunks_exact(6).zip(calibration.0.iter_mut()) {
+            *key = Key {
+                rest: u16::from_le_bytes([chunk[0], chunk[1]]),
+                bottom: u16::from_le_bytes([chunk[2], chunk[3]]),
+                actuation: chunk[4],
+                release: chunk[5],
+            };
+        }
+        Some(calibration)
+    }
+}
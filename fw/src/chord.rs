@@ -0,0 +1,131 @@
+//! Chorded entry, in the style of ASETNIOP.
+//!
+//! While chorded entry is on, the eight home row keys stop typing their own key
+//! codes. Instead, the keys held together are collected into a chord, and once
+//! all of them are released, the chord types a single letter: each key alone
+//! types its letter from "asetniop", and pairs of keys type the rest of the
+//! alphabet.
+
+use crate::action::{action, Action, Layout};
+use crate::key_code::KeyCode::{self, *};
+
+/// The keys of a chord, found by their key code in the base layer: the home
+/// row of a QWERTY keyboard.
+const CHORD_KEYS: [KeyCode; 8] = [A, S, D, F, J, K, L, SColon];
+
+/// The letter typed by each chord, with a bit for each of `CHORD_KEYS`.
+#[rustfmt::skip]
+const CHORDS: [(u8, KeyCode); 26] = [
+    (0b0000_0001, A), (0b0000_0010, S), (0b0000_0100, E), (0b0000_1000, T),
+    (0b0001_0000, N), (0b0010_0000, I), (0b0100_0000, O), (0b1000_0000, P),
+    (0b0000_0011, W), (0b0000_0101, X), (0b0000_1001, F), (0b0001_0001, Q),
+    (0b0010_0001, Z), (0b0000_0110, D), (0b0000_1010, C), (0b0001_0010, J),
+    (0b0010_0010, K), (0b0000_1100, R), (0b0001_0100, Y), (0b0001_1000, B),
+    (0b0010_1000, V), (0b0100_1000, G), (0b0011_0000, H), (0b0101_0000, U),
+    (0b1001_0000, M), (0b0110_0000, L),
+];
+
+/// Number of scans that a typed letter is reported for. This is long enough
+/// for the report to be sent at least once, even when the host polls slowly.
+pub const TAP_SCANS: u32 = 20;
+
+/// The state of chorded entry.
+#[derive(Default)]
+pub struct Chords {
+    active: bool,
+    /// A bit for each of `CHORD_KEYS` that's held
+    held: u8,
+    /// A bit for each of `CHORD_KEYS` pressed since the chord started
+    chord: u8,
+    /// The letter being typed, and the number of scans left to report it
+    tap: Option<(KeyCode, u32)>,
+}
+
+/// Find which of `CHORD_KEYS` is at a position, by its key code in the base
+/// layer.
+fn chord_bit<const R: usize, const C: usize>(
+    base: &Layout<R, C>,
+    row: usize,
+    col: usize,
+) -> Option<u8> {
+    match action(base, row, col) {
+        Some(Action::KeyCode(kc)) => CHORD_KEYS.iter().position(|k| k == kc).map(|i| 1 << i),
+        _ => None,
+    }
+}
+
+impl Chords {
+    /// Turn chorded entry on or off.
+    pub fn toggle(&mut self) {
+        self.active = !self.active;
+        self.held = 0;
+        self.chord = 0;
+    }
+
+    /// Handle the press of a key, returning true if it's part of a chord.
+    pub fn press<const R: usize, const C: usize>(
+        &mut self,
+        base: &Layout<R, C>,
+        row: usize,
+        col: usize,
+    ) -> bool {
+        match chord_bit(base, row, col) {
+            Some(bit) if self.active => {
+                self.held |= bit;
+                self.chord |= bit;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Handle the release of a key, returning true if it's part of a chord.
+    /// Releasing the last key of a chord types its letter.
+    pub fn release<const R: usize, const C: usize>(
+        &mut self,
+        base: &Layout<R, C>,
+        row: usize,
+        col: usize,
+    ) -> bool {
+        match chord_bit(base, row, col) {
+            Some(bit) if self.active => {
+                self.held &= !bit;
+                if self.held == 0 {
+                    let chord = core::mem::replace(&mut self.chord, 0);
+                    if let Some(&(_, kc)) = CHORDS.iter().find(|(c, _)| *c == chord) {
+                        self.tap = Some((kc, TAP_SCANS));
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Clear the keys that are part of chords from `pressed`, a bit per row
+    /// for each column, so that they don't type their own key codes.
+    pub fn filter<const R: usize, const C: usize>(
+        &self,
+        base: &Layout<R, C>,
+        pressed: &[u16; C],
+    ) -> [u16; C] {
+        let mut filtered = *pressed;
+        if self.active {
+            for (col, bits) in filtered.iter_mut().enumerate() {
+                for row in 0..R {
+                    if chord_bit(base, row, col).is_some() {
+                        *bits &= !(1 << row);
+                    }
+                }
+            }
+        }
+        filtered
+    }
+
+    /// The letter to report for this scan, if one is being typed.
+    pub fn tap(&mut self) -> Option<KeyCode> {
+        let (kc, remaining) = self.tap?;
+        self.tap = remaining.checked_sub(1).map(|r| (kc, r));
+        Some(kc)
+    }
+}
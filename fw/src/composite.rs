@@ -0,0 +1,257 @@
+//! Consumer control, mouse and absolute pointer HID device implementation.
+//!
+//! These reports don't fit in the boot keyboard report, so they are on their
+//! own interface, and told apart by their report ID.
+//!
+//! The wheel and AC Pan of the mouse support high resolution scrolling: a host
+//! that understands it sets the resolution multiplier, in a feature report,
+//! and then takes each unit of scrolling as a fraction of a notch. See
+//! [`Composite::resolution`].
+
+use crate::hid::{HidDevice, Protocol, ReportType, Subclass};
+
+const REPORT_DESCRIPTOR: &[u8] = &[
+    // Consumer control, report ID 1: a single 16 bit usage
+    0x05, 0x0C, 0x09, 0x01, 0xA1, 0x01, 0x85, 0x01, 0x15, 0x00, 0x26, 0xFF, 0x03, 0x19, 0x00, 0x2A,
+    0xFF, 0x03, 0x75, 0x10, 0x95, 0x01, 0x81, 0x00, 0xC0,
+    // Mouse, report ID 2: 5 buttons, then relative X and Y
+    0x05, 0x01, 0x09, 0x02, 0xA1, 0x01, 0x85, 0x02, 0x09, 0x01, 0xA1, 0x00, 0x05, 0x09, 0x19, 0x01,
+    0x29, 0x05, 0x15, 0x00, 0x25, 0x01, 0x75, 0x01, 0x95, 0x05, 0x81, 0x02, 0x75, 0x03, 0x95, 0x01,
+    0x81, 0x03, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7F, 0x75, 0x08, 0x95, 0x02,
+    0x81, 0x06,
+    // ...the wheel, in a logical collection with its resolution multiplier,
+    // which is 2 bits of feature report ID 4
+    0xA1, 0x02, 0x85, 0x04, 0x09, 0x48, 0x15, 0x00, 0x25, 0x01, 0x35, 0x01, 0x45, MULTIPLIER, 0x75,
+    0x02, 0x95, 0x01, 0xB1, 0x02, 0x85, 0x02, 0x35, 0x00, 0x45, 0x00, 0x09, 0x38, 0x15, 0x81, 0x25,
+    0x7F, 0x75, 0x08, 0x81, 0x06, 0xC0,
+    // ...and AC Pan, likewise, with the next 2 bits of the feature report and
+    // 4 bits of padding
+    0xA1, 0x02, 0x85, 0x04, 0x09, 0x48, 0x15, 0x00, 0x25, 0x01, 0x35, 0x01, 0x45, MULTIPLIER, 0x75,
+    0x02, 0xB1, 0x02, 0x35, 0x00, 0x45, 0x00, 0x75, 0x04, 0xB1, 0x03, 0x85, 0x02, 0x05, 0x0C, 0x0A,
+    0x38, 0x02, 0x15, 0x81, 0x25, 0x7F, 0x75, 0x08, 0x81, 0x06, 0xC0, 0xC0, 0xC0,
+    // Digitizer pen, report ID 3: in range, then absolute X and Y from 0 to 0x7fff
+    0x05, 0x0D, 0x09, 0x02, 0xA1, 0x01, 0x85, 0x03, 0x09, 0x20, 0xA1, 0x00, 0x09, 0x32, 0x15, 0x00,
+    0x25, 0x01, 0x75, 0x01, 0x95, 0x01, 0x81, 0x02, 0x75, 0x07, 0x95, 0x01, 0x81, 0x03, 0x05, 0x01,
+    0x09, 0x30, 0x09, 0x31, 0x15, 0x00, 0x26, 0xFF, 0x7F, 0x75, 0x10, 0x95, 0x02, 0x81, 0x02, 0xC0,
+    0xC0,
+];
+
+/// Report ID of the consumer control report.
+pub const CONSUMER_REPORT_ID: u8 = 1;
+
+/// Report ID of the mouse report.
+pub const MOUSE_REPORT_ID: u8 = 2;
+
+/// Report ID of the absolute pointer report.
+pub const POINTER_REPORT_ID: u8 = 3;
+
+/// Report ID of the resolution multiplier feature report.
+pub const RESOLUTION_REPORT_ID: u8 = 4;
+
+/// Units of scrolling per notch, with high resolution scrolling.
+pub const MULTIPLIER: u8 = 8;
+
+/// A consumer control, such as a media key, that a key may report with
+/// `Action::Consumer`. Each is listed in [`Consumer::ALL`] too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consumer {
+    PlayPause,
+    Stop,
+    NextTrack,
+    PreviousTrack,
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    BrightnessUp,
+    BrightnessDown,
+}
+
+impl Consumer {
+    /// Every consumer control.
+    pub const ALL: [Consumer; 9] = [
+        Consumer::PlayPause,
+        Consumer::Stop,
+        Consumer::NextTrack,
+        Consumer::PreviousTrack,
+        Consumer::VolumeUp,
+        Consumer::VolumeDown,
+        Consumer::Mute,
+        Consumer::BrightnessUp,
+        Consumer::BrightnessDown,
+    ];
+
+    /// The usage of the control, in the consumer page.
+    pub const fn usage(self) -> u16 {
+        match self {
+            Consumer::PlayPause => 0xCD,
+            Consumer::Stop => 0xB7,
+            Consumer::NextTrack => 0xB5,
+            Consumer::PreviousTrack => 0xB6,
+            Consumer::VolumeUp => 0xE9,
+            Consumer::VolumeDown => 0xEA,
+            Consumer::Mute => 0xE2,
+            Consumer::BrightnessUp => 0x6F,
+            Consumer::BrightnessDown => 0x70,
+        }
+    }
+
+    /// Find the control with the given usage, in the consumer page.
+    pub fn from_usage(usage: u16) -> Option<Self> {
+        Self::ALL.iter().copied().find(|c| c.usage() == usage)
+    }
+}
+
+/// The state of the mouse, as sent in the mouse report.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Mouse {
+    /// A bit for each button that's held, starting with the left button.
+    pub buttons: u8,
+    pub x: i8,
+    pub y: i8,
+    /// Scrolling, in fractions of a notch given by [`Composite::resolution`]
+    pub wheel: i8,
+    pub pan: i8,
+}
+
+/// A consumer control, mouse and absolute pointer HID device.
+pub struct Composite {
+    consumer: [u8; 3],
+    mouse: [u8; 6],
+    pointer: [u8; 6],
+    resolution: [u8; 2],
+    /// Has the consumer report changed since it was last sent?
+    consumer_changed: bool,
+    /// Has the mouse report changed since it was last sent?
+    mouse_changed: bool,
+    /// Has the absolute pointer report changed since it was last sent?
+    pointer_changed: bool,
+}
+
+impl Default for Composite {
+    fn default() -> Self {
+        Self {
+            consumer: [CONSUMER_REPORT_ID, 0, 0],
+            mouse: [MOUSE_REPORT_ID, 0, 0, 0, 0, 0],
+            pointer: [POINTER_REPORT_ID, 0, 0, 0, 0, 0],
+            resolution: [RESOLUTION_REPORT_ID, 0],
+            consumer_changed: false,
+            mouse_changed: false,
+            pointer_changed: false,
+        }
+    }
+}
+
+impl Composite {
+    /// Report the given consumer usage as held, or, with 0, nothing.
+    pub fn set_consumer(&mut self, usage: u16) {
+        let [lo, hi] = usage.to_le_bytes();
+        let report = [CONSUMER_REPORT_ID, lo, hi];
+        self.consumer_changed |= report != self.consumer;
+        self.consumer = report;
+    }
+
+    /// Report the given state of the mouse.
+    pub fn set_mouse(&mut self, mouse: Mouse) {
+        let report = [
+            MOUSE_REPORT_ID,
+            mouse.buttons,
+            mouse.x as u8,
+            mouse.y as u8,
+            mouse.wheel as u8,
+            mouse.pan as u8,
+        ];
+        self.mouse_changed |= report != self.mouse;
+        self.mouse = report;
+    }
+
+    /// Move the absolute pointer to the given position, or, with `None`, take
+    /// it out of range, leaving the pointer where it was.
+    ///
+    /// The pointer is reported as a pen hovering over a tablet, which hosts
+    /// use to move the cursor without clicking.
+    pub fn set_pointer(&mut self, position: Option<(u16, u16)>) {
+        let mut report = self.pointer;
+        match position {
+            Some((x, y)) => {
+                let [xlo, xhi] = x.to_le_bytes();
+                let [ylo, yhi] = y.to_le_bytes();
+                report = [POINTER_REPORT_ID, 1, xlo, xhi, ylo, yhi];
+            }
+            None => report[1] = 0,
+        }
+        self.pointer_changed |= report != self.pointer;
+        self.pointer = report;
+    }
+
+    /// Units of scrolling per notch of the wheel and of AC Pan, as set by the
+    /// host: [`MULTIPLIER`] with high resolution scrolling, and otherwise 1.
+    pub fn resolution(&self) -> (u8, u8) {
+        let multiplier = |bits: u8| if bits & 0b11 != 0 { MULTIPLIER } else { 1 };
+        (
+            multiplier(self.resolution[1]),
+            multiplier(self.resolution[1] >> 2),
+        )
+    }
+
+    /// The ID of a report that has changed since it was last sent, if any.
+    pub fn pending(&self) -> Option<u8> {
+        if self.consumer_changed {
+            Some(CONSUMER_REPORT_ID)
+        } else if self.mouse_changed {
+            Some(MOUSE_REPORT_ID)
+        } else if self.pointer_changed {
+            Some(POINTER_REPORT_ID)
+        } else {
+            None
+        }
+    }
+
+    /// Mark the report with the given ID as sent.
+    pub fn sent(&mut self, report_id: u8) {
+        match report_id {
+            CONSUMER_REPORT_ID => self.consumer_changed = false,
+            MOUSE_REPORT_ID => self.mouse_changed = false,
+            POINTER_REPORT_ID => self.pointer_changed = false,
+            _ => (),
+        }
+    }
+}
+
+impl HidDevice for Composite {
+    fn subclass(&self) -> Subclass {
+        Subclass::None
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::None
+    }
+
+    fn report_descriptor(&self) -> &[u8] {
+        REPORT_DESCRIPTOR
+    }
+
+    fn get_report(&mut self, report_type: ReportType, report_id: u8) -> Result<&[u8], ()> {
+        match (report_type, report_id) {
+            (ReportType::Input, CONSUMER_REPORT_ID) => Ok(&self.consumer),
+            (ReportType::Input, MOUSE_REPORT_ID) => Ok(&self.mouse),
+            (ReportType::Input, POINTER_REPORT_ID) => Ok(&self.pointer),
+            (ReportType::Feature, RESOLUTION_REPORT_ID) => Ok(&self.resolution),
+            _ => Err(()),
+        }
+    }
+
+    fn set_report(
+        &mut self,
+        report_type: ReportType,
+        report_id: u8,
+        data: &[u8],
+    ) -> Result<(), ()> {
+        match (report_type, report_id, data) {
+            (ReportType::Feature, RESOLUTION_REPORT_ID, &[RESOLUTION_REPORT_ID, bits]) => {
+                self.resolution[1] = bits;
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Counts of things going wrong, for the host to watch over days or weeks.
+//!
+//! Each is too rare, or too quiet, to notice while typing: a scan dropped
+//! because the main loop fell behind, a report that the USB peripheral
+//! refused, a storm of presses from a failing switch, or a reset. The counts
+//! are in the feature report, so that a script can poll them without a debug
+//! probe attached. Every count but the resets starts from 0 at reset.
+
+/// The counts, each wrapping around.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    /// Scans dropped because the queue to the main loop was full.
+    pub missed_scans: u32,
+    /// Keyboard reports that the USB peripheral failed to write, other than
+    /// those it was still busy for.
+    pub usb_errors: u32,
+    /// Times that presses started being throttled; see [`crate::storm`].
+    pub throttles: u32,
+    /// Resets since power-on, by the watchdog, the reset pin or software; see
+    /// [`crate::snapshot::resets`].
+    pub resets: u32,
+}
+
+impl Counters {
+    /// Size of `Counters` when converted to bytes.
+    pub const SIZE: usize = 16;
+
+    /// Convert the counts to bytes, each little endian, in the order of the
+    /// fields.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        let counts = [
+            self.missed_scans,
+            self.usb_errors,
+            self.throttles,
+            self.resets,
+        ];
+        for (chunk, count) in bytes.chunks_exact_mut(4).zip(counts.iter()) {
+            chunk.copy_from_slice(&count.to_le_bytes());
+        }
+        bytes
+    }
+}
@@ -0,0 +1,44 @@
+//! Actions defined by the firmware's builder, rather than by the layout engine.
+//!
+//! `Action::Custom(id)` calls the function at index `id` of `CUSTOM` in
+//! `main.rs` as its key is pressed, with the state that the built in actions
+//! change, so an action such as switching to the next host OS needs no more
+//! than a function:
+//!
+//! ```ignore
+//! fn next_host(ctx: &mut Context) {
+//!     let next = match ctx.host.os() {
+//!         HostOs::MacOs => HostOs::Linux,
+//!         _ => HostOs::MacOs,
+//!     };
+//!     ctx.host.force(Some(next));
+//! }
+//!
+//! static CUSTOM: &[Custom] = &[next_host];
+//! ```
+//!
+//! An id past the end of the table does nothing.
+
+use crate::chord::Chords;
+use crate::host::Host;
+use crate::settings::Profiles;
+use crate::tutor::Tutor;
+
+/// What a custom action may change.
+pub struct Context<'a> {
+    pub profiles: &'a mut Profiles,
+    pub host: &'a mut Host,
+    pub tutor: &'a mut Tutor,
+    pub chords: &'a mut Chords,
+}
+
+/// A custom action, called as its key is pressed.
+pub type Custom = fn(&mut Context);
+
+/// Call the custom action with the given id, in a table of them, if there is
+/// one.
+pub fn call(actions: &[Custom], id: u8, ctx: &mut Context) {
+    if let Some(action) = actions.get(id as usize) {
+        action(ctx);
+    }
+}
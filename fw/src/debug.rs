@@ -0,0 +1,47 @@
+//! Signals on spare pins that mirror what the firmware is doing, so that a
+//! logic analyzer on them and on the matrix can line the two up.
+//!
+//! Built with `debug-signals`, PA6..=PA8 are outputs, and [`PINS`] picks which
+//! of them each [`Signal`] is on. The scan signal is high while the scan
+//! interrupt runs. The others toggle at each event, rather than pulse, so that
+//! events closer together than the analyzer samples aren't lost.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use stm32f1xx_hal::pac::GPIOA;
+
+/// Something the firmware does that may be mirrored on a pin.
+#[derive(Clone, Copy)]
+pub enum Signal {
+    /// High while the scan interrupt runs
+    Scan,
+    /// Toggled as the debouncer emits each press or release
+    Debounce,
+    /// Toggled as each keyboard report is written to USB
+    UsbWrite,
+}
+
+/// The pin of Port A that each signal is on, in the order of [`Signal`], or
+/// `None` to leave it off. Only PA6..=PA8 are set up as outputs.
+pub const PINS: [Option<u8>; 3] = [Some(6), Some(7), Some(8)];
+
+/// The level of each pin that's toggled, with a bit per pin.
+static LEVELS: AtomicU16 = AtomicU16::new(0);
+
+/// Drive the pin of the signal high or low.
+pub fn set(signal: Signal, high: bool) {
+    if let Some(pin) = PINS[signal as usize] {
+        let bits = if high { 1 << pin } else { 1 << (pin + 16) };
+        // Safety: writes to the BSRR are atomic, and only change the pins
+        // that they set or reset, so they can't disturb the columns.
+        unsafe { (*GPIOA::ptr()).bsrr.write(|w| w.bits(bits)) };
+    }
+}
+
+/// Toggle the pin of the signal.
+pub fn toggle(signal: Signal) {
+    if let Some(pin) = PINS[signal as usize] {
+        let was_high = LEVELS.fetch_xor(1 << pin, Ordering::Relaxed) & (1 << pin) != 0;
+        set(signal, !was_high);
+    }
+}
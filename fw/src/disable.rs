@@ -0,0 +1,47 @@
+//! Disabling keys whose switches have failed.
+//!
+//! A broken switch may chatter, flooding the host with presses. Disabling its
+//! position in the matrix makes the debouncer see the key as released, whatever
+//! the switch does, until it's repaired and enabled again. Disabled keys are
+//! set by the host, and stored in flash with the profiles.
+
+/// The disabled keys, with a bit per row for each column.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Disabled(pub [u16; 6]);
+
+impl Disabled {
+    /// Size of `Disabled` when converted to bytes.
+    pub const SIZE: usize = 12;
+
+    /// Disable or enable the key at the given row and column.
+    pub fn set(&mut self, row: u8, col: u8, disabled: bool) {
+        if let (Some(bits), true) = (self.0.get_mut(col as usize), row < 16) {
+            if disabled {
+                *bits |= 1 << row;
+            } else {
+                *bits &= !(1 << row);
+            }
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        for (chunk, bits) in bytes.chunks_exact_mut(2).zip(self.0.iter()) {
+            chunk.copy_from_slice(&bits.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Parse disabled keys produced by `to_bytes`, returning `None` if there
+    /// are too few bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE {
+            return None;
+        }
+        let mut disabled = Self::default();
+        for (chunk, bits) in bytes.chunks_exact(2).zip(disabled.0.iter_mut()) {
+            *bits = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        Some(disabled)
+    }
+}
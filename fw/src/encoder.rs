@@ -0,0 +1,137 @@
+//! Rotary encoders wired into the matrix.
+//!
+//! An encoder is two switches, A and B, that open and close a quarter of a
+//! cycle apart as it turns, and which one leads tells the direction. Each is
+//! wired, with a diode, like a key at a spare position of the matrix, so the
+//! DMA scan samples them along with the keys. Their raw readings are decoded in
+//! the scan interrupt, before the debouncer, which would smear the order they
+//! change in, and taken out of the scan.
+//!
+//! Each detent turned taps a key: the one at A's position when turning
+//! clockwise, and the one at B's position when turning anticlockwise. So the
+//! layout maps each direction to an action, such as `VolUp` and `VolDown`. If
+//! an encoder turns the wrong way, swap its positions, or its A and B wires.
+
+#[cfg(not(feature = "dactyl"))]
+compile_error!("`encoders` needs the positions of the encoders, which only the Dactyl has");
+
+/// The change in count for each transition, indexed by the previous state of A
+/// and B, then the current one, two bits each. Transitions that skip a state
+/// are ignored, as their direction is unknown.
+const STEPS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+/// Counts per detent; most encoders go through a whole cycle of A and B
+/// between detents.
+const COUNTS_PER_DETENT: i8 = 4;
+
+/// Most detents that may wait to be tapped, so that a fast spin doesn't keep
+/// tapping long after it stops.
+const MAX_PENDING: i8 = 8;
+
+/// Number of scans that a tap is held for, then released for; 10ms.
+const TAP_SCANS: u32 = 20;
+
+/// Position in the matrix, as (row, col), of an encoder's A and B switches.
+pub type Positions = ((usize, usize), (usize, usize));
+
+/// A rotary encoder, and the taps that it's turned for.
+#[derive(Clone, Copy)]
+struct Encoder {
+    positions: Positions,
+    /// A and B at the last scan, as bits 1 and 0
+    state: u8,
+    /// Counts since the last detent, positive for clockwise
+    count: i8,
+    /// Detents yet to be tapped, positive for clockwise
+    pending: i8,
+    /// The tap in progress, if any: whether it's clockwise, and when it started
+    tap: Option<(bool, u32)>,
+}
+
+/// Whether the raw scan has the switch at `(row, col)` closed, taking it out of
+/// the scan.
+fn take(scanout: &mut [u16; 6], (row, col): (usize, usize)) -> bool {
+    let bit = 1 << (row + 3);
+    let closed = scanout[col] & bit != 0;
+    scanout[col] &= !bit;
+    closed
+}
+
+impl Encoder {
+    /// Decode a raw scan, returning whether the encoder moved.
+    fn update(&mut self, scanout: &mut [u16; 6], now: u32) -> bool {
+        let (a, b) = self.positions;
+        let state = ((take(scanout, a) as u8) << 1) | take(scanout, b) as u8;
+        let moved = state != self.state;
+        self.count += STEPS[((self.state << 2) | state) as usize];
+        self.state = state;
+        if self.count >= COUNTS_PER_DETENT {
+            self.count -= COUNTS_PER_DETENT;
+            self.pending = (self.pending + 1).min(MAX_PENDING);
+        } else if self.count <= -COUNTS_PER_DETENT {
+            self.count += COUNTS_PER_DETENT;
+            self.pending = (self.pending - 1).max(-MAX_PENDING);
+        }
+        let tapping = self
+            .tap
+            .map_or(false, |(_, start)| now.wrapping_sub(start) < 2 * TAP_SCANS);
+        if !tapping {
+            self.tap = match self.pending {
+                0 => None,
+                pending => Some((pending > 0, now)),
+            };
+            self.pending -= self.pending.signum();
+        }
+        moved
+    }
+
+    /// The position of the key that's held for the tap in progress, if any.
+    fn held(&self, now: u32) -> Option<(usize, usize)> {
+        let (clockwise, start) = self.tap?;
+        if now.wrapping_sub(start) >= TAP_SCANS {
+            return None;
+        }
+        let (a, b) = self.positions;
+        Some(if clockwise { a } else { b })
+    }
+}
+
+/// Every encoder.
+pub struct Encoders<const N: usize>([Encoder; N]);
+
+impl<const N: usize> Encoders<N> {
+    /// The encoders with switches at the given positions.
+    pub fn new(positions: [Positions; N]) -> Self {
+        let encoder = Encoder {
+            positions: ((0, 0), (0, 0)),
+            state: 0,
+            count: 0,
+            pending: 0,
+            tap: None,
+        };
+        let mut encoders = [encoder; N];
+        for (encoder, positions) in encoders.iter_mut().zip(positions.iter()) {
+            encoder.positions = *positions;
+        }
+        Self(encoders)
+    }
+
+    /// Decode a raw scan, as read by `MatrixScanner::read`, taking the
+    /// encoders' switches out of it. Returns whether any encoder moved.
+    pub fn update(&mut self, scanout: &mut [u16; 6], now: u32) -> bool {
+        let mut moved = false;
+        for encoder in self.0.iter_mut() {
+            moved |= encoder.update(scanout, now);
+        }
+        moved
+    }
+
+    /// The keys held for taps, with a bit per row for each column.
+    pub fn held(&self, now: u32) -> [u16; 6] {
+        let mut held = [0; 6];
+        for (row, col) in self.0.iter().filter_map(|encoder| encoder.held(now)) {
+            held[col] |= 1 << row;
+        }
+        held
+    }
+}
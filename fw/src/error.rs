@@ -0,0 +1,30 @@
+//! What can go wrong while setting up the firmware.
+//!
+//! Each of these leaves the keyboard unable to work, and none can happen
+//! unless the firmware is broken, as they're all things that are only set up
+//! once. Setup returns them, rather than panicking, so that `main` can decide
+//! what to do; it shows them on the status LED, which is more use than a
+//! keyboard that does nothing at all. See [`Error::flashes`].
+
+/// A part of the firmware that couldn't be set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// D+ couldn't be pulled down to reset the USB bus.
+    UsbReset,
+    /// The USB bus was already set up.
+    UsbBus,
+    /// The matrix scan was already started, so its buffers are in use.
+    Scan,
+    /// The debouncer's log was already taken.
+    Log,
+    /// The analog keys' sensors were already started.
+    Sensors,
+}
+
+impl Error {
+    /// Number of times the status LED flashes, between pauses, to show the
+    /// error: 1 for the first variant, 2 for the second, and so on.
+    pub fn flashes(self) -> u32 {
+        self as u32 + 1
+    }
+}
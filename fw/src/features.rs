@@ -0,0 +1,54 @@
+//! The cargo features that the firmware was built with, for host tools to
+//! read.
+//!
+//! Each feature that changes how the firmware behaves, rather than which board
+//! it's built for, has a bit in [`CAPABILITIES`], which is in the feature
+//! report of the keyboard. New features that are heavy enough to be optional
+//! get a bit here too, so that a host tool can tell a feature that's missing
+//! from one that's broken.
+
+/// Built with `split-cols`: the last two columns are on PB0 and PB1.
+pub const SPLIT_COLS: u16 = 1 << 0;
+
+/// Built with `deglitch`: the rows are read twice per column.
+pub const DEGLITCH: u16 = 1 << 1;
+
+/// Built with `clock-48mhz`: the system clock runs at 48 MHz rather than 72.
+pub const CLOCK_48MHZ: u16 = 1 << 2;
+
+/// Built with `hot-keymap`: the first keymap is in RAM, for `keymap-patch`.
+pub const HOT_KEYMAP: u16 = 1 << 3;
+
+/// Built with `debug-signals`: events are mirrored on spare pins.
+pub const DEBUG_SIGNALS: u16 = 1 << 4;
+
+/// Built with `sof-timebase`: reports are paced by USB frames, not scans.
+pub const SOF_TIMEBASE: u16 = 1 << 5;
+
+/// Built with `analog-keys`: sensors on the ADC act as thumb keys.
+pub const ANALOG_KEYS: u16 = 1 << 6;
+
+/// Built with `encoders`: rotary encoders in the matrix tap keys.
+pub const ENCODERS: u16 = 1 << 7;
+
+/// Built with `report-log`: changes to the keyboard report are in the log.
+pub const REPORT_LOG: u16 = 1 << 8;
+
+/// The features that the firmware was built with.
+pub const CAPABILITIES: u16 = flag(cfg!(feature = "split-cols"), SPLIT_COLS)
+    | flag(cfg!(feature = "deglitch"), DEGLITCH)
+    | flag(cfg!(feature = "clock-48mhz"), CLOCK_48MHZ)
+    | flag(cfg!(feature = "hot-keymap"), HOT_KEYMAP)
+    | flag(cfg!(feature = "debug-signals"), DEBUG_SIGNALS)
+    | flag(cfg!(feature = "sof-timebase"), SOF_TIMEBASE)
+    | flag(cfg!(feature = "analog-keys"), ANALOG_KEYS)
+    | flag(cfg!(feature = "encoders"), ENCODERS)
+    | flag(cfg!(feature = "report-log"), REPORT_LOG);
+
+const fn flag(enabled: bool, bit: u16) -> u16 {
+    if enabled {
+        bit
+    } else {
+        0
+    }
+}
@@ -0,0 +1,36 @@
+//! A 1 kHz timebase from the USB start of frame packets.
+//!
+//! The host sends a start of frame packet every millisecond, and the USB
+//! peripheral counts them in its frame number register. Pacing reports by that
+//! count, rather than by scans, keeps them in step with the host's polls, so
+//! that each report waits for its poll about as long as the last one did.
+
+use stm32f1xx_hal::pac::USB;
+
+/// The frame number register counts to 2047, then wraps to 0.
+const FRAME_MASK: u16 = 0x7ff;
+
+/// The USB frames counted so far.
+#[derive(Default)]
+pub struct FrameClock {
+    /// The frame number when last read
+    last: u16,
+    /// Number of frames counted, wrapping around
+    frames: u32,
+}
+
+impl FrameClock {
+    /// The number of frames counted, up to the current one.
+    ///
+    /// Frames are only counted while this is called at least once every 2
+    /// seconds, or the frame number wraps around unseen.
+    pub fn now(&mut self) -> u32 {
+        // Safety: reading the frame number register has no side effects, so
+        // this doesn't disturb the USB driver, which owns the peripheral.
+        let number = unsafe { (*USB::ptr()).fnr.read().fn_().bits() };
+        let elapsed = number.wrapping_sub(self.last) & FRAME_MASK;
+        self.last = number;
+        self.frames = self.frames.wrapping_add(elapsed as u32);
+        self.frames
+    }
+}
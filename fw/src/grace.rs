@@ -0,0 +1,41 @@
+//! A grace period after power-on that ignores keys which are already held.
+//!
+//! If a palm rests on a modifier while the keyboard powers up, that modifier
+//! would be pressed from the first scan, and stay pressed until the palm moves.
+//! Instead, any key seen pressed during the grace period is treated as
+//! released until it's first seen released.
+
+/// Filters held keys out of scans during, and after, the grace period.
+pub struct Grace<const C: usize> {
+    /// A bit for every key that's being treated as released, in the same
+    /// layout as the scan.
+    ignored: [u16; C],
+    /// Number of scans left in the grace period.
+    remaining: u32,
+}
+
+impl<const C: usize> Grace<C> {
+    /// Start a grace period lasting the given number of scans.
+    pub fn new(scans: u32) -> Self {
+        Self {
+            ignored: [0; C],
+            remaining: scans,
+        }
+    }
+
+    /// Remove the keys that are being ignored from a scan.
+    pub fn filter(&mut self, scanout_half: &[u16; C]) -> [u16; C] {
+        let mut filtered = *scanout_half;
+        let in_grace = self.remaining > 0;
+        self.remaining = self.remaining.saturating_sub(1);
+        for (val, ignored) in filtered.iter_mut().zip(self.ignored.iter_mut()) {
+            if in_grace {
+                *ignored |= *val;
+            }
+            // Once a key is seen released, it's no longer ignored.
+            *ignored &= *val;
+            *val &= !*ignored;
+        }
+        filtered
+    }
+}
@@ -7,10 +7,12 @@ use usb_device::bus::{InterfaceNumber, StringIndex, UsbBus, UsbBusAllocator};
 use usb_device::class::{ControlIn, ControlOut, UsbClass};
 use usb_device::control;
 use usb_device::control::{Recipient, RequestType};
-use usb_device::descriptor::DescriptorWriter;
+use usb_device::descriptor::{descriptor_type, DescriptorWriter};
 use usb_device::endpoint::{EndpointAddress, EndpointIn};
 use usb_device::UsbError;
 
+use crate::host::Fingerprint;
+
 pub const SPECIFICATION_RELEASE: u16 = 0x111;
 pub const INTERFACE_CLASS_HID: u8 = 0x03;
 
@@ -81,6 +83,23 @@ impl From<u8> for ReportType {
     }
 }
 
+/// The protocol a boot interface is using, as selected by the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ReportProtocol {
+    /// The fixed reports of the boot protocol, as used by a BIOS.
+    Boot = 0x00,
+    /// The reports described by the report descriptor.
+    Report = 0x01,
+}
+
+/// A HID device, exposed as its own interface by a [`HidClass`].
+///
+/// A device may use report IDs, in which case every report it returns from
+/// `get_report` starts with its ID. A device with a `BootInterface` subclass
+/// must not, as a BIOS only understands the boot reports, which have no ID.
+/// Reports that don't fit the boot protocol belong on another interface, so
+/// that adding them keeps the keyboard working in a BIOS.
 pub trait HidDevice {
     fn subclass(&self) -> Subclass;
 
@@ -99,38 +118,58 @@ pub struct HidClass<'a, B: UsbBus, D: HidDevice> {
     interface: InterfaceNumber,
     endpoint_interrupt_in: EndpointIn<'a, B>,
     expect_interrupt_in_complete: bool,
+    fingerprint: Fingerprint,
+    /// The idle rate set by the host, in units of 4 ms
+    idle: u8,
+    protocol: ReportProtocol,
 }
 
 impl<B: UsbBus, D: HidDevice> HidClass<'_, B, D> {
-    pub fn new(device: D, alloc: &UsbBusAllocator<B>) -> HidClass<'_, B, D> {
+    /// Make a class for the device, whose reports the host polls for every
+    /// `poll_ms` milliseconds.
+    pub fn new(device: D, alloc: &UsbBusAllocator<B>, poll_ms: u8) -> HidClass<'_, B, D> {
         HidClass {
             device,
             interface: alloc.interface(),
-            // NOTE: we want the interval to be as small as possible to
-            // enable the lowest latency possible
-            endpoint_interrupt_in: alloc.interrupt(8, 1),
+            endpoint_interrupt_in: alloc.interrupt(8, poll_ms),
             expect_interrupt_in_complete: false,
+            fingerprint: Fingerprint::default(),
+            idle: 0,
+            protocol: ReportProtocol::Report,
         }
     }
 
+    /// The requests made by the host during enumeration, for guessing its OS.
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.fingerprint
+    }
+
     pub fn device_mut(&mut self) -> &mut D {
         &mut self.device
     }
 
-    pub fn write(&mut self, data: &[u8]) -> Result<usize, ()> {
-        if self.expect_interrupt_in_complete {
-            return Ok(0);
-        }
+    /// The protocol selected by the host. This is always `Report` unless the
+    /// device is a boot interface.
+    pub fn protocol(&self) -> ReportProtocol {
+        self.protocol
+    }
 
-        if data.len() >= 8 {
-            self.expect_interrupt_in_complete = true;
-        }
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, ()> {
+        write_interrupt(
+            &self.endpoint_interrupt_in,
+            &mut self.expect_interrupt_in_complete,
+            data,
+        )
+    }
 
-        match self.endpoint_interrupt_in.write(data) {
-            Ok(count) => Ok(count),
-            Err(UsbError::WouldBlock) => Ok(0),
-            Err(_) => Err(()),
-        }
+    /// Write the input report with the given ID, as returned by the device.
+    pub fn write_report(&mut self, report_id: u8) -> Result<usize, ()> {
+        let data = self.device.get_report(ReportType::Input, report_id)?;
+        write_interrupt(
+            &self.endpoint_interrupt_in,
+            &mut self.expect_interrupt_in_complete,
+            data,
+        )
     }
 
     fn set_report(&mut self, xfer: ControlOut<B>) {
@@ -147,6 +186,30 @@ impl<B: UsbBus, D: HidDevice> HidClass<'_, B, D> {
         let iface: u8 = self.interface.into();
         iface as u16
     }
+
+    fn is_boot_interface(&self) -> bool {
+        self.device.subclass() == Subclass::BootInterface
+    }
+}
+
+fn write_interrupt<B: UsbBus>(
+    endpoint: &EndpointIn<B>,
+    expect_interrupt_in_complete: &mut bool,
+    data: &[u8],
+) -> Result<usize, ()> {
+    if *expect_interrupt_in_complete {
+        return Ok(0);
+    }
+
+    if data.len() >= endpoint.max_packet_size() as usize {
+        *expect_interrupt_in_complete = true;
+    }
+
+    match endpoint.write(data) {
+        Ok(count) => Ok(count),
+        Err(UsbError::WouldBlock) => Ok(0),
+        Err(_) => Err(()),
+    }
 }
 
 impl<B: UsbBus, D: HidDevice> UsbClass<B> for HidClass<'_, B, D> {
@@ -154,6 +217,11 @@ impl<B: UsbBus, D: HidDevice> UsbClass<B> for HidClass<'_, B, D> {
 
     fn reset(&mut self) {
         self.expect_interrupt_in_complete = false;
+        self.fingerprint = Fingerprint::default();
+        self.idle = 0;
+        // A host must select the boot protocol after every reset, and the
+        // report protocol is the default until it does.
+        self.protocol = ReportProtocol::Report;
     }
 
     fn get_configuration_descriptors(
@@ -207,6 +275,15 @@ impl<B: UsbBus, D: HidDevice> UsbClass<B> for HidClass<'_, B, D> {
     fn control_in(&mut self, xfer: ControlIn<B>) {
         let req = xfer.request();
         match (req.request_type, req.recipient) {
+            (RequestType::Standard, Recipient::Device) => {
+                // NOTE: this request is only observed; usb-device answers it
+                // once we return without accepting or rejecting it.
+                if req.request == control::Request::GET_DESCRIPTOR
+                    && req.descriptor_type_index().0 == descriptor_type::STRING
+                {
+                    self.fingerprint.string_request(req.length);
+                }
+            }
             (RequestType::Standard, Recipient::Interface) => {
                 if req.request == control::Request::GET_DESCRIPTOR {
                     let (dtype, index) = req.descriptor_type_index();
@@ -219,6 +296,28 @@ impl<B: UsbBus, D: HidDevice> UsbClass<B> for HidClass<'_, B, D> {
                     }
                 }
             }
+            (RequestType::Class, Recipient::Interface) => {
+                if req.index != self.interface_index() {
+                    return;
+                }
+                match Request::new(req.request) {
+                    Some(Request::GetReport) => {
+                        let [report_type, report_id] = req.value.to_be_bytes();
+                        let report_type = ReportType::from(report_type);
+                        match self.device.get_report(report_type, report_id) {
+                            Ok(data) => xfer.accept_with(data).ok(),
+                            Err(()) => xfer.reject().ok(),
+                        };
+                    }
+                    Some(Request::GetIdle) => {
+                        xfer.accept_with(&[self.idle]).ok();
+                    }
+                    Some(Request::GetProtocol) if self.is_boot_interface() => {
+                        xfer.accept_with(&[self.protocol as u8]).ok();
+                    }
+                    _ => (),
+                }
+            }
             _ => {}
         }
     }
@@ -227,8 +326,24 @@ impl<B: UsbBus, D: HidDevice> UsbClass<B> for HidClass<'_, B, D> {
         let req = xfer.request();
         if req.request_type == RequestType::Class && req.recipient == Recipient::Interface {
             if let Some(request) = Request::new(req.request) {
-                if request == Request::SetReport && req.index == self.interface_index() {
-                    self.set_report(xfer);
+                if req.index != self.interface_index() {
+                    return;
+                }
+                match request {
+                    Request::SetReport => self.set_report(xfer),
+                    Request::SetIdle => {
+                        self.fingerprint.set_idle();
+                        self.idle = req.value.to_be_bytes()[0];
+                        xfer.accept().ok();
+                    }
+                    Request::SetProtocol if self.is_boot_interface() => {
+                        self.protocol = match req.value {
+                            0 => ReportProtocol::Boot,
+                            _ => ReportProtocol::Report,
+                        };
+                        xfer.accept().ok();
+                    }
+                    _ => (),
                 }
             }
         }
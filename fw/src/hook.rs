@@ -0,0 +1,30 @@
+//! Hooks that post-process the keyboard report of a layer.
+//!
+//! A hook is a plain function, listed in `HOOKS` in `main.rs` at the same
+//! keymap and layer as the layout it belongs to. While that layer is active,
+//! it's handed the report built from the keys pressed, after remapping and the
+//! tutor, and may change it however it likes; say, to add Shift to every key
+//! of a symbols layer:
+//!
+//! ```ignore
+//! fn shifted(report: &mut KbHidReport) {
+//!     report.pressed(KeyCode::LShift);
+//! }
+//!
+//! static HOOKS: [&[Option<Hook>]; 1] = [&[None, Some(shifted)]];
+//! ```
+//!
+//! Hooks run on every scan, whether or not any key is pressed, so a hook that
+//! only changes the keys that are pressed should leave an empty report alone.
+//! Keys from chords, the status readout and latched modifiers are added after
+//! the hook has run.
+
+use crate::key_code::KbHidReport;
+
+/// Post-process the report of a layer.
+pub type Hook = fn(&mut KbHidReport);
+
+/// The hook of the layer with the given index, in the hooks of a keymap.
+pub fn find(hooks: &[Option<Hook>], layer: usize) -> Option<Hook> {
+    hooks.get(layer).copied().flatten()
+}
@@ -0,0 +1,143 @@
+//! Guessing the host operating system from how it enumerates the keyboard.
+//!
+//! Operating systems request string descriptors in characteristic ways. In
+//! particular, the `wLength` of these requests differs:
+//!
+//! OS      | `wLength` of string descriptor requests
+//! --------|------------------------------------------
+//! Linux   | 0xff, and never 0x04
+//! Windows | 0xff, at least twice, and 0x04
+//! macOS   | 0x02 and 0x04, but never 0xff
+//!
+//! Linux and Windows also send a `SET_IDLE` request to every boot keyboard,
+//! which is used to tell them apart from macOS when the string requests are
+//! ambiguous.
+
+/// An operating system that needs to be treated differently than the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HostOs {
+    Unknown,
+    Linux,
+    Windows,
+    MacOs,
+}
+
+/// The requests made during enumeration that are used to guess the host OS.
+#[derive(Debug, Default, Clone)]
+pub struct Fingerprint {
+    /// Number of string descriptor requests with a `wLength` of 0x02
+    string_02: u8,
+    /// Number of string descriptor requests with a `wLength` of 0x04
+    string_04: u8,
+    /// Number of string descriptor requests with a `wLength` of 0xff
+    string_ff: u8,
+    /// Has the host sent a `SET_IDLE` request?
+    set_idle: bool,
+}
+
+impl Fingerprint {
+    /// Record a string descriptor request with the given `wLength`.
+    pub fn string_request(&mut self, length: u16) {
+        let count = match length {
+            0x02 => &mut self.string_02,
+            0x04 => &mut self.string_04,
+            0xff => &mut self.string_ff,
+            _ => return,
+        };
+        *count = count.saturating_add(1);
+    }
+
+    /// Record a `SET_IDLE` request.
+    pub fn set_idle(&mut self) {
+        self.set_idle = true;
+    }
+
+    /// Guess which operating system made these requests.
+    pub fn guess(&self) -> HostOs {
+        if self.string_ff >= 2 && self.string_04 >= 1 {
+            HostOs::Windows
+        } else if self.string_ff >= 1 && self.string_04 == 0 {
+            HostOs::Linux
+        } else if self.string_ff == 0 && (self.string_02 >= 1 || self.string_04 >= 1) {
+            if self.set_idle {
+                HostOs::Unknown
+            } else {
+                HostOs::MacOs
+            }
+        } else {
+            HostOs::Unknown
+        }
+    }
+}
+
+/// The host OS that the keyboard adapts to.
+#[derive(Default)]
+pub struct Host {
+    detected: Option<HostOs>,
+    forced: Option<HostOs>,
+}
+
+impl Host {
+    /// Update the OS guessed from enumeration.
+    pub fn detect(&mut self, os: HostOs) {
+        self.detected = Some(os);
+    }
+
+    /// Use the given OS regardless of what's detected, or, with `None`, go
+    /// back to using the detected OS.
+    pub fn force(&mut self, os: Option<HostOs>) {
+        self.forced = os;
+    }
+
+    /// The OS to adapt to.
+    pub fn os(&self) -> HostOs {
+        self.forced.or(self.detected).unwrap_or(HostOs::Unknown)
+    }
+
+    /// Should media key codes be sent as consumer controls too? macOS
+    /// ignores them as key codes, so they are whenever the OS to adapt to is
+    /// macOS, as well as in profiles with the `macos` setting.
+    pub fn media_as_consumer(&self, macos: bool) -> bool {
+        macos || self.os() == HostOs::MacOs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detected_macos_sends_media_as_consumer() {
+        use crate::action::{Action, Layout};
+        use crate::key_code::KeyCode;
+        use crate::scan::consumer;
+
+        let layout: Layout<1, 1> = [[Action::KeyCode(KeyCode::Mute)]];
+        let mut host = Host::default();
+        host.detect(HostOs::Linux);
+        assert_eq!(consumer(&layout, &[1], host.media_as_consumer(false)), 0);
+        host.detect(HostOs::MacOs);
+        assert_eq!(consumer(&layout, &[1], host.media_as_consumer(false)), 0xE2);
+    }
+
+    #[test]
+    fn forced_os_takes_priority_over_detected() {
+        let mut host = Host::default();
+        host.detect(HostOs::MacOs);
+        host.force(Some(HostOs::Linux));
+        assert!(!host.media_as_consumer(false));
+        host.force(None);
+        assert!(host.media_as_consumer(false));
+        host.detect(HostOs::Windows);
+        host.force(Some(HostOs::MacOs));
+        assert!(host.media_as_consumer(false));
+    }
+
+    #[test]
+    fn the_macos_setting_sends_media_as_consumer_on_any_host() {
+        let mut host = Host::default();
+        host.detect(HostOs::Linux);
+        assert!(host.media_as_consumer(true));
+    }
+}
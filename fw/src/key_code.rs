@@ -1,279 +1,6 @@
 //! Key code definitions.
 
-#[allow(missing_docs)]
-/// Define a key code according to the HID specification.  Their names
-/// correspond to the american QWERTY layout.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-#[repr(u8)]
-pub enum KeyCode {
-    /// The "no" key, a placeholder to express nothing.
-    __ = 0x00,
-    /// Error if too much keys are pressed at the same time.
-    ErrorRollOver,
-    /// The POST fail error.
-    PostFail,
-    /// An undefined error occured.
-    ErrorUndefined,
-    /// `a` and `A`.
-    A,
-    B,
-    C,
-    D,
-    E,
-    F,
-    G,
-    H,
-    I,
-    J,
-    K,
-    L,
-    M, // 0x10
-    N,
-    O,
-    P,
-    Q,
-    R,
-    S,
-    T,
-    U,
-    V,
-    W,
-    X,
-    Y,
-    Z,
-    /// `1` and `!`.
-    Kb1,
-    /// `2` and `@`.
-    Kb2,
-    /// `3` and `#`.
-    Kb3, // 0x20
-    /// `4` and `$`.
-    Kb4,
-    /// `5` and `%`.
-    Kb5,
-    /// `6` and `^`.
-    Kb6,
-    /// `7` and `&`.
-    Kb7,
-    /// `8` and `*`.
-    Kb8,
-    /// `9` and `(`.
-    Kb9,
-    /// `0` and `)`.
-    Kb0,
-    Enter,
-    Escape,
-    BSpace,
-    Tab,
-    Space,
-    /// `-` and `_`.
-    Minus,
-    /// `=` and `+`.
-    Equal,
-    /// `[` and `{`.
-    LBracket,
-    /// `]` and `}`.
-    RBracket, // 0x30
-    /// `\` and `|`.
-    Bslash,
-    /// Non-US `#` and `~` (Typically near the Enter key).
-    NonUsHash,
-    /// `;` and `:`.
-    SColon,
-    /// `'` and `"`.
-    Quote,
-    // How to have ` as code?
-    /// \` and `~`.
-    Grave,
-    /// `,` and `<`.
-    Comma,
-    /// `.` and `>`.
-    Dot,
-    /// `/` and `?`.
-    Slash,
-    CapsLock,
-    F1,
-    F2,
-    F3,
-    F4,
-    F5,
-    F6,
-    F7, // 0x40
-    F8,
-    F9,
-    F10,
-    F11,
-    F12,
-    PScreen,
-    ScrollLock,
-    Pause,
-    Insert,
-    Home,
-    PgUp,
-    Delete,
-    End,
-    PgDown,
-    Right,
-    Left, // 0x50
-    Down,
-    Up,
-    NumLock,
-    /// Keypad `/`
-    KpSlash,
-    /// Keypad `*`
-    KpAsterisk,
-    /// Keypad `-`.
-    KpMinus,
-    /// Keypad `+`.
-    KpPlus,
-    /// Keypad enter.
-    KpEnter,
-    /// Keypad 1.
-    Kp1,
-    Kp2,
-    Kp3,
-    Kp4,
-    Kp5,
-    Kp6,
-    Kp7,
-    Kp8, // 0x60
-    Kp9,
-    Kp0,
-    KpDot,
-    /// Non-US `\` and `|` (Typically near the Left-Shift key)
-    NonUsBslash,
-    Application, // 0x65
-    /// not a key, used for errors
-    Power,
-    /// Keypad `=`.
-    KpEqual,
-    F13,
-    F14,
-    F15,
-    F16,
-    F17,
-    F18,
-    F19,
-    F20,
-    F21, // 0x70
-    F22,
-    F23,
-    F24,
-    Execute,
-    Help,
-    Menu,
-    Select,
-    Stop,
-    Again,
-    Undo,
-    Cut,
-    Copy,
-    Paste,
-    Find,
-    Mute,
-    VolUp, // 0x80
-    VolDown,
-    /// Deprecated.
-    LockingCapsLock,
-    /// Deprecated.
-    LockingNumLock,
-    /// Deprecated.
-    LockingScrollLock,
-    /// Keypad `,`, also used for the brazilian keypad period (.) key.
-    KpComma,
-    /// Used on AS/400 keyboard
-    KpEqualSign,
-    Intl1,
-    Intl2,
-    Intl3,
-    Intl4,
-    Intl5,
-    Intl6,
-    Intl7,
-    Intl8,
-    Intl9,
-    Lang1, // 0x90
-    Lang2,
-    Lang3,
-    Lang4,
-    Lang5,
-    Lang6,
-    Lang7,
-    Lang8,
-    Lang9,
-    AltErase,
-    SysReq,
-    Cancel,
-    Clear,
-    Prior,
-    Return,
-    Separator,
-    Out, // 0xA0
-    Oper,
-    ClearAgain,
-    CrSel,
-    ExSel,
-
-    // According to QMK, 0xA5-0xDF are not usable on modern keyboards
-
-    // Modifiers
-    /// Left Control.
-    LCtrl = 0xE0,
-    /// Left Shift.
-    LShift,
-    /// Left Alt.
-    LAlt,
-    /// Left GUI (the Windows key).
-    LGui,
-    /// Right Control.
-    RCtrl,
-    /// Right Shift.
-    RShift,
-    /// Right Alt (or Alt Gr).
-    RAlt,
-    /// Right GUI (the Windows key).
-    RGui, // 0xE7
-
-    // Unofficial
-    MediaPlayPause = 0xE8,
-    MediaStopCD,
-    MediaPreviousSong,
-    MediaNextSong,
-    MediaEjectCD,
-    MediaVolUp,
-    MediaVolDown,
-    MediaMute,
-    MediaWWW, // 0xF0
-    MediaBack,
-    MediaForward,
-    MediaStop,
-    MediaFind,
-    MediaScrollUp,
-    MediaScrollDown,
-    MediaEdit,
-    MediaSleep,
-    MediaCoffee,
-    MediaRefresh,
-    MediaCalc, // 0xFB
-}
-
-impl KeyCode {
-    /// Returns `true` if the key code corresponds to a modifier (sent
-    /// separately on the USB HID report).
-    pub fn is_modifier(self) -> bool {
-        KeyCode::LCtrl <= self && self <= KeyCode::RGui
-    }
-
-    /// Returns the byte with the bit corresponding to the USB HID
-    /// modifier bitfield set.
-    pub fn as_modifier_bit(self) -> u8 {
-        if self.is_modifier() {
-            1 << (self as u8 - KeyCode::LCtrl as u8)
-        } else {
-            0
-        }
-    }
-}
+pub use shared_types::key_code::KeyCode;
 
 /// A standard keyboard USB HID report.
 ///
@@ -302,6 +29,103 @@ impl KbHidReport {
                 .unwrap_or_else(|| self.set_all(ErrorRollOver)),
         }
     }
+    /// The report to send ahead of this one, if this one adds modifiers as
+    /// well as key codes to the `previous` one: this one's modifiers, with
+    /// only the key codes that were already in `previous`. Some hosts race
+    /// the modifiers of a report against its key codes, and see the key
+    /// codes pressed without the modifiers.
+    pub fn modifiers_first(&self, previous: &KbHidReport) -> Option<KbHidReport> {
+        let new_modifiers = self.0[0] & !previous.0[0] != 0;
+        let new_keys = self.0[2..]
+            .iter()
+            .any(|kc| *kc != 0 && !previous.0[2..].contains(kc));
+        if !(new_modifiers && new_keys) {
+            return None;
+        }
+        let mut first = KbHidReport::default();
+        first.0[0] = self.0[0];
+        let kept = self.0[2..]
+            .iter()
+            .filter(|kc| **kc != 0 && previous.0[2..].contains(kc));
+        for (slot, kc) in first.0[2..].iter_mut().zip(kept) {
+            *slot = *kc;
+        }
+        Some(first)
+    }
+
+    /// The report to send ahead of this one, if this one releases modifiers as
+    /// well as key codes from the `previous` one: `previous`'s modifiers, with
+    /// only the key codes that are still held in this one. This unwinds a
+    /// combination like Ctrl+Alt+Del in the reverse of the order it was
+    /// pressed in, so that hosts that race the two don't see the key codes
+    /// held without the modifiers.
+    pub fn keys_released_first(&self, previous: &KbHidReport) -> Option<KbHidReport> {
+        let released_modifiers = previous.0[0] & !self.0[0] != 0;
+        let released_keys = previous.0[2..]
+            .iter()
+            .any(|kc| *kc != 0 && !self.0[2..].contains(kc));
+        if !(released_modifiers && released_keys) {
+            return None;
+        }
+        let mut first = KbHidReport::default();
+        first.0[0] = previous.0[0];
+        let kept = previous.0[2..]
+            .iter()
+            .filter(|kc| **kc != 0 && self.0[2..].contains(kc));
+        for (slot, kc) in first.0[2..].iter_mut().zip(kept) {
+            *slot = *kc;
+        }
+        Some(first)
+    }
+
+    /// Does this report keep every change from `from` to `to`? That is, is
+    /// every modifier and key code that's held in one of them, but not the
+    /// other, held in this report just when it's held in `to`?
+    pub fn keeps_changes(&self, from: &KbHidReport, to: &KbHidReport) -> bool {
+        let changed = from.0[0] ^ to.0[0];
+        let held = |report: &KbHidReport, kc: &u8| report.0[2..].contains(kc);
+        (self.0[0] ^ to.0[0]) & changed == 0
+            && from.0[2..]
+                .iter()
+                .chain(to.0[2..].iter())
+                .filter(|kc| **kc != 0 && held(from, kc) != held(to, kc))
+                .all(|kc| held(self, kc) == held(to, kc))
+    }
+
+    /// The usages of the modifiers and key codes held in this report but not
+    /// in `previous`, with `true`, then of those held in `previous` but not in
+    /// this one, with `false`.
+    #[cfg_attr(not(feature = "report-log"), allow(dead_code))]
+    pub fn changes<'a>(
+        &'a self,
+        previous: &'a KbHidReport,
+    ) -> impl Iterator<Item = (u8, bool)> + 'a {
+        let added = self
+            .usages()
+            .filter(move |usage| !previous.holds(*usage))
+            .map(|usage| (usage, true));
+        let removed = previous
+            .usages()
+            .filter(move |usage| !self.holds(*usage))
+            .map(|usage| (usage, false));
+        added.chain(removed)
+    }
+
+    /// The usages of the modifiers and key codes held in this report.
+    #[cfg_attr(not(feature = "report-log"), allow(dead_code))]
+    fn usages(&self) -> impl Iterator<Item = u8> + '_ {
+        let modifiers = self.0[0];
+        (0..8)
+            .filter(move |bit| modifiers & (1 << bit) != 0)
+            .map(|bit| KeyCode::LCtrl as u8 + bit)
+            .chain(self.0[2..].iter().copied().filter(|kc| *kc != 0))
+    }
+
+    #[cfg_attr(not(feature = "report-log"), allow(dead_code))]
+    fn holds(&self, usage: u8) -> bool {
+        self.usages().any(|held| held == usage)
+    }
+
     fn set_all(&mut self, kc: KeyCode) {
         for c in &mut self.0[2..] {
             *c = kc as u8;
@@ -309,12 +133,44 @@ impl KbHidReport {
     }
 }
 
-pub type Layout<const ROW: usize, const COL: usize> = [[KeyCode; COL]; ROW];
+/// A report holding `keys`, for tests.
+#[cfg(test)]
+pub(crate) fn report(keys: &[KeyCode]) -> KbHidReport {
+    let mut report = KbHidReport::default();
+    for &kc in keys {
+        report.pressed(kc);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeyCode::*;
+
+    #[test]
+    fn keys_released_first_releases_the_keys_ahead_of_modifiers() {
+        let previous = report(&[LCtrl, LAlt, Delete]);
+        assert_eq!(
+            report(&[]).keys_released_first(&previous),
+            Some(report(&[LCtrl, LAlt]))
+        );
+    }
+
+    #[test]
+    fn keys_released_first_keeps_keys_still_held() {
+        let previous = report(&[LShift, A, B]);
+        assert_eq!(
+            report(&[B]).keys_released_first(&previous),
+            Some(report(&[LShift, B]))
+        );
+    }
 
-pub fn keycode<const COL: usize, const ROW: usize>(
-    layout: &'static Layout<ROW, COL>,
-    row: usize,
-    col: usize,
-) -> Option<&'static KeyCode> {
-    layout.get(row).and_then(|l| l.get(col))
+    #[test]
+    fn keys_released_first_needs_both_released() {
+        let previous = report(&[LCtrl, C]);
+        assert_eq!(report(&[LCtrl]).keys_released_first(&previous), None);
+        assert_eq!(report(&[C]).keys_released_first(&previous), None);
+        assert_eq!(report(&[LCtrl, C]).keys_released_first(&previous), None);
+    }
 }
@@ -1,20 +1,295 @@
 //! Keyboard HID device implementation.
 
+use crate::action::Action;
+use crate::audit::Audit;
+use crate::calibrate;
+use crate::counters::Counters;
+use crate::disable::Disabled;
+use crate::features;
 use crate::hid::{HidDevice, Protocol, ReportType, Subclass};
+use crate::host::HostOs;
 use crate::key_code::KbHidReport;
+use crate::rate::ScanRate;
+use crate::schedule::{Rule, WeekTime};
+use crate::settings::Settings;
 
 const REPORT_DESCRIPTOR: &[u8] = &[
     0x05, 0x01, 0x09, 0x06, 0xA1, 0x01, 0x05, 0x07, 0x19, 0xE0, 0x29, 0xE7, 0x15, 0x00, 0x25, 0x01,
     0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x01, 0x75, 0x08, 0x81, 0x03, 0x95, 0x05, 0x75, 0x01,
     0x05, 0x08, 0x19, 0x01, 0x29, 0x05, 0x91, 0x02, 0x95, 0x01, 0x75, 0x03, 0x91, 0x03, 0x95, 0x06,
     0x75, 0x08, 0x15, 0x00, 0x25, 0xFB, 0x05, 0x07, 0x19, 0x00, 0x29, 0xFB, 0x81, 0x00, 0x09, 0x03,
-    0x75, 0x08, 0x95, 0x40, 0xB1, 0x02, 0xC0,
+    0x75, 0x08, 0x95, 0x80, 0xB1, 0x02, 0xC0,
 ];
 
+/// Length of the feature report, as declared at the end of the report descriptor.
+/// It may be no longer than the 128 byte buffer that `usb-device` uses for
+/// control transfers.
+const FEATURE_REPORT_LEN: usize = 128;
+
+/// Parse a decimal number at compile time.
+const fn parse_u8(s: &str) -> u8 {
+    let bytes = s.as_bytes();
+    let mut val: u8 = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        val = val * 10 + (bytes[i] - b'0');
+        i += 1;
+    }
+    val
+}
+
+/// Commands the host may send with a feature report.
+///
+/// The first byte of the report selects the command, and the rest are its
+/// arguments:
+///
+/// Byte 0 | Command           | Arguments
+/// -------|-------------------|-----------------------------------------
+/// 1      | `SelectProfile`   | profile id
+/// 2      | `WriteProfile`    | profile id, then the settings as bytes
+/// 3      | `BlockKey`        | key code, then 1 to block it or 0 to unblock it
+/// 4      | `Inject`          | row, col, then 1 to press the key or 0 to release it
+/// 5      | `DebounceMode`    | 1 to defer changes until keys are stable, or 0 not to
+/// 6      | `Reroute`         | row and col wired to, then row and col to act as
+/// 7      | `SettleSweep`     | none
+/// 8      | `DisableKey`      | row, col, then 1 to disable the key or 0 to enable it
+/// 9      | `CalibrateAnalog` | 1 to start calibrating the analog keys or 0 to finish
+/// 10     | `AnalogPoints`    | analog key index, actuation depth, release depth
+/// 11     | `PreviewLayer`    | layer, then row
+/// 12     | `SetClock`        | local time, in seconds since 1970, little endian
+/// 13     | `ScheduleRule`    | index, days, minute of the day, little endian, profile id
+/// 14     | `Audit`           | 1 to report key events here instead of typing, or 0
+///
+/// `Inject` is meant for testing on real hardware: the key is held as if the
+/// debouncer said so, on top of the keys that are really held, until it's
+/// released by another `Inject`.
+///
+/// `Reroute` makes the key wired to one position act as the key at another,
+/// for a switch that's been rewired after a repair, and is stored in flash.
+/// Rerouting a position to itself stops rerouting it. See [`crate::reroute`].
+///
+/// `DisableKey` mutes a key whose switch has failed, and is stored in flash.
+/// See [`crate::disable`].
+///
+/// `CalibrateAnalog` records the travel of each analog key, and `AnalogPoints`
+/// sets the depths, in 256ths of that travel, at which one is pressed and
+/// released, or 0 for the defaults. Both are stored in flash. See
+/// [`crate::calibrate`].
+///
+/// `PreviewLayer` puts what each key in a row of a layer of the active keymap
+/// does into the feature report, so that a configurator can show the keymap,
+/// a row at a time, without knowing how layouts are resolved. See
+/// [`crate::scan::preview`].
+///
+/// `SetClock` sets the time that `ScheduleRule`s go by, which the host should
+/// send whenever it connects, and `ScheduleRule` sets when a profile is made
+/// active, and is stored in flash. Days have a bit each, from bit 0 for Monday,
+/// and a rule with none is removed. See [`crate::schedule`].
+///
+/// `Audit` stops the keyboard typing, or doing anything else, and reports each
+/// press and release in the feature report instead, to check a new layout.
+/// See [`crate::audit`].
+///
+/// `SettleSweep` finds the minimum reliable settle time of each column, while
+/// some keys are held. See [`crate::sweep`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    SelectProfile(u8),
+    WriteProfile(u8, Settings),
+    BlockKey(u8, bool),
+    Inject(u8, u8, bool),
+    DebounceMode(bool),
+    Reroute((u8, u8), (u8, u8)),
+    SettleSweep,
+    DisableKey(u8, u8, bool),
+    CalibrateAnalog(bool),
+    AnalogPoints(u8, u8, u8),
+    PreviewLayer(u8, u8),
+    SetClock(u32),
+    ScheduleRule(u8, Rule),
+    Audit(bool),
+}
+
+impl Command {
+    fn parse(data: &[u8]) -> Option<Self> {
+        match data {
+            [1, id, ..] => Some(Command::SelectProfile(*id)),
+            [2, id, settings @ ..] => {
+                Settings::from_bytes(settings).map(|s| Command::WriteProfile(*id, s))
+            }
+            [3, kc, blocked @ 0..=1, ..] => Some(Command::BlockKey(*kc, *blocked != 0)),
+            [4, row, col, pressed @ 0..=1, ..] => {
+                Some(Command::Inject(*row, *col, *pressed != 0))
+            }
+            [5, deferred @ 0..=1, ..] => Some(Command::DebounceMode(*deferred != 0)),
+            [6, from_row, from_col, to_row, to_col, ..] => Some(Command::Reroute(
+                (*from_row, *from_col),
+                (*to_row, *to_col),
+            )),
+            [7, ..] => Some(Command::SettleSweep),
+            [8, row, col, disabled @ 0..=1, ..] => {
+                Some(Command::DisableKey(*row, *col, *disabled != 0))
+            }
+            [9, start @ 0..=1, ..] => Some(Command::CalibrateAnalog(*start != 0)),
+            [10, key, actuation, release, ..] => {
+                Some(Command::AnalogPoints(*key, *actuation, *release))
+            }
+            [11, layer, row, ..] => Some(Command::PreviewLayer(*layer, *row)),
+            [12, a, b, c, d, ..] => Some(Command::SetClock(u32::from_le_bytes([*a, *b, *c, *d]))),
+            [13, index, days, minute_lo, minute_hi, profile, ..] => Some(Command::ScheduleRule(
+                *index,
+                Rule {
+                    days: *days,
+                    minute: u16::from_le_bytes([*minute_lo, *minute_hi]),
+                    profile: *profile,
+                },
+            )),
+            [14, on @ 0..=1, ..] => Some(Command::Audit(*on != 0)),
+            _ => None,
+        }
+    }
+}
+
 /// A keyboard HID device.
-#[derive(Default)]
 pub struct Keyboard {
     pub report: KbHidReport,
+    /// The feature report read by the host. It describes the firmware:
+    ///
+    /// Byte   | Contents
+    /// -------|-------------------------
+    /// 0..3   | major, minor and patch version of the firmware
+    /// 3      | the id of the active profile
+    /// 4      | the host OS that the keyboard is adapting to
+    /// 5..7   | the last interval between scans, in microseconds
+    /// 7..9   | the shortest interval between scans, in microseconds
+    /// 9..11  | the longest interval between scans, in microseconds
+    /// 11     | 1 if the debouncer defers changes until keys are stable, or 0
+    /// 12..18 | the minimum reliable settle time of each column, in twentieths
+    ///        | of a column, from the last `SettleSweep`, or 0 if unknown
+    /// 18..20 | the features the firmware was built with; see
+    ///        | [`crate::features`]
+    /// 20..32 | the disabled keys, with a bit per row for each column; see
+    ///        | [`crate::disable`]
+    /// 32     | the number of `LogMarker` keys pressed, wrapping around
+    /// 33     | the id of the last `LogMarker` key pressed
+    /// 34..38 | the time that it was pressed, in scans, as in the debouncer's log
+    /// 38..46 | the latest reading of each analog key's sensor, or 0 without
+    ///        | analog keys
+    /// 46     | 1 while the analog keys are being calibrated, or 0
+    /// 47     | the layer of the last `PreviewLayer`
+    /// 48     | the row of the last `PreviewLayer`
+    /// 49..61 | what each key in that row does, in two bytes per column; see
+    ///        | [`Action::to_bytes`], or 0 and 0 for nothing
+    /// 64..80 | counts of scans dropped, USB writes failed, storms of presses
+    ///        | throttled, and resets; see [`crate::counters`]
+    /// 80..84 | the time of the week that schedules go by, in seconds since
+    ///        | Monday at midnight, or all ones if it hasn't been set
+    /// 84..126 | whether the keyboard is auditing, and the latest key events
+    ///         | if so; see [`Audit::to_bytes`]
+    ///
+    /// The intervals, features, disabled keys, times, readings and counts are
+    /// little endian.
+    version: [u8; FEATURE_REPORT_LEN],
+    /// The most recent command from the host that has yet to be handled.
+    command: Option<Command>,
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        let mut version = [0; FEATURE_REPORT_LEN];
+        version[0] = parse_u8(env!("CARGO_PKG_VERSION_MAJOR"));
+        version[1] = parse_u8(env!("CARGO_PKG_VERSION_MINOR"));
+        version[2] = parse_u8(env!("CARGO_PKG_VERSION_PATCH"));
+        version[18..20].copy_from_slice(&features::CAPABILITIES.to_le_bytes());
+        Self {
+            report: Default::default(),
+            version,
+            command: None,
+        }
+    }
+}
+
+impl Keyboard {
+    /// Update the active profile reported to the host.
+    pub fn set_active_profile(&mut self, id: u8) {
+        self.version[3] = id;
+    }
+
+    /// Update the host OS reported to the host.
+    pub fn set_host_os(&mut self, os: HostOs) {
+        self.version[4] = os as u8;
+    }
+
+    /// Update the intervals between scans reported to the host.
+    pub fn set_scan_rate(&mut self, rate: &ScanRate) {
+        self.version[5..7].copy_from_slice(&rate.current.to_le_bytes());
+        self.version[7..9].copy_from_slice(&rate.min.to_le_bytes());
+        self.version[9..11].copy_from_slice(&rate.max.to_le_bytes());
+    }
+
+    /// Update the debounce mode reported to the host.
+    pub fn set_deferred(&mut self, deferred: bool) {
+        self.version[11] = deferred as u8;
+    }
+
+    /// Update the results of the last settle time sweep reported to the host.
+    pub fn set_settle(&mut self, settle: &[u8; 6]) {
+        self.version[12..18].copy_from_slice(settle);
+    }
+
+    /// Update the disabled keys reported to the host.
+    pub fn set_disabled(&mut self, disabled: &Disabled) {
+        self.version[20..32].copy_from_slice(&disabled.to_bytes());
+    }
+
+    /// Report that a `LogMarker` key with the given id was pressed at the given
+    /// time, so that a host polling the feature report can tell when.
+    pub fn mark(&mut self, id: u8, now: u32) {
+        self.version[32] = self.version[32].wrapping_add(1);
+        self.version[33] = id;
+        self.version[34..38].copy_from_slice(&now.to_le_bytes());
+    }
+
+    /// Update the readings of the analog keys reported to the host, and whether
+    /// they're being calibrated.
+    pub fn set_analog(&mut self, readings: &[u16; calibrate::KEYS], calibrating: bool) {
+        let bytes = self.version[38..46].chunks_exact_mut(2);
+        for (bytes, reading) in bytes.zip(readings.iter()) {
+            bytes.copy_from_slice(&reading.to_le_bytes());
+        }
+        self.version[46] = calibrating as u8;
+    }
+
+    /// Update the preview of a row of a layer reported to the host.
+    pub fn set_preview(&mut self, layer: u8, row: u8, actions: &[Option<Action>; 6]) {
+        self.version[47] = layer;
+        self.version[48] = row;
+        let bytes = self.version[49..61].chunks_exact_mut(Action::SIZE);
+        for (bytes, action) in bytes.zip(actions.iter()) {
+            bytes.copy_from_slice(&action.map_or([0; Action::SIZE], |a| a.to_bytes()));
+        }
+    }
+
+    /// Update the counts of things going wrong reported to the host.
+    pub fn set_counters(&mut self, counters: &Counters) {
+        self.version[64..80].copy_from_slice(&counters.to_bytes());
+    }
+
+    /// Update the time of the week reported to the host.
+    pub fn set_clock(&mut self, time: Option<WeekTime>) {
+        let secs = time.map_or(u32::MAX, |time| time.0);
+        self.version[80..84].copy_from_slice(&secs.to_le_bytes());
+    }
+
+    /// Update the key events reported to the host while auditing.
+    pub fn set_audit(&mut self, audit: &Audit) {
+        self.version[84..126].copy_from_slice(&audit.to_bytes());
+    }
+
+    /// Take the most recent command from the host, if there is one.
+    pub fn take_command(&mut self) -> Option<Command> {
+        self.command.take()
+    }
 }
 
 impl HidDevice for Keyboard {
@@ -33,6 +308,7 @@ impl HidDevice for Keyboard {
     fn get_report(&mut self, report_type: ReportType, _report_id: u8) -> Result<&[u8], ()> {
         match report_type {
             ReportType::Input => Ok(self.report.as_bytes()),
+            ReportType::Feature => Ok(&self.version),
             _ => Err(()),
         }
     }
@@ -46,6 +322,10 @@ impl HidDevice for Keyboard {
         if report_type == ReportType::Output && report_id == 0 && data.len() == 1 {
             return Ok(());
         }
+        if report_type == ReportType::Feature && report_id == 0 {
+            self.command = Some(Command::parse(data).ok_or(())?);
+            return Ok(());
+        }
         Err(())
     }
 }
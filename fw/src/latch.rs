@@ -0,0 +1,92 @@
+//! Latching modifiers, for typing with one hand.
+//!
+//! While latching is on, tapping a modifier latches it: it's reported until a
+//! key that isn't a modifier is pressed and released, so a shortcut can be
+//! typed one key at a time. Tapping it again, while it's latched, locks it on
+//! until it's tapped a third time. Holding a modifier works as it always does.
+//!
+//! This is unlike one-shot modifiers in that it's a setting of the profile,
+//! and applies to every modifier in the keymap.
+
+use crate::action::Action;
+use crate::key_code::KeyCode::{self, *};
+
+/// The modifiers, in the order of their bits in the report.
+const MODIFIERS: [KeyCode; 8] = [LCtrl, LShift, LAlt, LGui, RCtrl, RShift, RAlt, RGui];
+
+/// The modifiers that are latched or locked.
+#[derive(Default)]
+pub struct Latch {
+    /// A bit for each latched modifier, as in the report.
+    latched: u8,
+    /// A bit for each locked modifier, as in the report.
+    locked: u8,
+    /// The modifier pressed last, if nothing else has been pressed since.
+    tapping: Option<KeyCode>,
+    /// Has a key been pressed while modifiers were latched?
+    used: bool,
+    enabled: bool,
+}
+
+impl Latch {
+    /// Turn latching on or off. Turning it off releases every latched and
+    /// locked modifier.
+    pub fn enable(&mut self, enabled: bool) {
+        if !enabled {
+            *self = Self::default();
+        }
+        self.enabled = enabled;
+    }
+
+    /// Note that a key with the given action was pressed.
+    pub fn press(&mut self, action: Action) {
+        if !self.enabled {
+            return;
+        }
+        match action {
+            Action::KeyCode(kc) if kc.is_modifier() => self.tapping = Some(kc),
+            Action::KeyCode(_) => {
+                self.tapping = None;
+                self.used |= self.latched != 0;
+            }
+            _ => self.tapping = None,
+        }
+    }
+
+    /// Note that a key with the given action was released.
+    pub fn release(&mut self, action: Action) {
+        if !self.enabled {
+            return;
+        }
+        match action {
+            Action::KeyCode(kc) if kc.is_modifier() => {
+                if self.tapping.take() != Some(kc) {
+                    return;
+                }
+                let bit = kc.as_modifier_bit();
+                if self.locked & bit != 0 {
+                    self.locked &= !bit;
+                } else if self.latched & bit != 0 {
+                    self.latched &= !bit;
+                    self.locked |= bit;
+                } else {
+                    self.latched |= bit;
+                }
+            }
+            Action::KeyCode(_) if self.used => {
+                self.latched = 0;
+                self.used = false;
+            }
+            _ => (),
+        }
+    }
+
+    /// The modifiers to report, as they're latched or locked.
+    pub fn modifiers(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        let bits = self.latched | self.locked;
+        MODIFIERS
+            .iter()
+            .copied()
+            .filter(move |kc| bits & kc.as_modifier_bit() != 0)
+    }
+}
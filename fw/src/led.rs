@@ -0,0 +1,27 @@
+//! The status LED built into the Blue Pill board.
+
+use embedded_hal::digital::v2::OutputPin;
+use stm32f1xx_hal::gpio::{gpioc::PC13, Output, PushPull};
+
+/// The LED on PC13.
+///
+/// It's wired between 3.3V and the pin, so it lights when the pin is low.
+pub struct StatusLed(PC13<Output<PushPull>>);
+
+impl StatusLed {
+    pub fn new(pin: PC13<Output<PushPull>>) -> Self {
+        let mut led = Self(pin);
+        led.set(false);
+        led
+    }
+
+    /// Turn the LED on or off.
+    pub fn set(&mut self, on: bool) {
+        // NOTE: setting a GPIO is infallible, so there's nothing to handle
+        let _ = if on {
+            self.0.set_low()
+        } else {
+            self.0.set_high()
+        };
+    }
+}
@@ -1,25 +1,122 @@
-#![no_main]
-#![no_std]
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(not(test), no_std)]
 
+#[cfg(not(test))]
 use panic_halt as _;
 use embedded_hal::digital::v2::OutputPin;
 use stm32f1xx_hal::prelude::*;
+#[cfg(feature = "analog-keys")]
+use stm32f1xx_hal::adc::Adc;
 use stm32f1xx_hal::usb::{Peripheral, UsbBus, UsbBusType};
-use stm32f1xx_hal::pac::Peripherals;
+use stm32f1xx_hal::pac::{interrupt, Interrupt, Peripherals, RCC};
 use usb_device::bus::UsbBusAllocator;
 use usb_device::prelude::*;
+use cortex_m::interrupt::Mutex;
+use cortex_m::singleton;
 use cortex_m_rt::entry;
+use core::cell::RefCell;
 use core::default::Default;
+use core::fmt::Write as _;
+#[cfg(feature = "hot-keymap")]
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering;
 
+#[macro_use]
+mod action;
+#[cfg(feature = "analog-keys")]
+mod analog;
+mod audit;
+mod calibrate;
+mod chord;
+mod access;
+mod composite;
+mod counters;
+mod custom;
+mod disable;
+#[cfg(feature = "encoders")]
+mod encoder;
+mod error;
+#[cfg(feature = "debug-signals")]
+mod debug;
+mod features;
+#[cfg(feature = "sof-timebase")]
+mod frame;
+mod grace;
 mod hid;
+mod hook;
+mod host;
 mod key_code;
 mod keyboard;
+mod latch;
+mod led;
+mod pace;
+mod rate;
+mod remap;
+mod repeat;
+mod reroute;
+mod rest;
+mod safe_mode;
 mod scan;
+mod schedule;
+mod settings;
+mod snapshot;
+mod startup;
+mod status;
+mod sticky;
+mod storage;
+mod storm;
+mod sweep;
 mod trigger;
+#[cfg(feature = "dmote")]
+mod thumbs;
+mod tick;
+#[cfg(feature = "hot-keymap")]
+mod trial;
+mod tutor;
+mod warp;
+mod wiring;
 
-use key_code::{KeyCode::*, Layout};
-use scan::{dma_key_scan, scan, report, Cols, Log, Matrix, Rows};
-use trigger::QuickDraw;
+use access::Access;
+use action::{action, Action, Layout};
+use audit::Audit;
+use chord::Chords;
+use counters::Counters;
+use error::Error;
+use custom::{Context, Custom};
+#[cfg(feature = "sof-timebase")]
+use frame::FrameClock;
+use led::StatusLed;
+use pace::Pacer;
+use rate::ScanRate;
+use repeat::Repeat;
+use rest::Rest;
+use scan::{dma_key_scan, report, Cols, Event, Log, Matrix, Rows};
+use schedule::{Clock, WeekTime};
+use grace::Grace;
+use hook::Hook;
+use host::Host;
+use keyboard::Command;
+use latch::Latch;
+use settings::{Profiles, GAMING};
+use startup::{Loaded, Startup};
+use status::Status;
+use sticky::Sticky;
+use storage::Storage;
+use storm::Storm;
+#[cfg(feature = "dmote")]
+use thumbs::{Cluster, Thumbs};
+#[cfg(feature = "report-log")]
+use tick::ReportLogQueue;
+use tick::{
+    ReportLog, ScanQueue, ScanTask, DEBOUNCE, DEBOUNCE_RIGHT, DEFERRED, DISABLED, MARKER,
+    MARKER_ID, SWEEP,
+};
+#[cfg(feature = "hot-keymap")]
+use trial::Trial;
+use trigger::{Mode, QuickDraw};
+use tutor::Tutor;
+use warp::Warp;
+use wiring::WiringTest;
 
 /// A handly shortcut for the USB class type.
 pub type UsbClass = hid::HidClass<'static, UsbBusType, keyboard::Keyboard>;
@@ -28,9 +125,26 @@ const VID: u16 = 0x1209;
 
 const PID: u16 = 0x345c;
 
+/// Interval between the host's polls for reports, in ms; the `bInterval` of
+/// the endpoints. It's as small as possible, for the lowest latency, but may be
+/// raised for a host that struggles to keep up.
+const POLL_MS: u8 = 1;
+
+/// A handly shortcut for the USB class type of the consumer control and mouse
+/// interface.
+pub type UsbExtraClass = hid::HidClass<'static, UsbBusType, composite::Composite>;
+
 /// Constructor for `Class`.
 pub fn new_class(bus: &'static UsbBusAllocator<UsbBusType>) -> UsbClass {
-    hid::HidClass::new(keyboard::Keyboard::default(), bus)
+    hid::HidClass::new(keyboard::Keyboard::default(), bus, POLL_MS)
+}
+
+/// Constructor for `UsbExtraClass`.
+///
+/// This must be called after `new_class`, so that the keyboard is the first
+/// interface, which some BIOSes require of a boot keyboard.
+pub fn new_extra_class(bus: &'static UsbBusAllocator<UsbBusType>) -> UsbExtraClass {
+    hid::HidClass::new(composite::Composite::default(), bus, POLL_MS)
 }
 
 /// Constructor for a USB keyboard device.
@@ -41,14 +155,13 @@ pub fn new_device(
         .manufacturer("Me")
         .product("Dactyl Manuform: OTE")
         .serial_number(env!("CARGO_PKG_VERSION"))
-        .device_class(hid::INTERFACE_CLASS_HID)
         .build()
 }
 
 /// Mapping from switch positions to keys symbols; 'a', '1', '$', etc.
 #[rustfmt::skip]
 #[cfg(feature = "dmote")]
- pub static LAYOUT: Layout<13, 6> = [
+ pub static LAYOUT: Layout<13, 6> = thumbs::place(layout![
      /*                 Port A                          */
      /* 0     1       2            3          4       5 */
      /* -------------- Left Fingers -------------------      Port B */
@@ -57,31 +170,43 @@ pub fn new_device(
      [__,     Q,      S,           D,         F,      G     ], /* 5 */
      [__,     A,      X,           C,         V,      B     ], /* 6 */
      [__,     Z,      NonUsBslash, Left,      Right,  __    ], /* 7 */
-     /* ------------------- Thumbs -------------------- */
-     /*  Thumb Cluster  Last Middle key  Thumb Cluster
-      *      +---+       +---+   +---+       +---+
-      *  +---+9,0+---+   |8,2|   |8,3|   +---+9,5+---+
-      *  |a,0+---+8,0|   +---+   +---+   |a,5+---+8,5|
-      *  +---+9,1+---+                   +---+9,4+---+
-      *  |a,1+---+8,1|     Face keys     |a,4+---+a,4|
-      *  +---+9,2+---+   +---+   +---+   +---+9,3+---+
-      *      +---+       |a,2|   |a,3|       +---+
-      *                  +---+   +---+
-      */
-     /* --- Right  ------------|---------- Left ------- */
-     [__,     BSpace, RBracket,    Grave,     LShift, LCtrl ], /* 8 */
-     [RAlt,   Enter,  Tab,         Escape,    Space,  LAlt  ], /* 9 */
-     [Kb3,    Kb4,    F12,         Pause,     Kb8,    Kb5   ], /* 10(a) */
+     /* ------------------- Thumbs, below -------------- */
+     [__,     __,     __,          __,        __,     __    ], /* 8 */
+     [__,     __,     __,          __,        __,     __    ], /* 9 */
+     [__,     __,     __,          __,        __,     __    ], /* 10(a) */
      /* ------------- Right Fingers ----------------- */
      [__,     __,     __,          __,        __,     __    ], /* 11 */
      [Y,      U,      I,           O,         __,     __    ], /* 12 */
      [H,      J,      K,           L,         P,      Bslash], /* 13 */
      [N,      M,      Comma,       Dot,       SColon, Quote ], /* 14 */
      [__,     Up,     Down,        LBracket,  Slash,  RShift], /* 15 */
-];
+ ], Thumbs {
+     /*  Thumb Cluster  Last Middle key  Thumb Cluster
+      *      +---+       +---+   +---+       +---+
+      *  +---+ m +---+   | 0 |   | 1 |   +---+ m +---+
+      *  | l +---+ r |   +---+   +---+   | l +---+ r |
+      *  +---+ m +---+                   +---+ m +---+
+      *  | l +---+ r |     Face keys     | l +---+ r |
+      *  +---+ m +---+   +---+   +---+   +---+ m +---+
+      *      +---+       | 0 |   | 1 |       +---+
+      *                  +---+   +---+
+      */
+     right_thumb: Cluster {
+         left:   keys![Kb3, Kb4],
+         middle: keys![RAlt, Enter, Tab],
+         right:  keys![{Action::Layer(1)}, BSpace],
+     },
+     left_thumb: Cluster {
+         left:   keys![Kb5, Kb8],
+         middle: keys![LAlt, Space, Escape],
+         right:  keys![LCtrl, LShift],
+     },
+     middle: keys![RBracket, Grave],
+     face:   keys![F12, Pause],
+ });
 #[rustfmt::skip]
 #[cfg(feature = "dmote")]
- pub static LAYOUT_ALT: Layout<13, 6> = [
+ pub static LAYOUT_ALT: Layout<13, 6> = thumbs::place(layout![
      /*                 Port A                          */
      /* 0     1       2            3          4       5 */
      /* -------------- Left Fingers -------------------      Port B */
@@ -89,32 +214,44 @@ pub fn new_device(
      [__,     __,     F3,          F4,        F5,     F6    ], /* 4 */
      [F1,     F2,     Kb2,         Kb3,       Kb4,    Kb5   ], /* 5 */
      [Equal,  Kb1,    X,           C,         V,      B     ], /* 6 */
-     [__,     Z,      NonUsBslash, Home,      End,    __    ], /* 7 */
-     /* ------------------- Thumbs -------------------- */
-     /*  Thumb Cluster  Last Middle key  Thumb Cluster
-      *      +---+       +---+   +---+       +---+
-      *  +---+9,0+---+   |8,2|   |8,3|   +---+9,5+---+
-      *  |a,0+---+8,0|   +---+   +---+   |a,5+---+8,5|
-      *  +---+9,1+---+                   +---+9,4+---+
-      *  |a,1+---+8,1|     Face keys     |a,4+---+a,4|
-      *  +---+9,2+---+   +---+   +---+   +---+9,3+---+
-      *      +---+       |a,2|   |a,3|       +---+
-      *                  +---+   +---+
-      */
-     /* --- Right  ------------|---------- Left ------- */
-     [__,     BSpace, RBracket,    Grave,     LShift, LCtrl ], /* 8 */
-     [RAlt,   Enter,  Tab,         Escape,    Space,  LAlt  ], /* 9 */
-     [PgUp,   PgDown, F12,         Pause,     End,    Home  ], /* 10(a) */
+     [__,     Z,      NonUsBslash, Home,      End,    {Action::GamingMode}], /* 7 */
+     /* ------------------- Thumbs, below -------------- */
+     [__,     __,     __,          __,        __,     __    ], /* 8 */
+     [__,     __,     __,          __,        __,     __    ], /* 9 */
+     [__,     __,     __,          __,        __,     __    ], /* 10(a) */
      /* ------------- Right Fingers ----------------- */
      [__,     __,     __,          __,        __,     __    ], /* 11 */
      [F7,     F8,     F9,          F10,       __,    __     ], /* 12 */
      [Kb6,    Kb7,    Kb8,         Kb9,       F11,    F12   ], /* 13 */
      [N,      M,      Comma,       Dot,       Kb0,    Minus ], /* 14 */
      [__,     PgUp,   PgDown,      LBracket,  Slash,  RShift], /* 15 */
-];
+ ], Thumbs {
+     /*  Thumb Cluster  Last Middle key  Thumb Cluster
+      *      +---+       +---+   +---+       +---+
+      *  +---+ m +---+   | 0 |   | 1 |   +---+ m +---+
+      *  | l +---+ r |   +---+   +---+   | l +---+ r |
+      *  +---+ m +---+                   +---+ m +---+
+      *  | l +---+ r |     Face keys     | l +---+ r |
+      *  +---+ m +---+   +---+   +---+   +---+ m +---+
+      *      +---+       | 0 |   | 1 |       +---+
+      *                  +---+   +---+
+      */
+     right_thumb: Cluster {
+         left:   keys![PgUp, PgDown],
+         middle: keys![RAlt, Enter, Tab],
+         right:  keys![{Action::Layer(1)}, BSpace],
+     },
+     left_thumb: Cluster {
+         left:   keys![Home, End],
+         middle: keys![LAlt, Space, Escape],
+         right:  keys![LCtrl, LShift],
+     },
+     middle: keys![RBracket, Grave],
+     face:   keys![F12, Pause],
+ });
 #[rustfmt::skip]
 #[cfg(feature = "dactyl")]
-pub static LAYOUT: Layout<13, 6> = [
+pub static LAYOUT: Layout<13, 6> = layout![
     /*                 Port A                            */
     /* 0     1       2            3          4         5 */
     /* -------------- Left Fingers ------------------------- Port B */
@@ -134,8 +271,8 @@ pub static LAYOUT: Layout<13, 6> = [
      *                        +---+---+---+
      */
     [LShift, BSpace, End,         Home,     LAlt,     LCtrl ], /* 8 */
-    /* PB9 is not wired to anything on the Dactyl */
-    [__,     __,     __,          __,       __,       __    ], /* 9 */
+    /* PB9 is only wired to the rotary encoders, with `encoders` */
+    [VolUp,  VolDown, PgDown,     PgUp,     __,       __    ], /* 9 */
     /* ------------- Right Fingers --------------------------         */
     [Kb6,    Kb7,    Kb8,         Kb9,      Kb0,      Minus ], /* 10 */
     [Y,      U,      I,           O,        P,        Bslash], /* 11 */
@@ -155,38 +292,476 @@ pub static LAYOUT: Layout<13, 6> = [
     [RCtrl,  RGui,   PgUp,        PgDown,   Enter,    Space], /* 15 */
 ];
 
-static mut USB_BUS: Option<UsbBusAllocator<UsbBusType>> = None;
-static mut USB_CLASS: Option<UsbClass> = None;
+/*
+ * The ErgoDox, hand-wired to the same matrix. Each half has 38 keys: the six
+ * outer columns use that half's five rows, and the inner column and thumb
+ * cluster use that half's three columns of the shared rows.
+ *
+ *                  Left thumb cluster        Right thumb cluster
+ *                      +---+---+                 +---+---+
+ *                      |9,3|9,4|                 |9,0|9,1|
+ *                  +---+---+---+                 +---+---+---+
+ *                  |   |   |9,5|                 |9,2|   |   |
+ *                  |a,3|a,4+---+                 +---+a,1|a,0|
+ *                  |   |   |a,5|                 |a,2|   |   |
+ *                  +---+---+---+                 +---+---+---+
+ */
+#[rustfmt::skip]
+#[cfg(feature = "ergodox")]
+pub static LAYOUT: Layout<13, 6> = layout![
+    /*                 Port A                            */
+    /* 0     1       2            3          4         5 */
+    /* -------------- Left Fingers ------------------------- Port B */
+    [Equal,  Kb1,    Kb2,         Kb3,      Kb4,      Kb5   ], /* 3 */
+    [Delete, Q,      W,           E,        R,        T     ], /* 4 */
+    [BSpace, A,      S,           D,        F,        G     ], /* 5 */
+    [LShift, Z,      X,           C,        V,        B     ], /* 6 */
+    [Grave,  Quote,  LAlt,        Left,     Right,    __    ], /* 7 */
+    /* --- Right inner column -|- Left inner column --- */
+    /* row 0,  row 1,              row 3     row 0,   row 1,               row 3 */
+    [Delete, {Action::Layer(1)}, RBracket, Escape, {Action::Layer(1)}, Tab ], /* 8 */
+    /* --- Right thumbs -------|---------- Left thumbs - */
+    [RAlt,   RCtrl,  PgUp,        Application, LGui,  Home  ], /* 9 */
+    [Enter,  Tab,    PgDown,      Space,    BSpace,   End   ], /* 10 */
+    /* ------------- Right Fingers --------------------------         */
+    [Kb6,    Kb7,    Kb8,         Kb9,      Kb0,      Minus ], /* 11 */
+    [Y,      U,      I,           O,        P,        Bslash], /* 12 */
+    [H,      J,      K,           L,        SColon,   Quote ], /* 13 */
+    [N,      M,      Comma,       Dot,      Slash,    RShift], /* 14 */
+    [__,     Up,     Down,        LBracket, RBracket, RGui  ], /* 15 */
+];
+/// Function keys, and navigation under the right hand, while a layer key is held.
+#[rustfmt::skip]
+#[cfg(feature = "ergodox")]
+pub static LAYOUT_FN: Layout<13, 6> = layout![
+    /*                 Port A                            */
+    /* 0     1       2            3          4         5 */
+    /* -------------- Left Fingers ------------------------- Port B */
+    [F11,    F1,     F2,          F3,       F4,       F5    ], /* 3 */
+    [Delete, Q,      W,           E,        R,        T     ], /* 4 */
+    [BSpace, A,      S,           D,        F,        G     ], /* 5 */
+    [LShift, Z,      X,           C,        V,        B     ], /* 6 */
+    [Grave,  Quote,  LAlt,        Home,     End,      __    ], /* 7 */
+    /* --- Right inner column -|- Left inner column --- */
+    [Delete, {Action::Layer(1)}, RBracket, Escape, {Action::Layer(1)}, Tab ], /* 8 */
+    /* --- Right thumbs -------|---------- Left thumbs - */
+    [RAlt,   RCtrl,  PgUp,        Application, LGui,  Home  ], /* 9 */
+    [Enter,  Tab,    PgDown,      Space,    BSpace,   End   ], /* 10 */
+    /* ------------- Right Fingers --------------------------         */
+    [F6,     F7,     F8,          F9,       F10,      F12   ], /* 11 */
+    [Y,      Home,   Up,          End,      PgUp,     Bslash], /* 12 */
+    [H,      Left,   Down,        Right,    PgDown,   Quote ], /* 13 */
+    [N,      M,      Comma,       Dot,      Slash,    RShift], /* 14 */
+    [__,     PgUp,   PgDown,      LBracket, RBracket, RGui  ], /* 15 */
+];
+
+/*
+ * The Lily58, hand-wired to the same matrix. Each half has 29 keys, all on that
+ * half's five rows; the shared rows are not used. The bottom row of each half
+ * holds the four thumb keys and the extra key beside the inner column.
+ */
+#[rustfmt::skip]
+#[cfg(feature = "lily58")]
+pub static LAYOUT: Layout<13, 6> = layout![
+    /*                 Port A                            */
+    /* 0     1       2            3          4         5 */
+    /* -------------- Left Fingers ------------------------- Port B */
+    [Escape, Kb1,    Kb2,         Kb3,      Kb4,      Kb5   ], /* 3 */
+    [Tab,    Q,      W,           E,        R,        T     ], /* 4 */
+    [LCtrl,  A,      S,           D,        F,        G     ], /* 5 */
+    [LShift, Z,      X,           C,        V,        B     ], /* 6 */
+    /*       ---------------- Thumbs ----------------  inner  */
+    [__,     LAlt,   LGui,        {Action::Layer(1)}, Space, LBracket], /* 7 */
+    [__,     __,     __,          __,       __,       __    ], /* 8 */
+    [__,     __,     __,          __,       __,       __    ], /* 9 */
+    [__,     __,     __,          __,       __,       __    ], /* 10 */
+    /* ------------- Right Fingers --------------------------         */
+    [Kb6,    Kb7,    Kb8,         Kb9,      Kb0,      Grave ], /* 11 */
+    [Y,      U,      I,           O,        P,        Minus ], /* 12 */
+    [H,      J,      K,           L,        SColon,   Quote ], /* 13 */
+    [N,      M,      Comma,       Dot,      Slash,    RShift], /* 14 */
+    /* inner  ---------------- Thumbs ----------------        */
+    [RBracket, Enter, {Action::Layer(2)}, BSpace, RGui, __  ], /* 15 */
+];
+/// Function keys and numbers, while the left layer key is held.
+#[rustfmt::skip]
+#[cfg(feature = "lily58")]
+pub static LAYOUT_LOWER: Layout<13, 6> = layout![
+    /*                 Port A                            */
+    /* 0     1       2            3          4         5 */
+    /* -------------- Left Fingers ------------------------- Port B */
+    [F1,     F2,     F3,          F4,       F5,       F6    ], /* 3 */
+    [Grave,  Kb1,    Kb2,         Kb3,      Kb4,      Kb5   ], /* 4 */
+    [LCtrl,  A,      S,           D,        F,        G     ], /* 5 */
+    [LShift, Z,      X,           C,        V,        B     ], /* 6 */
+    [__,     LAlt,   LGui,        {Action::Layer(1)}, Space, LBracket], /* 7 */
+    [__,     __,     __,          __,       __,       __    ], /* 8 */
+    [__,     __,     __,          __,       __,       __    ], /* 9 */
+    [__,     __,     __,          __,       __,       __    ], /* 10 */
+    /* ------------- Right Fingers --------------------------         */
+    [F7,     F8,     F9,          F10,      F11,      F12   ], /* 11 */
+    [Kb6,    Kb7,    Kb8,         Kb9,      Kb0,      Equal ], /* 12 */
+    [H,      J,      K,           L,        SColon,   Quote ], /* 13 */
+    [N,      M,      Comma,       Dot,      Slash,    RShift], /* 14 */
+    [RBracket, Enter, {Action::Layer(2)}, BSpace, RGui, __  ], /* 15 */
+];
+/// Navigation under the right hand, while the right layer key is held.
+#[rustfmt::skip]
+#[cfg(feature = "lily58")]
+pub static LAYOUT_RAISE: Layout<13, 6> = layout![
+    /*                 Port A                            */
+    /* 0     1       2            3          4         5 */
+    /* -------------- Left Fingers ------------------------- Port B */
+    [Escape, Kb1,    Kb2,         Kb3,      Kb4,      Kb5   ], /* 3 */
+    [Tab,    Q,      W,           E,        R,        T     ], /* 4 */
+    [LCtrl,  A,      S,           D,        F,        G     ], /* 5 */
+    [LShift, Z,      X,           C,        V,        B     ], /* 6 */
+    [__,     LAlt,   LGui,        {Action::Layer(1)}, Space, LBracket], /* 7 */
+    [__,     __,     __,          __,       __,       __    ], /* 8 */
+    [__,     __,     __,          __,       __,       __    ], /* 9 */
+    [__,     __,     __,          __,       __,       __    ], /* 10 */
+    /* ------------- Right Fingers --------------------------         */
+    [Kb6,    Kb7,    Kb8,         Kb9,      Kb0,      Grave ], /* 11 */
+    [Y,      PgUp,   Up,          PgDown,   __,       Minus ], /* 12 */
+    [Home,   Left,   Down,        Right,    End,      Quote ], /* 13 */
+    [N,      M,      Comma,       Dot,      Slash,    RShift], /* 14 */
+    [RBracket, Enter, {Action::Layer(2)}, BSpace, RGui, __  ], /* 15 */
+];
+
+/*
+ * A macropad: the left half of a DMOTE, on its own Blue Pill, used as a
+ * keypad. Only the left fingers and the left thumb cluster are wired, so the
+ * rows of the right half are empty.
+ */
+#[rustfmt::skip]
+#[cfg(feature = "macropad")]
+pub static LAYOUT: Layout<13, 6> = layout![
+    /*                 Port A                            */
+    /* 0     1       2            3          4         5 */
+    /* -------------- Left Fingers ------------------------- Port B */
+    [__,     __,     __,          __,       __,       __    ], /* 3 */
+    [__,     NumLock, Kp7,        Kp8,      Kp9,      KpMinus], /* 4 */
+    [__,     KpSlash, Kp4,        Kp5,      Kp6,      KpPlus], /* 5 */
+    [__,     KpAsterisk, Kp1,     Kp2,      Kp3,      KpEnter], /* 6 */
+    [__,     BSpace, Kp0,         KpDot,    KpEqual,  __    ], /* 7 */
+    /* ------------------------------|---- Left thumbs ----- */
+    [__,     __,     __,          Tab,      Escape,   {Action::Layer(1)}], /* 8 */
+    [__,     __,     __,          Space,    LShift,   LCtrl ], /* 9 */
+    [__,     __,     __,          Home,     End,      Enter ], /* 10 */
+    [__,     __,     __,          __,       __,       __    ], /* 11 */
+    [__,     __,     __,          __,       __,       __    ], /* 12 */
+    [__,     __,     __,          __,       __,       __    ], /* 13 */
+    [__,     __,     __,          __,       __,       __    ], /* 14 */
+    [__,     __,     __,          __,       __,       __    ], /* 15 */
+];
+/// Function keys and navigation, while the layer key is held.
+#[rustfmt::skip]
+#[cfg(feature = "macropad")]
+pub static LAYOUT_FN: Layout<13, 6> = layout![
+    /*                 Port A                            */
+    /* 0     1       2            3          4         5 */
+    /* -------------- Left Fingers ------------------------- Port B */
+    [__,     __,     __,          __,       __,       __    ], /* 3 */
+    [__,     F10,    F7,          F8,       F9,       VolDown], /* 4 */
+    [__,     F11,    F4,          F5,       F6,       VolUp ], /* 5 */
+    [__,     F12,    F1,          F2,       F3,       Mute  ], /* 6 */
+    [__,     Delete, Insert,      PgUp,     PgDown,   __    ], /* 7 */
+    /* ------------------------------|---- Left thumbs ----- */
+    [__,     __,     __,          Tab,      Escape,   {Action::Layer(1)}], /* 8 */
+    [__,     __,     __,          Space,    LShift,   LCtrl ], /* 9 */
+    [__,     __,     __,          Left,     Right,    Enter ], /* 10 */
+    [__,     __,     __,          __,       __,       __    ], /* 11 */
+    [__,     __,     __,          __,       __,       __    ], /* 12 */
+    [__,     __,     __,          __,       __,       __    ], /* 13 */
+    [__,     __,     __,          __,       __,       __    ], /* 14 */
+    [__,     __,     __,          __,       __,       __    ], /* 15 */
+];
+
+/// Number of scans after power-on during which keys that are held are ignored
+/// until they're released; 250ms at a 2kHz scan rate.
+const GRACE_SCANS: u32 = 500;
+
+/// Number of scans between flashes of the LED while USB is suspended; 2s.
+const SUSPEND_BLINK_SCANS: u32 = 2000 * SCAN_KHZ;
+
+/// Number of scans that each flash of the LED lasts while USB is suspended;
+/// 20ms.
+const SUSPEND_FLASH_SCANS: u32 = 20 * SCAN_KHZ;
 
-#[entry]
+/// Frequency of the system clock, in MHz. Some clone MCUs on Blue Pills are
+/// flaky with USB at 72 MHz, but fine at 48, the slowest that USB works at.
+#[cfg(not(feature = "clock-48mhz"))]
+const SYSCLK_MHZ: u32 = 72;
+#[cfg(feature = "clock-48mhz")]
+const SYSCLK_MHZ: u32 = 48;
+
+/// Frequency of the system clock when the crystal doesn't start, the fastest
+/// that the internal oscillator can reach with USB.
+const FALLBACK_SYSCLK_MHZ: u32 = 48;
+
+/// Cycles of the internal oscillator to wait for the crystal to start; 100ms.
+const HSE_TIMEOUT_CYCLES: u32 = 800_000;
+
+/// Frequency of matrix scans, in kHz.
+const SCAN_KHZ: u32 = 2;
+
+/// Convert a time from settings, in units of 10 ms, to a number of scans.
+fn settings_scans(time: u8) -> u32 {
+    time as u32 * 10 * SCAN_KHZ
+}
+
+/// Convert a debounce time from settings, in ms, to a number of scans. Settings
+/// hold at most [`settings::MAX_DEBOUNCE`], which fits the debouncer's count.
+fn debounce_scans(ms: u8) -> u8 {
+    (ms.min(settings::MAX_DEBOUNCE) as u32 * SCAN_KHZ) as u8
+}
+
+/// The keys of the right half, with a bit per row for each column, which are
+/// debounced with `debounce_right` rather than `debounce`.
+#[cfg(feature = "dmote")]
+const RIGHT_HALF: [u16; 6] = [0x1fe0, 0x1fe0, 0x1fe0, 0x1f00, 0x1f00, 0x1f00];
+#[cfg(feature = "dactyl")]
+const RIGHT_HALF: [u16; 6] = [0x1f80; 6];
+#[cfg(feature = "ergodox")]
+const RIGHT_HALF: [u16; 6] = [0x1fe0, 0x1fe0, 0x1fe0, 0x1f00, 0x1f00, 0x1f00];
+#[cfg(feature = "lily58")]
+const RIGHT_HALF: [u16; 6] = [0x1f00; 6];
+#[cfg(feature = "macropad")]
+const RIGHT_HALF: [u16; 6] = [0; 6];
+
+/// Positions of the A and B switches of each rotary encoder, as (row, col), on
+/// the row of PB9, which no key uses; see [`encoder`].
+#[cfg(all(feature = "encoders", feature = "dactyl"))]
+const ENCODERS: [encoder::Positions; 2] = [((6, 0), (6, 1)), ((6, 2), (6, 3))];
+
+/// Keymaps that a profile may select. Each is a list of layers, selected with
+/// the `Layer` actions in the first.
+#[cfg(feature = "dmote")]
+static KEYMAPS: [&[&Layout<13, 6>]; 1] = [&[&LAYOUT, &LAYOUT_ALT]];
+#[cfg(feature = "dactyl")]
+static KEYMAPS: [&[&Layout<13, 6>]; 1] = [&[&LAYOUT]];
+#[cfg(feature = "ergodox")]
+static KEYMAPS: [&[&Layout<13, 6>]; 1] = [&[&LAYOUT, &LAYOUT_FN]];
+#[cfg(feature = "lily58")]
+static KEYMAPS: [&[&Layout<13, 6>]; 1] = [&[&LAYOUT, &LAYOUT_LOWER, &LAYOUT_RAISE]];
+#[cfg(feature = "macropad")]
+static KEYMAPS: [&[&Layout<13, 6>]; 1] = [&[&LAYOUT, &LAYOUT_FN]];
+
+/// Hooks that post-process the report of each layer of each keymap, in the
+/// same order as [`KEYMAPS`]; see [`hook`]. Layers past the end of a keymap's
+/// list have none.
+static HOOKS: [&[Option<Hook>]; 1] = [&[]];
+
+/// Custom actions, by id; see [`custom`].
+static CUSTOM: &[Custom] = &[];
+
+/// Number of layers that `hot-keymap` has room for in RAM.
+#[cfg(feature = "hot-keymap")]
+const HOT_LAYERS: usize = 4;
+
+/// The layers of the first keymap, copied to RAM at startup, so that
+/// `keymap-patch` can overwrite them through the probe while the firmware runs.
+/// With `hot-keymap`, every profile uses these layers.
+#[cfg(feature = "hot-keymap")]
+#[no_mangle]
+static mut HOT_KEYMAP: [Layout<13, 6>; HOT_LAYERS] =
+    [[[Action::KeyCode(key_code::KeyCode::__); 6]; 13]; HOT_LAYERS];
+
+/// With `hot-keymap`, layers that `keymap-patch` has staged to try out in place
+/// of the first layers of `HOT_KEYMAP`; see [`trial`].
+#[cfg(feature = "hot-keymap")]
+#[no_mangle]
+static mut HOT_STAGED: [Layout<13, 6>; HOT_LAYERS] =
+    [[[Action::KeyCode(key_code::KeyCode::__); 6]; 13]; HOT_LAYERS];
+
+/// Number of layers in `HOT_STAGED`, which `keymap-patch` writes once they're
+/// all there, and the main loop clears as it swaps them in.
+#[cfg(feature = "hot-keymap")]
+#[no_mangle]
+static HOT_STAGED_LEN: AtomicU8 = AtomicU8::new(0);
+
+/// Perform the action bound to a key as it's pressed.
+///
+/// Key codes are reported for as long as they're held, by `report`, so only
+/// actions that change the state of the keyboard itself are handled here.
+fn on_press(
+    action: Action,
+    profiles: &mut Profiles,
+    host: &mut Host,
+    warp: &mut Warp,
+    tutor: &mut Tutor,
+    chords: &mut Chords,
+    latch: &mut Latch,
+) {
+    latch.press(action);
+    match action {
+        // The status line is typed by the main loop.
+        Action::KeyCode(_) | Action::Consumer(_) | Action::Layer(_) | Action::Status => (),
+        Action::GamingMode => profiles.toggle(GAMING),
+        Action::Profile(id) => profiles.select(id as usize),
+        Action::Host(os) => host.force(os),
+        Action::Warp(col, row) => warp.press(col, row),
+        Action::Tutor => tutor.toggle(),
+        Action::ChordMode => chords.toggle(),
+        Action::Remap(flags) => profiles.update(|s| s.remap.toggle(flags)),
+        Action::DebounceMode => {
+            DEFERRED.fetch_xor(true, Ordering::Relaxed);
+        }
+        Action::LogMarker(id) => {
+            MARKER_ID.store(id, Ordering::Relaxed);
+            MARKER.store(true, Ordering::Release);
+        }
+        Action::Custom(id) => {
+            let mut ctx = Context {
+                profiles,
+                host,
+                tutor,
+                chords,
+            };
+            custom::call(CUSTOM, id, &mut ctx);
+        }
+    }
+}
+
+/// Undo the action bound to a key as it's released, for the actions that last
+/// for as long as they're held, but aren't reported by `report`.
+fn on_release(action: Action, warp: &mut Warp, latch: &mut Latch) {
+    latch.release(action);
+    if let Action::Warp(..) = action {
+        warp.release();
+    }
+}
+
+/// Write the next keyboard report, if it's due at the given time, in the units
+/// that the pacer counts in, counting the writes that fail, and logging what
+/// each write changes.
+fn write_report(
+    pacer: &mut Pacer,
+    usb_class: &mut UsbClass,
+    now: u32,
+    counters: &mut Counters,
+    report_log: &mut ReportLog,
+) {
+    if let (Some(rep), Some(built)) = (pacer.next(now).cloned(), pacer.built()) {
+        match usb_class.write(rep.as_bytes()) {
+            Ok(0) => (),
+            Ok(_) => {
+                report_log.changes(&rep, pacer.sent(), built);
+                pacer.written(now);
+                #[cfg(feature = "debug-signals")]
+                debug::toggle(debug::Signal::UsbWrite);
+            }
+            Err(()) => counters.usb_errors = counters.usb_errors.wrapping_add(1),
+        }
+    }
+}
+
+/// The scan task, until the scan interrupt takes it for its own.
+static SCAN_TASK: Mutex<RefCell<Option<ScanTask>>> = Mutex::new(RefCell::new(None));
+
+/// Start the crystal oscillator, returning whether it's ready.
+///
+/// `freeze` waits forever for the crystal, which hangs boards with a bad one.
+/// This gives up after [`HSE_TIMEOUT_CYCLES`] and turns it back off instead, so
+/// that the internal oscillator can be used.
+fn start_hse() -> bool {
+    // Safety: the clocks aren't configured yet, so nothing else is using RCC.
+    let rcc = unsafe { &*RCC::ptr() };
+    rcc.cr.modify(|_, w| w.hseon().set_bit());
+    for _ in 0..HSE_TIMEOUT_CYCLES / 1000 {
+        if rcc.cr.read().hserdy().bit_is_set() {
+            return true;
+        }
+        cortex_m::asm::delay(1000);
+    }
+    rcc.cr.modify(|_, w| w.hseon().clear_bit());
+    false
+}
+
+/// Show an error from setup on the status LED until the next reset: it flashes
+/// [`Error::flashes`] times, pauses, and starts over.
+fn fail(led: &mut StatusLed, error: Error, sysclk_hz: u32) -> ! {
+    let ms = sysclk_hz / 1000;
+    loop {
+        for _ in 0..error.flashes() {
+            led.set(true);
+            cortex_m::asm::delay(150 * ms);
+            led.set(false);
+            cortex_m::asm::delay(250 * ms);
+        }
+        cortex_m::asm::delay(1000 * ms);
+    }
+}
+
+/// Requested by DMA1 CH5 as each scan completes; see `scan::dma_key_scan`.
+#[interrupt]
+fn DMA1_CHANNEL5() {
+    static mut TASK: Option<ScanTask> = None;
+    #[cfg(feature = "debug-signals")]
+    debug::set(debug::Signal::Scan, true);
+    if TASK.is_none() {
+        // `main` sets up the task before it unmasks this interrupt, and
+        // doesn't touch it again.
+        *TASK = cortex_m::interrupt::free(|cs| SCAN_TASK.borrow(cs).take());
+    }
+    if let Some(task) = TASK {
+        task.run();
+    }
+    #[cfg(feature = "debug-signals")]
+    debug::set(debug::Signal::Scan, false);
+}
+
+#[cfg_attr(not(test), entry)]
 fn main() -> ! {
-    let device = unsafe { Peripherals::steal() };
+    // If these were already taken, the firmware is broken beyond repair, so we
+    // _should_ panic if this fails
+    let device = Peripherals::take().unwrap();
+    let mut core = cortex_m::Peripherals::take().unwrap();
 
     let mut flash = device.FLASH.constrain();
     let mut rcc = device.RCC.constrain();
-    let mut debouncer: [[QuickDraw<100>; 13]; 6] = [[Default::default(); 13]; 6];
-    let scan_freq = 2.khz();
-
-    let clocks = rcc
-        .cfgr
-        .use_hse(8_u32.mhz())
-        .sysclk(72_u32.mhz())
-        .pclk1(36_u32.mhz())
+    let mut debouncer: [[QuickDraw; 13]; 6] = [[Default::default(); 13]; 6];
+    let scan_freq = SCAN_KHZ.khz();
+
+    // Without the crystal, USB runs off the internal oscillator, which is
+    // less accurate than USB asks for, but most hosts put up with.
+    let (cfgr, sysclk) = if start_hse() {
+        (rcc.cfgr.use_hse(8_u32.mhz()), SYSCLK_MHZ)
+    } else {
+        (rcc.cfgr, FALLBACK_SYSCLK_MHZ)
+    };
+    let clocks = cfgr
+        .sysclk(sysclk.mhz())
+        .pclk1((sysclk / 2).mhz())
         .freeze(&mut flash.acr);
 
+    core.DCB.enable_trace();
+    core.DWT.enable_cycle_counter();
+    let scan_rate = ScanRate::new(clocks.sysclk().0);
+
+    // Keys restored from the snapshot were already held before the reset, so
+    // they're not the stray presses that the grace period is meant to catch.
+    let grace = if snapshot::restore(&mut debouncer) {
+        Grace::new(0)
+    } else {
+        Grace::new(GRACE_SCANS)
+    };
+
     let mut gpioa = device.GPIOA.split(&mut rcc.apb2);
     let mut gpiob = device.GPIOB.split(&mut rcc.apb2);
+    let mut gpioc = device.GPIOC.split(&mut rcc.apb2);
+    let mut led = StatusLed::new(gpioc.pc13.into_push_pull_output(&mut gpioc.crh));
     let mut afio = device.AFIO.constrain(&mut rcc.apb2);
     let (_, pb3, pb4) = afio.mapr.disable_jtag(gpioa.pa15, gpiob.pb3, gpiob.pb4);
 
     // BluePill board has a pull-up resistor on the D+ line.
     // Pull the D+ pin down to send a RESET condition to the USB bus.
     let mut usb_dp = gpioa.pa12.into_push_pull_output(&mut gpioa.crh);
-    // If we can't do this, we can't be a keyboard, so we _should_ panic if this
-    // fails
+    // If we can't do this, we can't be a keyboard, so there's nothing left to
+    // do but say so
     match usb_dp.set_low() {
         Ok(_) => (),
-        Err(_) => panic!(),
+        Err(_) => fail(&mut led, Error::UsbReset, clocks.sysclk().0),
     };
     cortex_m::asm::delay(clocks.sysclk().0 / 100);
 
@@ -196,26 +771,19 @@ fn main() -> ! {
         pin_dp: usb_dp.into_floating_input(&mut gpioa.crh),
     };
 
-    let usb_bus = unsafe {
-        USB_BUS = Some(UsbBus::new(usb));
-        // If we can't do this, we can't be a keyboard, so we _should_ panic if this
-        // fails
-        match USB_BUS.as_ref() {
-            Some(ub) => ub,
-            None => panic!(),
-        }
-    };
-
-    let usb_class = unsafe {
-        USB_CLASS = Some(new_class(usb_bus));
-        match USB_CLASS.as_mut() {
-            Some(uc) => uc,
-            None => panic!(),
-        }
-    };
+    // The classes and the device borrow the bus for as long as the firmware
+    // runs, so it lives in a static. `main` runs once, so this can't fail.
+    let usb_bus: &'static UsbBusAllocator<UsbBusType> =
+        match singleton!(: UsbBusAllocator<UsbBusType> = UsbBus::new(usb)) {
+            Some(bus) => bus,
+            None => fail(&mut led, Error::UsbBus, clocks.sysclk().0),
+        };
+    let mut usb_class = new_class(usb_bus);
+    let mut usb_extra_class = new_extra_class(usb_bus);
 
     // NOTE: These have to be setup, though they are dropped, as without this setup
     // code, it's not possible to read the matrix.
+    #[cfg(not(feature = "split-cols"))]
     let cols = Cols(
         gpioa.pa0.into_push_pull_output(&mut gpioa.crl),
         gpioa.pa1.into_push_pull_output(&mut gpioa.crl),
@@ -224,6 +792,21 @@ fn main() -> ! {
         gpioa.pa4.into_push_pull_output(&mut gpioa.crl),
         gpioa.pa5.into_push_pull_output(&mut gpioa.crl),
     );
+    #[cfg(feature = "split-cols")]
+    let cols = Cols(
+        gpioa.pa0.into_push_pull_output(&mut gpioa.crl),
+        gpioa.pa1.into_push_pull_output(&mut gpioa.crl),
+        gpioa.pa2.into_push_pull_output(&mut gpioa.crl),
+        gpioa.pa3.into_push_pull_output(&mut gpioa.crl),
+        gpiob.pb0.into_push_pull_output(&mut gpiob.crl),
+        gpiob.pb1.into_push_pull_output(&mut gpiob.crl),
+    );
+    #[cfg(feature = "debug-signals")]
+    {
+        gpioa.pa6.into_push_pull_output(&mut gpioa.crl);
+        gpioa.pa7.into_push_pull_output(&mut gpioa.crl);
+        gpioa.pa8.into_push_pull_output(&mut gpioa.crh);
+    }
     #[rustfmt::skip]
     let rows = Rows(
               pb3.into_pull_down_input(&mut gpiob.crl),
@@ -241,38 +824,523 @@ fn main() -> ! {
         gpiob.pb15.into_pull_down_input(&mut gpiob.crh),
     );
 
-    let (dma, scanout) = dma_key_scan(
+    let mut matrix = Matrix { rows, cols };
+
+    let mut storage = Storage::new(flash);
+    let (stored, loaded) = if safe_mode::requested(&mut matrix, clocks.sysclk().0) {
+        (None, Loaded::SafeMode)
+    } else {
+        match storage.load() {
+            Some(stored) => (Some(stored), Loaded::Stored),
+            None => (None, Loaded::Defaults),
+        }
+    };
+    let startup = Startup::new(loaded);
+    let (mut profiles, mut reroutes, mut disabled, mut calibration, mut schedule) =
+        stored.unwrap_or_default();
+    for (atomic, bits) in DISABLED.iter().zip(disabled.0.iter()) {
+        atomic.store(*bits, Ordering::Relaxed);
+    }
+
+    let scanner = dma_key_scan(
         scan_freq,
-        Matrix { rows, cols },
+        matrix,
         device.DMA1,
         device.TIM1,
         &mut rcc.ahb,
         &mut rcc.apb2,
         &clocks,
-    );
+    )
+    .unwrap_or_else(|error| fail(&mut led, error, clocks.sysclk().0));
+    #[cfg(feature = "analog-keys")]
+    let mut sensors = analog::Sensors::start(
+        Adc::adc1(device.ADC1, &mut rcc.apb2, clocks),
+        analog::Pins(
+            gpioa.pa6.into_analog(&mut gpioa.crl),
+            gpioa.pa7.into_analog(&mut gpioa.crl),
+            gpiob.pb0.into_analog(&mut gpiob.crl),
+            gpiob.pb1.into_analog(&mut gpiob.crl),
+        ),
+        &calibration,
+    )
+    .unwrap_or_else(|error| fail(&mut led, error, clocks.sysclk().0));
     let mut usb_dev = new_device(usb_bus);
     let _ = usb_dev.force_reset();
 
-    let log = Log::get();
-    let mut now: u32 = 0;
+    let mut host = Host::default();
+    let mut warp = Warp::default();
+    let mut tutor = Tutor::default();
+    let mut chords = Chords::default();
+    let mut latch = Latch::default();
+    let mut repeat = Repeat::default();
+    let mut rest = Rest::default();
+    let mut status = Status::default();
+    let mut sticky = Sticky::default();
+    let mut wiring = WiringTest::default();
+    // Reports are paced to one per poll, counted in scans, or with
+    // `sof-timebase`, in USB frames.
+    #[cfg(not(feature = "sof-timebase"))]
+    let mut pacer = Pacer::new(SCAN_KHZ * POLL_MS as u32);
+    #[cfg(feature = "sof-timebase")]
+    let mut pacer = Pacer::new(POLL_MS as u32);
+    #[cfg(feature = "sof-timebase")]
+    let mut frames = FrameClock::default();
+    let mut access = Access::new(&scan::held(&debouncer, Mode::Eager), 0);
+    let mut pressed = [0; 6];
+    // Keys held by `Command::Inject`, with a bit per row for each column.
+    let mut injected = [0u16; 6];
+    let mut counters = Counters {
+        resets: snapshot::resets(),
+        ..Counters::default()
+    };
+    let mut clock = Clock::default();
+    // The layer and row of the last `Command::PreviewLayer`.
+    let mut preview = (0, 0);
+    let mut audit = Audit::default();
+    // Number of layers of the first keymap that fit in `HOT_KEYMAP`.
+    #[cfg(feature = "hot-keymap")]
+    let hot_len = KEYMAPS[0].len().min(HOT_LAYERS);
+    // Safety: the probe writes `HOT_KEYMAP` behind the firmware's back, so it's
+    // only ever copied in and out through a pointer, never borrowed.
+    #[cfg(feature = "hot-keymap")]
+    unsafe {
+        let hot = ::core::ptr::addr_of_mut!(HOT_KEYMAP);
+        let mut layers = hot.read_volatile();
+        for (hot, layer) in layers.iter_mut().zip(KEYMAPS[0].iter()) {
+            *hot = **layer;
+        }
+        hot.write_volatile(layers);
+    }
+    // The layers that a keymap on trial replaced, to put back if it isn't kept.
+    #[cfg(feature = "hot-keymap")]
+    let mut hot_backup = [[[Action::KeyCode(key_code::KeyCode::__); 6]; 13]; HOT_LAYERS];
+    #[cfg(feature = "hot-keymap")]
+    let mut trial: Option<Trial> = None;
+
+    DEBOUNCE.store(debounce_scans(profiles.settings().debounce), Ordering::Relaxed);
+    DEBOUNCE_RIGHT.store(debounce_scans(profiles.settings().debounce_right), Ordering::Relaxed);
+    let log = Log::get().unwrap_or_else(|error| fail(&mut led, error, clocks.sysclk().0));
+    // The scan task and this loop share the queue for as long as the firmware
+    // runs, so it lives in a static. `main` runs once, so this can't fail.
+    let scan_queue: &'static mut ScanQueue = match singleton!(: ScanQueue = ScanQueue::new()) {
+        Some(queue) => queue,
+        None => fail(&mut led, Error::Scan, clocks.sysclk().0),
+    };
+    let (producer, mut queue) = scan_queue.split();
+    // Likewise for the changes to the keyboard report, which the scan task
+    // writes into the log, so that it's the log's only writer.
+    #[cfg(feature = "report-log")]
+    let report_log_queue: &'static mut ReportLogQueue =
+        match singleton!(: ReportLogQueue = ReportLogQueue::new()) {
+            Some(queue) => queue,
+            None => fail(&mut led, Error::Log, clocks.sysclk().0),
+        };
+    #[cfg(feature = "report-log")]
+    let (report_log_producer, report_log_consumer) = report_log_queue.split();
+    let mut report_log = ReportLog {
+        #[cfg(feature = "report-log")]
+        queue: report_log_producer,
+    };
+    cortex_m::interrupt::free(|cs| {
+        SCAN_TASK.borrow(cs).replace(Some(ScanTask {
+            scanner,
+            grace,
+            storm: Storm::new(&scan::held(&debouncer, Mode::Eager), 0),
+            debouncer,
+            log,
+            rate: scan_rate,
+            right_half: &RIGHT_HALF,
+            queue: producer,
+            #[cfg(feature = "report-log")]
+            report_log: report_log_consumer,
+            now: 0,
+            freq: scan_freq.into(),
+            idle: false,
+            active: 0,
+            mode: Mode::Eager,
+            sweep: None,
+            settle: [0; 6],
+            missed: 0,
+            #[cfg(feature = "encoders")]
+            encoders: encoder::Encoders::new(ENCODERS),
+        }));
+    });
+    // Safety: the task is in place, and nothing here relies on the interrupt
+    // staying masked.
+    unsafe { cortex_m::peripheral::NVIC::unmask(Interrupt::DMA1_CHANNEL5) };
+
     loop {
-        usb_dev.poll(&mut [usb_class]);
-        let dma_isr = dma.5.isr();
-        if dma_isr.bits() != 0 {
-            let half: usize = if dma_isr.htif4().bits() { 0 } else { 1 };
-            dma.5.ifcr().write(|w| w.cgif5().clear());
-            now = now.wrapping_add(1);
-            let token = scan(&scanout[half], &mut debouncer, log, now);
-            #[cfg(feature = "dmote")]
-            let layout = if debouncer[0][5].is_pressed() {
-                &LAYOUT_ALT
+        usb_dev.poll(&mut [&mut usb_class, &mut usb_extra_class]);
+        // Write the next report as soon as its frame starts, rather than at the
+        // next scan.
+        #[cfg(feature = "sof-timebase")]
+        write_report(
+            &mut pacer,
+            &mut usb_class,
+            frames.now(),
+            &mut counters,
+            &mut report_log,
+        );
+        if let Some(scanned) = queue.dequeue() {
+            let now = scanned.now;
+            host.detect(usb_class.fingerprint().guess());
+            // Whether a command changed what's stored, so that it's saved below.
+            let mut save = false;
+            if let Some(command) = usb_class.device_mut().take_command() {
+                match command {
+                    Command::SelectProfile(id) => profiles.select(id as usize),
+                    Command::WriteProfile(id, settings) => profiles.set(id as usize, settings),
+                    Command::BlockKey(kc, blocked) => tutor.set_blocked(kc, blocked),
+                    Command::DebounceMode(deferred) => DEFERRED.store(deferred, Ordering::Relaxed),
+                    Command::SettleSweep => SWEEP.store(true, Ordering::Relaxed),
+                    Command::Reroute(from, to) => {
+                        // Repairs are rare, so this is stored right away,
+                        // rather than waiting for the profiles to settle.
+                        save = reroutes.set(from, to);
+                    }
+                    Command::DisableKey(row, col, off) => {
+                        disabled.set(row, col, off);
+                        if let Some(bits) = DISABLED.get(col as usize) {
+                            bits.store(disabled.0[col as usize], Ordering::Relaxed);
+                        }
+                        save = true;
+                    }
+                    Command::CalibrateAnalog(start) => {
+                        #[cfg(feature = "analog-keys")]
+                        if start {
+                            sensors.start_recording();
+                        } else if sensors.recording() {
+                            sensors.finish_recording(&mut calibration);
+                            save = true;
+                        }
+                        #[cfg(not(feature = "analog-keys"))]
+                        let _ = start;
+                    }
+                    Command::AnalogPoints(key, actuation, release) => {
+                        calibration.set_points(key, actuation, release);
+                        #[cfg(feature = "analog-keys")]
+                        sensors.calibrate(&calibration);
+                        save = true;
+                    }
+                    Command::PreviewLayer(layer, row) => preview = (layer, row),
+                    Command::SetClock(secs) => clock.set(WeekTime::from_local(secs), now),
+                    Command::Audit(on) => audit.set(on),
+                    Command::ScheduleRule(index, rule) => {
+                        schedule.set(index, rule);
+                        save = true;
+                    }
+                    Command::Inject(row, col, press) => {
+                        if let (Some(bits), 0..=12) = (injected.get_mut(col as usize), row) {
+                            if press {
+                                *bits |= 1 << row;
+                            } else {
+                                *bits &= !(1 << row);
+                            }
+                        }
+                    }
+                }
+            }
+            let time = clock.update(now, SCAN_KHZ * 1000);
+            if let Some(id) = time.and_then(|time| schedule.check(time)) {
+                profiles.select(id as usize);
+            }
+            // This scan uses a copy of the hot layers, so the probe can't
+            // change them under it.
+            #[cfg(feature = "hot-keymap")]
+            let hot = {
+                let staged = HOT_STAGED_LEN.swap(0, Ordering::Acquire) as usize;
+                let hot = ::core::ptr::addr_of_mut!(HOT_KEYMAP);
+                // Safety: as for `HOT_KEYMAP` above, the hot layers are only
+                // copied through pointers, and the probe is done with the
+                // staged ones by the time it sets `HOT_STAGED_LEN`.
+                let mut layers = unsafe { hot.read_volatile() };
+                if staged != 0 {
+                    // A keymap staged during a trial replaces the one on
+                    // trial, but it's still the first one that's put back.
+                    if trial.is_none() {
+                        hot_backup = layers;
+                    }
+                    let count = staged.min(HOT_LAYERS);
+                    let staged = unsafe { ::core::ptr::addr_of!(HOT_STAGED).read_volatile() };
+                    layers[..count].copy_from_slice(&staged[..count]);
+                    unsafe { hot.write_volatile(layers) };
+                    trial = Some(Trial::start(&hot_backup[0], now));
+                } else if trial.as_ref().map_or(false, |trial| trial.expired(now)) {
+                    layers = hot_backup;
+                    unsafe { hot.write_volatile(layers) };
+                    trial = None;
+                }
+                layers
+            };
+            // NOTE: the layout is chosen from the previous scan, so that it may
+            // be used while handling the events of this one.
+            #[cfg(not(feature = "hot-keymap"))]
+            let layers = KEYMAPS
+                .get(profiles.settings().keymap as usize)
+                .copied()
+                .unwrap_or(KEYMAPS[0]);
+            #[cfg(feature = "hot-keymap")]
+            let hot_refs = {
+                let mut refs = [&hot[0]; HOT_LAYERS];
+                for (layer, hot) in refs.iter_mut().zip(hot.iter()) {
+                    *layer = hot;
+                }
+                refs
+            };
+            #[cfg(feature = "hot-keymap")]
+            let layers = &hot_refs[..hot_len];
+            #[cfg(not(feature = "hot-keymap"))]
+            let hooks = HOOKS
+                .get(profiles.settings().keymap as usize)
+                .copied()
+                .unwrap_or(HOOKS[0]);
+            #[cfg(feature = "hot-keymap")]
+            let hooks = HOOKS[0];
+            let base = layers[0];
+            let layer = scan::layer(base, &pressed);
+            let layout = layers.get(layer).copied().unwrap_or(base);
+            let settings = profiles.settings();
+            let follow = settings.follow_layers;
+            let slow = settings_scans(settings.slow_keys);
+            let bounce = settings_scans(settings.bounce_keys);
+            latch.enable(settings.latch_mods);
+            let mut held = reroutes.apply(&scanned.held);
+            for (bits, injected) in held.iter_mut().zip(injected.iter()) {
+                *bits |= injected;
+            }
+            #[cfg(feature = "analog-keys")]
+            {
+                let analog = sensors.update(settings.analog_actuation, settings.analog_rapid);
+                for (bits, analog) in held.iter_mut().zip(analog.iter()) {
+                    *bits |= analog;
+                }
+            }
+            let mut marked = None;
+            pressed = access.filter(&held, now, slow, bounce, |event| {
+                match event {
+                    Event::Press(row, col) => {
+                        let (row, col) = (row as usize, col as usize);
+                        if audit.on() {
+                            let act = action(layout, row, col).copied();
+                            audit.record((row, col), true, layer, act, now);
+                            return;
+                        }
+                        if wiring.press(base, row, col) {
+                            return;
+                        }
+                        rest.press(now);
+                        sticky.press(row, col, layer);
+                        #[cfg(feature = "hot-keymap")]
+                        if trial.as_ref().map_or(false, |trial| trial.confirms(row, col)) {
+                            trial = None;
+                        }
+                        if chords.press(base, row, col) {
+                            return;
+                        }
+                        if let Some(&act) = action(layout, row, col) {
+                            match act {
+                                Action::KeyCode(kc) if !kc.is_modifier() => {
+                                    repeat.press(row, col, now)
+                                }
+                                Action::Status => status.start(&profiles),
+                                Action::LogMarker(id) => marked = Some(id),
+                                _ => (),
+                            }
+                            on_press(
+                                act,
+                                &mut profiles,
+                                &mut host,
+                                &mut warp,
+                                &mut tutor,
+                                &mut chords,
+                                &mut latch,
+                            );
+                        }
+                    }
+                    Event::Release(row, col) => {
+                        let (row, col) = (row as usize, col as usize);
+                        if audit.on() {
+                            let act = action(layout, row, col).copied();
+                            audit.record((row, col), false, layer, act, now);
+                            return;
+                        }
+                        if let Some((pin, col)) = wiring.release(base, row, col) {
+                            // If too many keys are released at once, the line
+                            // is cut short.
+                            let _ = write!(status, "r{:02}c{:02} ", pin, col);
+                            return;
+                        }
+                        if wiring.active() {
+                            return;
+                        }
+                        repeat.release(row, col);
+                        if chords.release(base, row, col) {
+                            return;
+                        }
+                        let held_in = sticky.layout(layers, layer, follow, row, col);
+                        if let Some(&act) = action(held_in, row, col) {
+                            on_release(act, &mut warp, &mut latch);
+                        }
+                    }
+                }
+            });
+            DEBOUNCE.store(debounce_scans(profiles.settings().debounce), Ordering::Relaxed);
+            DEBOUNCE_RIGHT.store(
+                debounce_scans(profiles.settings().debounce_right),
+                Ordering::Relaxed,
+            );
+            let configured = usb_dev.state() == UsbDeviceState::Configured;
+            if let Some(on) = startup.led(now / SCAN_KHZ, configured) {
+                led.set(on);
+            } else if usb_dev.state() == UsbDeviceState::Suspend {
+                // Flash briefly, at a duty low enough for the suspend current
+                // limit, to tell a sleeping host from a dead keyboard.
+                led.set(now % SUSPEND_BLINK_SCANS < SUSPEND_FLASH_SCANS);
+            } else if scanned.throttled {
+                // Blink at 5 Hz, to warn that presses are being dropped.
+                led.set(now / 200 % 2 == 0);
+            } else if let Some(on) = rest.led(now, settings.break_minutes) {
+                led.set(on);
+            } else {
+                led.set(profiles.settings().led);
+            }
+            // Profiles are only stored once they've settled, but everything
+            // else as soon as it changes.
+            let commit = profiles.take_commit(now);
+            if save || commit {
+                // If this fails, what changed will be back as it was after the
+                // next reset, which is the best we can do.
+                let _ = storage.save(&profiles, &reroutes, &disabled, &calibration, &schedule);
+            }
+            usb_class
+                .device_mut()
+                .set_active_profile(profiles.active_id() as u8);
+            usb_class.device_mut().set_host_os(host.os());
+            usb_class.device_mut().set_scan_rate(&scanned.rate);
+            usb_class
+                .device_mut()
+                .set_deferred(DEFERRED.load(Ordering::Relaxed));
+            usb_class.device_mut().set_settle(&scanned.settle);
+            usb_class.device_mut().set_disabled(&disabled);
+            counters.missed_scans = scanned.missed;
+            counters.throttles = scanned.throttles;
+            usb_class.device_mut().set_counters(&counters);
+            usb_class.device_mut().set_clock(time);
+            usb_class.device_mut().set_audit(&audit);
+            #[cfg(feature = "analog-keys")]
+            usb_class
+                .device_mut()
+                .set_analog(&sensors.readings(), sensors.recording());
+            let (preview_layer, preview_row) = preview;
+            let actions = scan::preview(
+                layers,
+                preview_layer as usize,
+                preview_row as usize,
+                settings.remap,
+            );
+            usb_class
+                .device_mut()
+                .set_preview(preview_layer, preview_row, &actions);
+            if let Some(id) = marked {
+                usb_class.device_mut().mark(id, now);
+            }
+            let repeated = repeat.filter(
+                &pressed,
+                now,
+                settings_scans(settings.repeat_delay),
+                settings_scans(settings.repeat_interval),
+            );
+            // Held keys do what they did in the layer they were pressed in,
+            // unless they follow the layer.
+            let resolved = sticky.resolve(layers, layer, follow);
+            let mut rep = report(
+                &resolved,
+                &chords.filter(base, &repeated),
+                settings.remap,
+                &tutor,
+                scanned.token,
+            );
+            if let Some(hook) = hook::find(hooks, layer) {
+                hook(&mut rep);
+            }
+            if wiring.active() {
+                // Only the positions typed by the status line are reported.
+                rep = Default::default();
+            }
+            if let Some(kc) = chords.tap() {
+                rep.pressed(kc);
+            }
+            if let Some(kc) = status.tap() {
+                rep.pressed(kc);
+            }
+            for kc in latch.modifiers().filter_map(|kc| settings.remap.apply(kc)) {
+                rep.pressed(kc);
+            }
+            if audit.on() {
+                rep = Default::default();
+            }
+            pacer.push(rep, now, settings.modifiers_first);
+            #[cfg(not(feature = "sof-timebase"))]
+            write_report(
+                &mut pacer,
+                &mut usb_class,
+                now,
+                &mut counters,
+                &mut report_log,
+            );
+            usb_extra_class.device_mut().set_pointer(warp.position());
+            let consumer = if audit.on() || wiring.active() {
+                0
             } else {
-                &LAYOUT
+                scan::consumer(&resolved, &pressed, host.media_as_consumer(settings.macos))
             };
-            #[cfg(feature = "dactyl")]
-            let layout = &LAYOUT;
-            let rep = report(layout, &debouncer, token);
-            let _ = usb_class.write(rep.as_bytes());
+            usb_extra_class.device_mut().set_consumer(consumer);
+            if let Some(id) = usb_extra_class.device_mut().pending() {
+                if usb_extra_class.write_report(id).map_or(false, |len| len > 0) {
+                    usb_extra_class.device_mut().sent(id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "dmote"))]
+mod tests {
+    use super::*;
+    use key_code::KeyCode::{self, A, B};
+    use thumbs::{Cluster, Thumbs};
+
+    /// A cluster with every key set to `kc`.
+    fn cluster(kc: KeyCode) -> Cluster {
+        let key = Action::KeyCode(kc);
+        Cluster {
+            left: [key; 2],
+            middle: [key; 3],
+            right: [key; 2],
+        }
+    }
+
+    #[test]
+    fn thumb_clusters_are_debounced_with_their_half() {
+        let none = Action::KeyCode(KeyCode::__);
+        let layout = thumbs::place(
+            [[none; 6]; 13],
+            Thumbs {
+                right_thumb: cluster(A),
+                left_thumb: cluster(B),
+                middle: [none; 2],
+                face: [none; 2],
+            },
+        );
+        for (row, actions) in layout.iter().enumerate() {
+            for (col, act) in actions.iter().enumerate() {
+                let right = RIGHT_HALF[col] & 1 << row != 0;
+                match act {
+                    Action::KeyCode(A) => assert!(right, "right thumb at {}, {}", row, col),
+                    Action::KeyCode(B) => assert!(!right, "left thumb at {}, {}", row, col),
+                    _ => (),
+                }
+            }
         }
     }
 }
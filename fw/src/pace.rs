@@ -0,0 +1,242 @@
+//! Sending keyboard reports at most once per USB frame.
+//!
+//! The main loop builds a keyboard report every scan, which is more often than
+//! the host polls for one, once per 1 ms frame. Writing a report while the last
+//! one is still waiting for the host fails, and the next scan's report takes
+//! its place, so a key pressed and released between two polls could be lost.
+//! [`Pacer`] queues the reports instead, merging each into the one queued before
+//! it only when that doesn't undo a change, and hands them out one per frame.
+
+use heapless::Vec;
+
+use crate::key_code::KbHidReport;
+
+/// Number of reports that may wait to be sent.
+const QUEUE_LEN: usize = 8;
+
+/// The report to queue ahead of `report`, if it follows `previous`; see
+/// [`Pacer::push`].
+fn ahead(
+    report: &KbHidReport,
+    previous: &KbHidReport,
+    modifiers_first: bool,
+) -> Option<KbHidReport> {
+    if !modifiers_first {
+        return None;
+    }
+    report
+        .modifiers_first(previous)
+        .or_else(|| report.keys_released_first(previous))
+}
+
+/// A report waiting to be sent.
+struct Queued {
+    report: KbHidReport,
+    /// The scan that the report was built in
+    built: u32,
+}
+
+/// The keyboard reports waiting to be sent.
+pub struct Pacer {
+    /// The last report sent to the host
+    sent: KbHidReport,
+    /// When the last report was sent, if one has been
+    sent_at: Option<u32>,
+    /// Reports waiting to be sent, oldest first
+    queue: Vec<Queued, QUEUE_LEN>,
+    /// Number of scans per USB frame
+    frame: u32,
+}
+
+impl Pacer {
+    /// Pace reports to one per `frame` scans.
+    pub fn new(frame: u32) -> Self {
+        Self {
+            sent: KbHidReport::default(),
+            sent_at: None,
+            queue: Vec::new(),
+            frame,
+        }
+    }
+
+    /// Queue the report built in the scan at `built`.
+    ///
+    /// With `modifiers_first`, a report that adds modifiers as well as key
+    /// codes is queued after one with only its modifiers added, and a report
+    /// that releases both after one with only its key codes released; see
+    /// [`KbHidReport::modifiers_first`] and
+    /// [`KbHidReport::keys_released_first`].
+    pub fn push(&mut self, report: KbHidReport, built: u32, modifiers_first: bool) {
+        let newest = self
+            .queue
+            .last()
+            .map_or(&self.sent, |queued| &queued.report);
+        if report == *newest {
+            return;
+        }
+        match ahead(&report, newest, modifiers_first) {
+            // This report mustn't be merged into the one that goes ahead of it.
+            Some(first) => {
+                self.merge(first, built, modifiers_first);
+                self.append(report, built);
+            }
+            None => self.merge(report, built, modifiers_first),
+        }
+    }
+
+    /// Queue a report, in place of the newest one if that loses no change.
+    fn merge(&mut self, report: KbHidReport, built: u32, modifiers_first: bool) {
+        let len = self.queue.len();
+        if let Some(Queued { report: newest, .. }) = self.queue.last() {
+            let before = if len > 1 {
+                &self.queue[len - 2].report
+            } else {
+                &self.sent
+            };
+            // The newest report may be replaced only if this one keeps every
+            // key that it pressed or released, so that no tap is lost, and
+            // wouldn't have needed a report ahead of it after the one before.
+            if report.keeps_changes(before, newest)
+                && ahead(&report, before, modifiers_first).is_none()
+            {
+                self.queue[len - 1] = Queued { report, built };
+                return;
+            }
+        }
+        self.append(report, built);
+    }
+
+    /// Queue a report after the newest one.
+    fn append(&mut self, report: KbHidReport, built: u32) {
+        let len = self.queue.len();
+        if let Err(queued) = self.queue.push(Queued { report, built }) {
+            // The host has stopped polling, so what it misses hardly matters,
+            // but it should see the latest keys once it's back.
+            self.queue[len - 1] = queued;
+        }
+    }
+
+    /// The last report sent to the host.
+    pub fn sent(&self) -> &KbHidReport {
+        &self.sent
+    }
+
+    /// The report to write in this scan, if any.
+    pub fn next(&self, now: u32) -> Option<&KbHidReport> {
+        let due = self
+            .sent_at
+            .map_or(true, |at| now.wrapping_sub(at) >= self.frame);
+        self.queue
+            .first()
+            .filter(|_| due)
+            .map(|queued| &queued.report)
+    }
+
+    /// The scan that the report from [`Pacer::next`] was built in.
+    pub fn built(&self) -> Option<u32> {
+        self.queue.first().map(|queued| queued.built)
+    }
+
+    /// Note that the report from [`Pacer::next`] was written.
+    pub fn written(&mut self, now: u32) {
+        if self.queue.is_empty() {
+            return;
+        }
+        self.queue.rotate_left(1);
+        if let Some(Queued { report, .. }) = self.queue.pop() {
+            self.sent = report;
+            self.sent_at = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_code::{report, KeyCode::*};
+
+    /// Send every queued report, one per frame from `now`, in order.
+    fn drain(pacer: &mut Pacer, now: &mut u32) -> std::vec::Vec<KbHidReport> {
+        let mut sent = std::vec::Vec::new();
+        while let Some(report) = pacer.next(*now).cloned() {
+            pacer.written(*now);
+            sent.push(report);
+            *now += 1;
+        }
+        sent
+    }
+
+    #[test]
+    fn releases_keys_before_modifiers() {
+        let (mut pacer, mut now) = (Pacer::new(1), 0);
+        pacer.push(report(&[LCtrl, C]), now, true);
+        assert_eq!(
+            drain(&mut pacer, &mut now),
+            [report(&[LCtrl]), report(&[LCtrl, C])]
+        );
+        pacer.push(report(&[]), now, true);
+        assert_eq!(drain(&mut pacer, &mut now), [report(&[LCtrl]), report(&[])]);
+    }
+
+    #[test]
+    fn releases_together_without_modifiers_first() {
+        let (mut pacer, mut now) = (Pacer::new(1), 0);
+        pacer.push(report(&[LCtrl, C]), now, false);
+        assert_eq!(drain(&mut pacer, &mut now), [report(&[LCtrl, C])]);
+        pacer.push(report(&[]), now, false);
+        assert_eq!(drain(&mut pacer, &mut now), [report(&[])]);
+    }
+
+    #[test]
+    fn keeps_release_order_when_appending() {
+        let (mut pacer, mut now) = (Pacer::new(1), 0);
+        pacer.push(report(&[LCtrl, C]), now, true);
+        pacer.push(report(&[]), now, true);
+        assert_eq!(
+            drain(&mut pacer, &mut now),
+            [
+                report(&[LCtrl]),
+                report(&[LCtrl, C]),
+                report(&[LCtrl]),
+                report(&[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_release_order_when_merging() {
+        let (mut pacer, mut now) = (Pacer::new(1), 0);
+        pacer.push(report(&[LCtrl, C]), now, false);
+        drain(&mut pacer, &mut now);
+        // C is released, then Ctrl, before the first release is sent.
+        pacer.push(report(&[LCtrl]), now, true);
+        pacer.push(report(&[]), now, true);
+        assert_eq!(drain(&mut pacer, &mut now), [report(&[LCtrl]), report(&[])]);
+    }
+
+    #[test]
+    fn merges_a_release_that_keeps_the_order() {
+        let (mut pacer, mut now) = (Pacer::new(1), 0);
+        pacer.push(report(&[LCtrl, LAlt, Delete]), now, false);
+        drain(&mut pacer, &mut now);
+        // Alt and Ctrl are released, one after the other, after Delete.
+        pacer.push(report(&[LCtrl, LAlt]), now, true);
+        pacer.push(report(&[LCtrl]), now, true);
+        pacer.push(report(&[]), now, true);
+        assert_eq!(
+            drain(&mut pacer, &mut now),
+            [report(&[LCtrl, LAlt]), report(&[])]
+        );
+    }
+
+    #[test]
+    fn reports_keep_the_scan_they_were_built_in() {
+        let mut pacer = Pacer::new(1);
+        pacer.push(report(&[A]), 3, false);
+        // B is pressed before A's report is sent, and merged into it.
+        pacer.push(report(&[A, B]), 4, false);
+        assert_eq!(pacer.built(), Some(4));
+        pacer.written(5);
+        assert_eq!(pacer.built(), None);
+    }
+}
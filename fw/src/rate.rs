@@ -0,0 +1,53 @@
+//! Measuring the interval between scans, as achieved rather than configured.
+//!
+//! The interval is measured with the cycle counter in the scan interrupt, so it
+//! includes any jitter in when the interrupt runs, and a scan that the interrupt
+//! misses shows up as an interval twice as long as it should be. A broken clock
+//! configuration, or timer math, shows up as every interval being off.
+//!
+//! Only scans at the usual rate are measured, not those while idle; see
+//! [`crate::tick`].
+
+use cortex_m::peripheral::DWT;
+
+/// The current, shortest and longest interval between scans, in microseconds.
+#[derive(Clone, Copy)]
+pub struct ScanRate {
+    /// Cycles of the system clock per microsecond
+    cycles_per_us: u32,
+    /// The cycle count at the last scan, if there's been one
+    last: Option<u32>,
+    pub current: u16,
+    pub min: u16,
+    pub max: u16,
+}
+
+impl ScanRate {
+    /// Start measuring. The cycle counter must already be enabled.
+    pub fn new(sysclk_hz: u32) -> Self {
+        Self {
+            cycles_per_us: sysclk_hz / 1_000_000,
+            last: None,
+            current: 0,
+            min: u16::MAX,
+            max: 0,
+        }
+    }
+
+    /// Note that a scan has been read.
+    pub fn tick(&mut self) {
+        let now = DWT::get_cycle_count();
+        if let Some(last) = self.last {
+            let us = now.wrapping_sub(last) / self.cycles_per_us;
+            self.current = us.min(u16::MAX as u32) as u16;
+            self.min = self.min.min(self.current);
+            self.max = self.max.max(self.current);
+        }
+        self.last = Some(now);
+    }
+
+    /// Forget the last scan, so that the interval from it isn't measured.
+    pub fn restart(&mut self) {
+        self.last = None;
+    }
+}
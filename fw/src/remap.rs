@@ -0,0 +1,47 @@
+//! Remapping of modifiers, applied as the report is built.
+//!
+//! This covers the common reasons to change a keymap, such as swapping Caps
+//! Lock and Control, without storing a whole keymap per profile.
+
+use crate::key_code::KeyCode::{self, *};
+
+/// A set of remappings, one per bit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Remap(pub u8);
+
+impl Remap {
+    /// Swap Caps Lock and left Control.
+    pub const SWAP_CTRL_CAPS: u8 = 1 << 0;
+    /// Report neither GUI key, so they can't switch away from a game.
+    pub const NO_GUI: u8 = 1 << 1;
+    /// Swap Alt and GUI on both sides, for hosts that expect Command next to
+    /// the space bar.
+    pub const SWAP_ALT_GUI: u8 = 1 << 2;
+
+    /// Turn each of the given remappings on if it was off, or off if it was on.
+    pub fn toggle(&mut self, flags: u8) {
+        self.0 ^= flags;
+    }
+
+    fn has(&self, flag: u8) -> bool {
+        self.0 & flag != 0
+    }
+
+    /// The key code to report in place of `kc`, or `None` if it shouldn't be
+    /// reported at all.
+    pub fn apply(&self, kc: KeyCode) -> Option<KeyCode> {
+        let kc = match kc {
+            CapsLock if self.has(Self::SWAP_CTRL_CAPS) => LCtrl,
+            LCtrl if self.has(Self::SWAP_CTRL_CAPS) => CapsLock,
+            LAlt if self.has(Self::SWAP_ALT_GUI) => LGui,
+            RAlt if self.has(Self::SWAP_ALT_GUI) => RGui,
+            LGui if self.has(Self::SWAP_ALT_GUI) => LAlt,
+            RGui if self.has(Self::SWAP_ALT_GUI) => RAlt,
+            kc => kc,
+        };
+        match kc {
+            LGui | RGui if self.has(Self::NO_GUI) => None,
+            kc => Some(kc),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Typematic repeat, for hosts that don't repeat held keys themselves, such as
+//! BIOS setup screens and some KVMs.
+//!
+//! The key pressed last repeats while it's held: after a delay, it's left out
+//! of the report for the first half of every interval, so that the host sees
+//! it released and pressed again. Modifiers, and keys that aren't key codes,
+//! don't repeat. Repeat is off when its delay is 0.
+
+/// The key that repeats.
+#[derive(Default)]
+pub struct Repeat {
+    /// The row and column of the key, and when it was pressed
+    key: Option<(usize, usize, u32)>,
+}
+
+impl Repeat {
+    /// Repeat the key at the given row and column, which was just pressed,
+    /// rather than any other.
+    pub fn press(&mut self, row: usize, col: usize, now: u32) {
+        self.key = Some((row, col, now));
+    }
+
+    /// Stop repeating the key at the given row and column, if it repeats.
+    pub fn release(&mut self, row: usize, col: usize) {
+        if let Some((r, c, _)) = self.key {
+            if (r, c) == (row, col) {
+                self.key = None;
+            }
+        }
+    }
+
+    /// Filter the pressed keys, with a bit per row for each column, leaving
+    /// out the key that repeats while it's between repeats.
+    ///
+    /// `delay` is the number of scans a key must be held before it repeats,
+    /// and `interval` the number of scans between repeats.
+    pub fn filter<const C: usize>(
+        &self,
+        pressed: &[u16; C],
+        now: u32,
+        delay: u32,
+        interval: u32,
+    ) -> [u16; C] {
+        let mut pressed = *pressed;
+        if let Some((row, col, since)) = self.key {
+            let held = now.wrapping_sub(since);
+            let interval = interval.max(2);
+            if delay != 0 && held >= delay && (held - delay) % interval < interval / 2 {
+                if let Some(bits) = pressed.get_mut(col) {
+                    *bits &= !(1 << row);
+                }
+            }
+        }
+        pressed
+    }
+}
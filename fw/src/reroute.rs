@@ -0,0 +1,105 @@
+//! Rerouting keys from one position in the matrix to another.
+//!
+//! When a trace breaks, the switch is often bodged to a spare intersection of
+//! the matrix instead. Rerouting that spare position to the key's original one
+//! lets the switch work as it did, without changing the layouts. Reroutes are
+//! set by the host, and stored in flash with the profiles.
+
+/// Maximum number of reroutes.
+pub const MAX: usize = 4;
+
+/// Marks an unused reroute when converted to bytes.
+const UNUSED: u8 = 0xff;
+
+/// A key wired to the position `from` acts as the key at `to`. Positions are
+/// (row, col).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reroute {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+}
+
+/// Every reroute in use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Reroutes([Option<Reroute>; MAX]);
+
+impl Reroutes {
+    /// Size of `Reroutes` when converted to bytes.
+    pub const SIZE: usize = MAX * 4;
+
+    /// Reroute the key at `from` to `to`, replacing any reroute of `from`, or,
+    /// if `from` and `to` are the same, stop rerouting it.
+    ///
+    /// Returns false if there's no room for another reroute.
+    pub fn set(&mut self, from: (u8, u8), to: (u8, u8)) -> bool {
+        for reroute in self.0.iter_mut() {
+            if reroute.map_or(false, |r| r.from == from) {
+                *reroute = None;
+            }
+        }
+        if from == to {
+            return true;
+        }
+        match self.0.iter_mut().find(|r| r.is_none()) {
+            Some(unused) => {
+                *unused = Some(Reroute { from, to });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move the keys held at each rerouted position to where they're rerouted
+    /// to. `held` has a bit per row for each column.
+    pub fn apply<const C: usize>(&self, held: &[u16; C]) -> [u16; C] {
+        let mut rerouted = *held;
+        for reroute in self.0.iter().flatten() {
+            let (from_row, from_col) = reroute.from;
+            let (to_row, to_col) = reroute.to;
+            let pressed = held
+                .get(from_col as usize)
+                .map_or(false, |bits| from_row < 16 && bits & (1 << from_row) != 0);
+            if let Some(bits) = rerouted.get_mut(from_col as usize) {
+                if from_row < 16 {
+                    *bits &= !(1 << from_row);
+                }
+            }
+            if let (true, Some(bits), true) =
+                (pressed, rerouted.get_mut(to_col as usize), to_row < 16)
+            {
+                *bits |= 1 << to_row;
+            }
+        }
+        rerouted
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [UNUSED; Self::SIZE];
+        for (chunk, reroute) in bytes.chunks_exact_mut(4).zip(self.0.iter()) {
+            if let Some(r) = reroute {
+                chunk.copy_from_slice(&[r.from.0, r.from.1, r.to.0, r.to.1]);
+            }
+        }
+        bytes
+    }
+
+    /// Parse reroutes produced by `to_bytes`, returning `None` if they're
+    /// malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reroutes = Self::default();
+        if bytes.len() < Self::SIZE {
+            return None;
+        }
+        for (chunk, reroute) in bytes.chunks_exact(4).zip(reroutes.0.iter_mut()) {
+            *reroute = match chunk {
+                [UNUSED, ..] => None,
+                [from_row, from_col, to_row, to_col] => Some(Reroute {
+                    from: (*from_row, *from_col),
+                    to: (*to_row, *to_col),
+                }),
+                _ => return None,
+            };
+        }
+        Some(reroutes)
+    }
+}
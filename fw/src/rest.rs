@@ -0,0 +1,53 @@
+//! A reminder to take a break from typing.
+//!
+//! Typing time counts from the first press after a rest, through pauses
+//! shorter than a rest, until no key has been pressed for [`REST_SCANS`]. Once
+//! it passes `break_minutes` in the settings, the status LED pulses until the
+//! typist has rested, which starts the count over. Nothing is sent to the host,
+//! so the reminder can't get in the way of what's being typed.
+
+/// Number of scans in a minute.
+const MINUTE_SCANS: u32 = 60 * 2000;
+
+/// Number of scans without a press that count as a rest; 5 minutes.
+const REST_SCANS: u32 = 5 * MINUTE_SCANS;
+
+/// Number of scans between pulses of the LED; 1 second.
+const PULSE_PERIOD_SCANS: u32 = 2000;
+
+/// Number of scans that each pulse of the LED lasts; 100 ms.
+const PULSE_SCANS: u32 = 200;
+
+/// Tracks how long the typist has been typing for.
+#[derive(Default)]
+pub struct Rest {
+    /// When typing started, if it has since the last rest
+    started: Option<u32>,
+    /// When a key was last pressed
+    pressed: u32,
+}
+
+impl Rest {
+    /// Note that a key was pressed.
+    pub fn press(&mut self, now: u32) {
+        if self.rested(now) {
+            self.started = Some(now);
+        }
+        self.pressed = now;
+    }
+
+    fn rested(&self, now: u32) -> bool {
+        self.started.is_none() || now.wrapping_sub(self.pressed) >= REST_SCANS
+    }
+
+    /// Whether the LED should be lit to remind the typist to take a break,
+    /// after `minutes` of typing, or `None` if no break is due.
+    pub fn led(&self, now: u32, minutes: u8) -> Option<bool> {
+        let started = self.started?;
+        let due = minutes as u32 * MINUTE_SCANS;
+        if minutes == 0 || self.rested(now) || now.wrapping_sub(started) < due {
+            return None;
+        }
+        Some(now % PULSE_PERIOD_SCANS < PULSE_SCANS)
+    }
+}
@@ -0,0 +1,29 @@
+//! Booting without the settings stored in flash.
+//!
+//! If the key wired to column PA2 and row PB4 is held as the keyboard powers
+//! on, the stored profiles, reroutes, disabled keys and calibration are left
+//! unread, and the built in defaults are used in their place. That's a way back to a
+//! working keyboard after a write that leaves it unusable, such as a profile
+//! that debounces every press away. Nothing is written to flash until a
+//! setting is changed, which then replaces what was stored.
+//!
+//! The key is W in the built in layouts, or 7 on the keypad of the macropad.
+
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+use crate::scan::Matrix;
+
+/// Microseconds that the column is driven before the row is read, for it to
+/// charge through the switch and diode.
+const SETTLE_US: u32 = 10;
+
+/// Is the key held? This must be called before the matrix is handed to
+/// `scan::dma_key_scan`, which drives the columns from then on.
+pub fn requested(matrix: &mut Matrix, sysclk_hz: u32) -> bool {
+    // NOTE: setting and reading a GPIO are infallible
+    let _ = matrix.cols.2.set_high();
+    cortex_m::asm::delay(sysclk_hz / 1_000_000 * SETTLE_US);
+    let held = matrix.rows.1.is_high().unwrap_or(false);
+    let _ = matrix.cols.2.set_low();
+    held
+}
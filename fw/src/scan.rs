@@ -1,8 +1,12 @@
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use cortex_m::singleton;
 use stm32f1::stm32f103;
+#[cfg(not(feature = "split-cols"))]
+use stm32f1xx_hal::gpio::gpioa::{PA4, PA5};
+#[cfg(feature = "split-cols")]
+use stm32f1xx_hal::gpio::gpiob::{PB0, PB1};
 use stm32f1xx_hal::gpio::{
-    gpioa::{PA0, PA1, PA2, PA3, PA4, PA5},
+    gpioa::{PA0, PA1, PA2, PA3},
     gpiob::{PB10, PB11, PB12, PB13, PB14, PB15, PB3, PB4, PB5, PB6, PB7, PB8, PB9},
     Input, Output, PullDown, PushPull,
 };
@@ -13,8 +17,12 @@ use stm32f1xx_hal::{dma, pac};
 
 use shared_types::{DebState, KeyState, PressRelease};
 
-use crate::key_code::{keycode, KbHidReport, Layout};
-use crate::trigger::QuickDraw;
+use crate::action::{action, Action, Layout};
+use crate::error::Error;
+use crate::key_code::KbHidReport;
+use crate::remap::Remap;
+use crate::trigger::{Mode, QuickDraw};
+use crate::tutor::Tutor;
 
 /// Compute the Auto Reload Register and Prescaller Register values for a timer
 #[inline(always)]
@@ -35,6 +43,10 @@ fn compute_arr_presc(freq: u32, clock: u32) -> (u16, u16) {
 /// PA3 | Middle Home col & Thumb col -1    | Ring Home col
 /// PA4 | Pointer Home col & Thumb Home col | Pinky Home col
 /// PA5 | Pointer col + 1 & Thumb col + 1   | Pinky col - 1
+///
+/// With the `split-cols` feature, the last two columns are wired to PB0 and PB1
+/// in place of PA4 and PA5, for builds that need those pins for something else.
+#[cfg(not(feature = "split-cols"))]
 pub struct Cols(
     pub PA0<Output<PushPull>>,
     pub PA1<Output<PushPull>>,
@@ -43,6 +55,15 @@ pub struct Cols(
     pub PA4<Output<PushPull>>,
     pub PA5<Output<PushPull>>,
 );
+#[cfg(feature = "split-cols")]
+pub struct Cols(
+    pub PA0<Output<PushPull>>,
+    pub PA1<Output<PushPull>>,
+    pub PA2<Output<PushPull>>,
+    pub PA3<Output<PushPull>>,
+    pub PB0<Output<PushPull>>,
+    pub PB1<Output<PushPull>>,
+);
 
 /// Rows of the keyboard matrix
 ///
@@ -114,7 +135,8 @@ pub struct Matrix {
  *
  * Since it's a bad idea to attempt to read a scan out when it's being written, the
  * DMA is given 2 buffers for scanning out. This allows user code to treat the scan
- * as if it were a double-buffered peripheral.
+ * as if it were a double-buffered peripheral. The returned [`MatrixScanner`] takes
+ * care of reading the right buffer, and of reading it as memory written by hardware.
  *
  * # Interrupts
  *
@@ -126,14 +148,49 @@ pub struct Matrix {
  * is set, use buffer 0, and when it's clear, indicating that the interrupt was
  * generated with the DMA transfer complete interrupt, buffer 1 should be used.
  *
- * # Panics
+ * # Split columns
+ *
+ * With the `split-cols` feature, the columns span two ports, so a single DMA
+ * channel writing to a single BSRR can't strobe them. Instead, output compare 3
+ * and 4 of Timer 1 are both set to the same point of the period, and each
+ * requests a DMA channel that writes the strobe for one port:
+ *
+ * ```text
+ * Request  | DMA1 channel | Writes to  | Columns
+ * ---------|--------------|------------|------------
+ * TIM1_CH4 | 4            | GPIOA BSRR | PA0..=PA3
+ * TIM1_CH3 | 6            | GPIOB BSRR | PB0, PB1
+ * TIM1_UP  | 5            | (reads GPIOB IDR)
+ * ```
+ *
+ * The two writes happen a few bus cycles apart, which is insignificant next to
+ * the settling time before the rows are read. As the rows are on PB3..=PB15,
+ * the column pins on PB0 and PB1 share the row read without disturbing it.
+ *
+ * # Deglitching
+ *
+ * With the `deglitch` feature, output compare 2 of Timer 1 is set to 4/5 of
+ * the period, and requests DMA1 channel 3 to read the rows into a second
+ * buffer, shaped like the first. Both reads happen while the same column is
+ * strobed, 2/5 of a period apart, and [`MatrixScanner::read`] only reports a
+ * key as pressed when both reads saw it pressed. A glitch that's shorter than
+ * the time between the reads is dropped before it reaches the debouncer, so
+ * the debounce timeout need not be raised to cover it.
  *
- * This function is intended as initialization, and so will panic if called more than
- * once. However, as this takes ownership of the DMA1 and TIM1 structs without returning
- * them, it should not be possible to call this more than once.
+ * ```text
+ * Request  | DMA1 channel | At         | Reads
+ * ---------|--------------|------------|-------------------------
+ * TIM1_CH2 | 3            | 4/5 period | GPIOB IDR, early buffer
+ * TIM1_UP  | 5            | end        | GPIOB IDR, buffer
+ * ```
+ *
+ * # Errors
+ *
+ * This function is intended as initialization, and so returns [`Error::Scan`] if called
+ * more than once. However, as this takes ownership of the DMA1 and TIM1 structs, keeping
+ * them in the returned [`MatrixScanner`], it should not be possible to call this more
+ * than once.
  */
-// TODO: better return type? Perhaps it would be better to accept DMA1CH4 and DMA1CH5
-// and return DMA1CH5's interrupt status register?
 pub fn dma_key_scan(
     freq: impl Into<Hertz>,
     _matrix: Matrix,
@@ -142,23 +199,11 @@ pub fn dma_key_scan(
     ahb: &mut AHB,
     apb2: &mut APB2,
     clocks: &Clocks,
-) -> (dma::dma1::Channels, &'static [[u16; 6]; 2]) {
-    // Values to be written to the Bit Set & Reset Register (BSRR).
-    //
-    // The upper 16 bits (16..=31) set pins to 0 when written (reset), and the
-    // lower 16 bits (0..=15) set pins to 1 when written (set). This way we won't attept
-    // to write to bits that are not part of those that are part of the matrix
-    #[rustfmt::skip]
-    const SCANIN: [u32; 6] = [
-        (0b111110 << 16) | 0b000001,
-        (0b111101 << 16) | 0b000010,
-        (0b111011 << 16) | 0b000100,
-        (0b110111 << 16) | 0b001000,
-        (0b101111 << 16) | 0b010000,
-        (0b011111 << 16) | 0b100000,
-    ];
+) -> Result<MatrixScanner, Error> {
     let mut dma = dma.split(ahb);
-    let scanout = singleton!(: [[u16; 6]; 2] = [[0; 6]; 2]).unwrap();
+    let scanout = singleton!(: [[u16; 6]; 2] = [[0; 6]; 2]).ok_or(Error::Scan)?;
+    #[cfg(feature = "deglitch")]
+    let early_scanout = singleton!(: [[u16; 6]; 2] = [[0; 6]; 2]).ok_or(Error::Scan)?;
 
     // Implementation Notes:
     //
@@ -190,17 +235,11 @@ pub fn dma_key_scan(
         unsafe { (*stm32f103::GPIOA::ptr()).bsrr.as_ptr() } as u32,
         false,
     );
-    // Safety: we have the lenth correct below. This should probably be unsafe, because
-    // we're asking the DMA hardware to derefrence a raw pointer. But hey, it's not.
-    dma.4.set_memory_address(SCANIN.as_ptr() as u32, true);
-    dma.4
-        .set_transfer_length(core::mem::size_of_val(&SCANIN) / core::mem::size_of_val(&SCANIN[0]));
+    // NOTE: the memory address and length are set, and the channel enabled, by
+    // `MatrixScanner::reconfigure`
     #[rustfmt::skip]
     dma.4.ch().cr.modify(|_read, write| {
         write
-            // EN: Enable
-            // NOTE: we're enabling DMA here, but no triggeres have been enabled yet
-            .en().enabled()
             // CIRC: CIRCular mode
             // Uppon end of transfer, start another one
             .circ().enabled()
@@ -216,6 +255,28 @@ pub fn dma_key_scan(
             .msize().bits32()
     });
 
+    // # DMA1 CH6: Requested by Output Compare 3 (ch3) with Timer 1
+    //
+    // This is configured exactly like CH4, but strobes the columns on Port B.
+    #[cfg(feature = "split-cols")]
+    {
+        dma.6.set_peripheral_address(
+            // Safety: we don't enable pointer incrimenting of Perihperal addresses
+            // Further, this pointer dereference is always safe.
+            unsafe { (*stm32f103::GPIOB::ptr()).bsrr.as_ptr() } as u32,
+            false,
+        );
+        #[rustfmt::skip]
+        dma.6.ch().cr.modify(|_read, write| {
+            write
+                .circ().enabled()
+                .dir().from_memory()
+                .minc().enabled()
+                .psize().bits32()
+                .msize().bits32()
+        });
+    }
+
     // # DMA1 CH5: Requested by Update/Overflow of Timer 1
     dma.5.set_peripheral_address(
         // Safety: we don't enable pointer incrimenting of Perihperal addresses
@@ -250,14 +311,36 @@ pub fn dma_key_scan(
             .tcie().enabled()
     });
 
+    // # DMA1 CH3: Requested by Output Compare 2 (ch2) with Timer 1
+    //
+    // This is configured like CH5, but without interrupts, as it completes
+    // each half of its buffer just before CH5 does.
+    #[cfg(feature = "deglitch")]
+    {
+        dma.3.set_peripheral_address(
+            // Safety: we don't enable pointer incrimenting of Perihperal addresses
+            // Further, this pointer dereference is always safe.
+            unsafe { (*stm32f103::GPIOB::ptr()).idr.as_ptr() } as *const u16 as u32,
+            false,
+        );
+        // Safety: the length is set to match by `MatrixScanner::reconfigure`,
+        // and we only read the half of the buffer that's not in use by DMA.
+        dma.3
+            .set_memory_address(early_scanout.as_mut_ptr() as *mut u8 as u32, true);
+        #[rustfmt::skip]
+        dma.3.ch().cr.modify(|_read, write| {
+            write
+                .circ().enabled()
+                .dir().from_peripheral()
+                .minc().enabled()
+                .psize().bits32()
+                .msize().bits16()
+        });
+    }
+
     let clk = APB2::get_timer_frequency(&clocks);
     pac::TIM1::enable(apb2);
     pac::TIM1::reset(apb2);
-    let timeout = (freq.into() * 6).0;
-    let (psc, arr) = compute_arr_presc(timeout, clk.0);
-    // CCR4: Counter Compare Register 4 (channel 4, I think).
-    // CCR: Courter Compare Register (it's the value to compare with).
-    tim1.ccr4.modify(|_, w| w.ccr().bits(arr * 2 / 5));
     // Impl NOTE: We enable the follwing
     // UDE: Update DMA Event
     // CC4DE: Counter Compare 4 DMA Event
@@ -265,30 +348,314 @@ pub fn dma_key_scan(
     // CC4E: Counter Compare 4 Enable (should probably be .enabled, but for some reason
     // the hal only exports .set_bit)
     tim1.ccer.modify(|_, w| w.cc4e().set_bit());
+    // Output compare 3 requests the strobe of the columns on Port B at the same
+    // time as output compare 4 requests the strobe of those on Port A.
+    #[cfg(feature = "split-cols")]
+    {
+        tim1.dier.modify(|_, w| w.cc3de().enabled());
+        tim1.ccer.modify(|_, w| w.cc3e().set_bit());
+    }
+    // Output compare 2 requests the early row read.
+    #[cfg(feature = "deglitch")]
+    {
+        tim1.dier.modify(|_, w| w.cc2de().enabled());
+        tim1.ccer.modify(|_, w| w.cc2e().set_bit());
+    }
+
+    let mut scanner = MatrixScanner {
+        tim1,
+        strobe: dma.4,
+        #[cfg(feature = "split-cols")]
+        strobe_b: dma.6,
+        buffer: ScanBuffer {
+            ch: dma.5,
+            buf: scanout,
+            #[cfg(feature = "deglitch")]
+            early_ch: dma.3,
+            #[cfg(feature = "deglitch")]
+            early_buf: early_scanout,
+        },
+        clk,
+        settle: DEFAULT_SETTLE,
+    };
+    scanner.reconfigure(STROBES, freq);
+    Ok(scanner)
+}
+
+/// Values to be written to the Bit Set & Reset Register (BSRR) of each port
+/// with columns, one for each column.
+///
+/// The upper 16 bits (16..=31) set pins to 0 when written (reset), and the
+/// lower 16 bits (0..=15) set pins to 1 when written (set). This way we won't attept
+/// to write to bits that are not part of those that are part of the matrix
+#[derive(Clone, Copy)]
+pub struct Strobes {
+    /// Written to GPIOA
+    pub porta: &'static [u32; 6],
+    /// Written to GPIOB
+    #[cfg(feature = "split-cols")]
+    pub portb: &'static [u32; 6],
+}
+
+/// Strobe each column in turn, one at a time.
+pub const STROBES: Strobes = Strobes {
+    porta: &SCANIN,
+    #[cfg(feature = "split-cols")]
+    portb: &SCANIN_B,
+};
+
+/// The values to write to the BSRR of a port to strobe each column in turn:
+/// `pins[col]` is the pin of the column on that port, or `None` if it's on
+/// another port. Each value sets the pin of its column, if it's on the port,
+/// and resets the pins of every other column on the port.
+const fn strobes(pins: [Option<u8>; 6]) -> [u32; 6] {
+    let mut all = 0;
+    let mut col = 0;
+    while col < 6 {
+        if let Some(pin) = pins[col] {
+            all |= 1 << pin;
+        }
+        col += 1;
+    }
+    let mut values = [0; 6];
+    col = 0;
+    while col < 6 {
+        let set = match pins[col] {
+            Some(pin) => 1 << pin,
+            None => 0,
+        };
+        values[col] = ((all & !set) << 16) | set;
+        col += 1;
+    }
+    values
+}
+
+#[cfg(not(feature = "split-cols"))]
+const SCANIN: [u32; 6] = strobes([Some(0), Some(1), Some(2), Some(3), Some(4), Some(5)]);
+// The columns on Port A, PA0..=PA3.
+#[cfg(feature = "split-cols")]
+const SCANIN: [u32; 6] = strobes([Some(0), Some(1), Some(2), Some(3), None, None]);
+// The columns on Port B, PB0 and PB1.
+#[cfg(feature = "split-cols")]
+const SCANIN_B: [u32; 6] = strobes([None, None, None, None, Some(0), Some(1)]);
+
+/// Is each column set by exactly one of the strobe values for it?
+const fn one_pin_per_column(strobes: Strobes) -> bool {
+    let mut col = 0;
+    while col < 6 {
+        #[allow(unused_mut)]
+        let mut set = (strobes.porta[col] & 0xffff).count_ones();
+        #[cfg(feature = "split-cols")]
+        {
+            set += (strobes.portb[col] & 0xffff).count_ones();
+        }
+        if set != 1 {
+            return false;
+        }
+        col += 1;
+    }
+    true
+}
+
+const _: () = assert!(one_pin_per_column(STROBES));
+
+/// The pins of the rows on Port B, PB3..=PB15.
+#[cfg(feature = "split-cols")]
+const ROW_PINS: u32 = 0x1fff << 3;
+
+/// Every pin that's set or reset by any of the given strobe values.
+#[cfg(feature = "split-cols")]
+const fn strobed_pins(values: &[u32; 6]) -> u32 {
+    let mut pins = 0;
+    let mut col = 0;
+    while col < 6 {
+        pins |= (values[col] | values[col] >> 16) & 0xffff;
+        col += 1;
+    }
+    pins
+}
+
+// Strobing a row pin would make its keys read as pressed in every column.
+#[cfg(feature = "split-cols")]
+const _: () = assert!(strobed_pins(&SCANIN_B) & ROW_PINS == 0);
+
+/// Time from strobing a column to reading its rows, in twentieths of the time
+/// that each column is strobed for, as in the timing diagram on
+/// [`dma_key_scan`].
+pub const DEFAULT_SETTLE: u8 = 12;
+
+/// The timer and DMA channels scanning the matrix, as setup by [`dma_key_scan`].
+pub struct MatrixScanner {
+    tim1: pac::TIM1,
+    /// The DMA channel strobing the columns on Port A.
+    strobe: dma::dma1::C4,
+    /// The DMA channel strobing the columns on Port B.
+    #[cfg(feature = "split-cols")]
+    strobe_b: dma::dma1::C6,
+    buffer: ScanBuffer,
+    /// Frequency of the clock driving Timer 1
+    clk: Hertz,
+    /// Time from strobing a column to reading its rows; see [`DEFAULT_SETTLE`].
+    settle: u8,
+}
+
+impl MatrixScanner {
+    /// Read the most recently completed scan, if one completed since the last
+    /// call to `read`.
+    ///
+    /// With the `deglitch` feature, a row only reads as pressed if it did in
+    /// both reads of its column.
+    pub fn read(&mut self) -> Option<[u16; 6]> {
+        self.buffer.read()
+    }
+
+    /// Stop scanning.
+    ///
+    /// Both the column strobe and the row read are requested by Timer 1, so
+    /// stopping it stops both, and they stay in step when it's resumed.
+    pub fn pause(&mut self) {
+        // CEN: Counter ENabled
+        self.tim1.cr1.modify(|_, w| w.cen().clear_bit());
+    }
+
+    /// Continue scanning from where [`pause`](Self::pause) stopped.
+    pub fn resume(&mut self) {
+        self.tim1.cr1.modify(|_, w| w.cen().set_bit());
+    }
+
+    /// Strobe each column earlier or later, so that the rows are read the
+    /// given time after the column is strobed, in twentieths of the time each
+    /// column is strobed for. This takes effect from the next column.
+    pub fn set_settle(&mut self, settle: u8) {
+        self.settle = settle.max(1).min(19);
+        let arr = self.tim1.arr.read().arr().bits() as u32;
+        let strobe_at = (arr * (20 - self.settle as u32) / 20) as u16;
+        // CCR4: Counter Compare Register 4 (channel 4, I think).
+        // CCR: Courter Compare Register (it's the value to compare with).
+        self.tim1.ccr4.modify(|_, w| w.ccr().bits(strobe_at));
+        #[cfg(feature = "split-cols")]
+        self.tim1.ccr3.modify(|_, w| w.ccr().bits(strobe_at));
+    }
+
+    /// Scan with different strobes at a different frequency.
+    ///
+    /// The scan in progress is thrown away and scanning restarts from the first
+    /// column, so the next scan returned by [`read`](Self::read) is made
+    /// entirely with the new strobes.
+    pub fn reconfigure(&mut self, strobes: Strobes, freq: impl Into<Hertz>) {
+        self.pause();
+        // DMA channels may only be reprogrammed while disabled. Stopping them
+        // also clears the flags of any scan that completed before the pause.
+        self.strobe.stop();
+        #[cfg(feature = "split-cols")]
+        self.strobe_b.stop();
+        self.buffer.ch.stop();
+        #[cfg(feature = "deglitch")]
+        self.buffer.early_ch.stop();
+
+        // Safety: we have the lenth correct below. This should probably be unsafe, because
+        // we're asking the DMA hardware to derefrence a raw pointer. But hey, it's not.
+        self.strobe
+            .set_memory_address(strobes.porta.as_ptr() as u32, true);
+        self.strobe.set_transfer_length(strobes.porta.len());
+        #[cfg(feature = "split-cols")]
+        {
+            self.strobe_b
+                .set_memory_address(strobes.portb.as_ptr() as u32, true);
+            self.strobe_b.set_transfer_length(strobes.portb.len());
+        }
+        // Rewind the row read to the start of the buffer, in step with the strobe.
+        self.buffer
+            .ch
+            .set_transfer_length(2 * ScanBuffer::HALF_LEN as usize);
+        #[cfg(feature = "deglitch")]
+        self.buffer
+            .early_ch
+            .set_transfer_length(2 * ScanBuffer::HALF_LEN as usize);
+
+        let timeout = (freq.into() * 6).0;
+        let (psc, arr) = compute_arr_presc(timeout, self.clk.0);
+        // PSC: Prescaller
+        self.tim1.psc.write(|w| w.psc().bits(psc));
+        // ARR: Auto Reload Register
+        self.tim1.arr.write(|w| w.arr().bits(arr));
+        self.set_settle(self.settle);
+        #[cfg(feature = "deglitch")]
+        self.tim1.ccr2.modify(|_, w| w.ccr().bits(arr * 4 / 5));
 
-    // pause
-    // CEN: Counter ENabled
-    tim1.cr1.modify(|_, w| w.cen().clear_bit());
-    // PSC: Prescaller
-    tim1.psc.write(|w| w.psc().bits(psc));
-    // ARR: Auto Reload Register
-    tim1.arr.write(|w| w.arr().bits(arr));
-
-    // URS: Update Request Source
-    // Trigger an update event to load the prescaler value to the clock
-    // Sets the URS bit to prevent an interrupt from being triggered by
-    // the UG bit
-    tim1.cr1.modify(|_, w| w.urs().set_bit());
-
-    // EGR: Event Generation Register
-    // UG: Force an update
-    tim1.egr.write(|w| w.ug().set_bit());
-    tim1.cr1.modify(|_, w| w.urs().clear_bit());
-
-    // start counter
-    tim1.cr1.modify(|_, w| w.cen().set_bit());
-
-    (dma, &*scanout)
+        // URS: Update Request Source
+        // Trigger an update event to load the prescaler value to the clock
+        // Sets the URS bit to prevent an interrupt, or a row read, from being
+        // triggered by the UG bit
+        self.tim1.cr1.modify(|_, w| w.urs().set_bit());
+
+        // EGR: Event Generation Register
+        // UG: Force an update
+        self.tim1.egr.write(|w| w.ug().set_bit());
+        self.tim1.cr1.modify(|_, w| w.urs().clear_bit());
+
+        // EN: Enable
+        // NOTE: nothing is transfered until the timer requests it
+        self.strobe.start();
+        #[cfg(feature = "split-cols")]
+        self.strobe_b.start();
+        self.buffer.ch.start();
+        #[cfg(feature = "deglitch")]
+        self.buffer.early_ch.start();
+        self.resume();
+    }
+}
+
+/// The double buffer that the DMA scans the matrix into.
+pub struct ScanBuffer {
+    /// The DMA channel reading the rows into the buffer.
+    ch: dma::dma1::C5,
+    buf: &'static [[u16; 6]; 2],
+    /// The DMA channel reading the rows a second time, earlier in each column.
+    #[cfg(feature = "deglitch")]
+    early_ch: dma::dma1::C3,
+    #[cfg(feature = "deglitch")]
+    early_buf: &'static [[u16; 6]; 2],
+}
+
+impl ScanBuffer {
+    /// Number of transfers that fill one half of the buffer.
+    const HALF_LEN: u32 = 6;
+
+    /// Read the most recently completed scan, if one completed since the last
+    /// call to `read`.
+    pub fn read(&mut self) -> Option<[u16; 6]> {
+        let isr = self.ch.isr();
+        if isr.htif5().bit_is_clear() && isr.tcif5().bit_is_clear() {
+            return None;
+        }
+        self.ch.ifcr().write(|w| w.cgif5().clear());
+        // NDTR counts down the transfers left until the end of the buffer. While
+        // it's above half, the DMA is writing the first half, so the second half
+        // holds the most recent complete scan, and vice versa.
+        let half = if self.ch.get_ndtr() > Self::HALF_LEN {
+            1
+        } else {
+            0
+        };
+        // Safety: the DMA is writing to the other half of the buffer, and won't
+        // get to this half until a whole scan from now. A volatile read keeps the
+        // compiler from assuming that the buffer never changes, as nothing it can
+        // see writes to it.
+        #[cfg_attr(not(feature = "deglitch"), allow(unused_mut))]
+        let mut scan = unsafe { core::ptr::read_volatile(&self.buf[half]) };
+        // The early read of each column happens before the read into `buf`, so
+        // it has finished the same half of its buffer.
+        #[cfg(feature = "deglitch")]
+        {
+            // Safety: as above
+            let early = unsafe { core::ptr::read_volatile(&self.early_buf[half]) };
+            for (rows, early) in scan.iter_mut().zip(early.iter()) {
+                *rows &= early;
+            }
+        }
+        Some(scan)
+    }
 }
 
 const LOG_SIZE: usize = 1024;
@@ -305,14 +672,19 @@ const LOG_SIZE: usize = 1024;
 /// is unable to keep up, but then there is a lul in activity, it should be
 /// possible for the debugger to catch up eventually.
 ///
+/// Only the scan interrupt writes to the log. It writes a record into the body
+/// before it moves the head past it, with release ordering, so a reader that
+/// loads the head with acquire ordering sees every record before the head
+/// complete, up until the writer laps it.
 pub struct Log {
-    /// Location of the next b
-    head: usize,
+    /// Location of the next record to be written, which is also the oldest
+    /// record once the log has filled up.
+    head: AtomicUsize,
     body: [KeyState; LOG_SIZE],
 }
 
 static mut THELOG: Log = Log {
-    head: 0,
+    head: AtomicUsize::new(0),
     body: [KeyState {
         timestamp: 0,
         col: 0,
@@ -323,21 +695,20 @@ static mut THELOG: Log = Log {
 };
 impl Log {
     pub fn log(&mut self, elem: KeyState) {
-        self.body[self.head] = elem;
-        self.head += 1;
-        self.head %= LOG_SIZE;
+        let head = self.head.load(Ordering::Relaxed);
+        self.body[head] = elem;
+        self.head.store((head + 1) % LOG_SIZE, Ordering::Release);
     }
 
-    /// Return the log singleton. Panics if called twice
-    pub fn get() -> &'static mut Self {
+    /// Return the log singleton, or [`Error::Log`] if called twice
+    pub fn get() -> Result<&'static mut Self, Error> {
         // NOTE: This is a manual implementation of the singleton macro so that the
         // names are more predictable
         static TAKEN: AtomicBool = AtomicBool::new(false);
         if TAKEN.swap(true, Ordering::AcqRel) {
-            // The aforementioned panic when called twice
-            panic!();
+            return Err(Error::Log);
         }
-        unsafe { &mut THELOG }
+        Ok(unsafe { &mut THELOG })
     }
 }
 
@@ -346,21 +717,48 @@ impl Log {
 /// user from forgetting to scan first.
 pub struct ReportToken();
 
+/// A key changed between pressed and released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The key at (row, col) was pressed.
+    Press(u8, u8),
+    /// The key at (row, col) was released.
+    Release(u8, u8),
+}
+
+/// How the triggers debounce the keys.
+pub struct Debounce<'a, const C: usize> {
+    pub mode: Mode,
+    /// Number of scans a key must be stable before the triggers accept another
+    /// change.
+    pub timeout: u8,
+    /// As `timeout`, for the keys of the right half.
+    pub right_timeout: u8,
+    /// A bit per row for each column, set for the keys of the right half.
+    pub right_half: &'a [u16; C],
+}
+
 /// Scan all keys into the triggers and generate a HID report.
-pub fn scan<'a, const R: usize, const C: usize, const T: u8>(
+pub fn scan<'a, const R: usize, const C: usize>(
     scanout_half: &'a [u16; C],
-    triggers: &'a mut [[QuickDraw<T>; R]; C],
+    triggers: &'a mut [[QuickDraw; R]; C],
     log: &'a mut Log,
     timestamp: u32,
+    debounce: &Debounce<C>,
 ) -> ReportToken {
     for (col, (row_val, trigger_row)) in scanout_half.iter().zip(&mut triggers[..]).enumerate() {
         for row in 0..R {
             let press = (row_val & (1 << (row + 3))) != 0;
-            let old: QuickDraw<T> = trigger_row[row].clone();
-            let is_old_pressed = old.is_pressed();
-            trigger_row[row].step(press, timestamp as u8);
+            let timeout = if debounce.right_half[col] & (1 << row) != 0 {
+                debounce.right_timeout
+            } else {
+                debounce.timeout
+            };
+            let old: QuickDraw = trigger_row[row].clone();
+            let is_old_pressed = old.is_pressed_in(debounce.mode);
+            trigger_row[row].step(press, timestamp as u8, timeout);
             let new = &trigger_row[row];
-            let is_new_pressed = new.is_pressed();
+            let is_new_pressed = new.is_pressed_in(debounce.mode);
             if *new != old {
                 let event = if is_old_pressed == is_new_pressed {
                     PressRelease::None
@@ -369,6 +767,10 @@ pub fn scan<'a, const R: usize, const C: usize, const T: u8>(
                 } else {
                     PressRelease::Press
                 };
+                #[cfg(feature = "debug-signals")]
+                if event != PressRelease::None {
+                    crate::debug::toggle(crate::debug::Signal::Debounce);
+                }
                 log.log(KeyState {
                     timestamp,
                     row: row as u8,
@@ -382,21 +784,115 @@ pub fn scan<'a, const R: usize, const C: usize, const T: u8>(
     ReportToken()
 }
 
-pub fn report<'a, const R: usize, const C: usize, const T: u8>(
-    layout: &'static Layout<R, C>,
-    triggers: &'a [[QuickDraw<T>; R]; C],
+/// Collect the keys that the triggers hold pressed, when debouncing in the
+/// given mode, into a bit per row for each column.
+pub fn held<const R: usize, const C: usize>(
+    triggers: &[[QuickDraw; R]; C],
+    mode: Mode,
+) -> [u16; C] {
+    let mut held = [0; C];
+    for (bits, trigger_row) in held.iter_mut().zip(triggers.iter()) {
+        for (row, trigger) in trigger_row.iter().enumerate() {
+            if trigger.is_pressed_in(mode) {
+                *bits |= 1 << row;
+            }
+        }
+    }
+    held
+}
+
+/// Find the layer selected by held keys with a `Layer` action in the base
+/// layer. When several are held, the highest layer wins.
+///
+/// `pressed` has a bit per row for each column, set when that key is pressed.
+pub fn layer<const R: usize, const C: usize>(base: &Layout<R, C>, pressed: &[u16; C]) -> usize {
+    let mut layer = 0;
+    for (col, bits) in pressed.iter().enumerate() {
+        for row in 0..R {
+            if bits & (1 << row) != 0 {
+                if let Some(&Action::Layer(l)) = action(base, row, col) {
+                    layer = layer.max(l as usize);
+                }
+            }
+        }
+    }
+    layer
+}
+
+/// The action of each key in the given row of the given layer, as it would be
+/// performed: a layer that the keymap doesn't have falls through to the first
+/// one, as when it's chosen by [`layer`], a `Layer` action does nothing outside
+/// the first layer, and key codes are remapped, or left out if they're not
+/// reported at all.
+pub fn preview<const R: usize, const C: usize>(
+    layers: &[&Layout<R, C>],
+    layer: usize,
+    row: usize,
+    remap: Remap,
+) -> [Option<Action>; C] {
+    let base = layers[0];
+    let layout = layers.get(layer).copied().unwrap_or(base);
+    let mut actions = [None; C];
+    for (col, resolved) in actions.iter_mut().enumerate() {
+        *resolved = match action(layout, row, col) {
+            Some(&Action::KeyCode(kc)) => remap.apply(kc).map(Action::KeyCode),
+            Some(&Action::Layer(_)) if !core::ptr::eq(layout, base) => None,
+            other => other.copied(),
+        };
+    }
+    actions
+}
+
+/// Build a HID report of the key codes of the pressed keys, after remapping,
+/// leaving out those blocked by the tutor.
+pub fn report<const R: usize, const C: usize>(
+    layout: &Layout<R, C>,
+    pressed: &[u16; C],
+    remap: Remap,
+    tutor: &Tutor,
     #[allow(unused_variables)]
     token: ReportToken,
 ) -> KbHidReport {
     let mut rep = KbHidReport::default();
-    for (col, trigger_row) in triggers.iter().enumerate() {
+    for (col, bits) in pressed.iter().enumerate() {
         for row in 0..R {
-            if trigger_row[row].is_pressed() {
-                if let Some(&kc) = keycode(layout, row, col) {
-                    rep.pressed(kc);
+            if bits & (1 << row) != 0 {
+                if let Some(&Action::KeyCode(kc)) = action(layout, row, col) {
+                    match remap.apply(kc) {
+                        Some(kc) if !tutor.blocks(kc) => rep.pressed(kc),
+                        _ => (),
+                    }
                 }
             }
         }
     }
     rep
 }
+
+/// The consumer control usage of the first pressed key that has one, or 0.
+///
+/// `Action::Consumer` keys always have one. Media key codes only do with
+/// `macos`, which ignores them as key codes; see
+/// [`crate::key_code::KeyCode::consumer_usage`].
+pub fn consumer<const R: usize, const C: usize>(
+    layout: &Layout<R, C>,
+    pressed: &[u16; C],
+    macos: bool,
+) -> u16 {
+    for (col, bits) in pressed.iter().enumerate() {
+        for row in 0..R {
+            if bits & (1 << row) != 0 {
+                match action(layout, row, col) {
+                    Some(&Action::Consumer(control)) => return control.usage(),
+                    Some(&Action::KeyCode(kc)) if macos => {
+                        if let Some(usage) = kc.consumer_usage() {
+                            return usage;
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    0
+}
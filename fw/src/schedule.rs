@@ -0,0 +1,149 @@
+//! Switching profiles at set times of the week.
+//!
+//! The keyboard has no battery backed clock, so the host sets the time with
+//! `Command::SetClock` each time it connects, and the keyboard keeps it from
+//! then on by counting scans. Until the time is set, nothing is scheduled.
+//!
+//! Each rule makes a profile active when its time comes around on one of its
+//! days, such as the profile with the LED off at 22:00 every day, or the gaming
+//! profile at midnight on Saturday and the usual one at midnight on Monday. A
+//! profile chosen by hand stays active until the next rule's time. Rules are set
+//! by the host, and stored in flash with the profiles.
+
+/// Maximum number of rules.
+pub const MAX: usize = 4;
+
+/// Seconds in a day.
+const DAY_SECS: u32 = 24 * 60 * 60;
+
+/// Seconds in a week.
+const WEEK_SECS: u32 = 7 * DAY_SECS;
+
+/// A time of the week, in seconds since midnight at the start of Monday.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WeekTime(pub u32);
+
+impl WeekTime {
+    /// The time of the week at the given local time, in seconds since the
+    /// start of 1970 in the host's time zone.
+    pub fn from_local(secs: u32) -> Self {
+        // The first of January 1970 was a Thursday, 3 days after a Monday.
+        Self((secs % WEEK_SECS + 3 * DAY_SECS) % WEEK_SECS)
+    }
+
+    /// The day, from 0 for Monday to 6 for Sunday.
+    fn day(self) -> u32 {
+        self.0 / DAY_SECS
+    }
+
+    /// Minutes since midnight.
+    fn minute(self) -> u32 {
+        self.0 % DAY_SECS / 60
+    }
+}
+
+/// Keeps the time of the week set by the host.
+#[derive(Debug, Default)]
+pub struct Clock {
+    /// The time when it was last updated, if it's been set
+    time: Option<WeekTime>,
+    /// The time of the last update, in scans
+    updated: u32,
+    /// Scans since the last whole second
+    scans: u32,
+}
+
+impl Clock {
+    /// Set the time, at the time `now` in scans.
+    pub fn set(&mut self, time: WeekTime, now: u32) {
+        self.time = Some(time);
+        self.updated = now;
+        self.scans = 0;
+    }
+
+    /// Bring the time up to `now`, in scans, at `scans_per_sec` scans per
+    /// second, returning it if it's been set.
+    pub fn update(&mut self, now: u32, scans_per_sec: u32) -> Option<WeekTime> {
+        let time = self.time.as_mut()?;
+        self.scans += now.wrapping_sub(self.updated);
+        self.updated = now;
+        let secs = self.scans / scans_per_sec;
+        self.scans %= scans_per_sec;
+        time.0 = (time.0 + secs % WEEK_SECS) % WEEK_SECS;
+        Some(*time)
+    }
+}
+
+/// Make a profile active at a time of day, on some days of the week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    /// A bit per day, from bit 0 for Monday to bit 6 for Sunday
+    pub days: u8,
+    /// Minutes since midnight
+    pub minute: u16,
+    /// The id of the profile to make active
+    pub profile: u8,
+}
+
+/// Every rule in use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    rules: [Option<Rule>; MAX],
+    /// The time when the rules were last checked
+    checked: Option<WeekTime>,
+}
+
+impl Schedule {
+    /// Size of `Schedule` when converted to bytes.
+    pub const SIZE: usize = MAX * 4;
+
+    /// Replace the rule with the given index, or with no days, remove it.
+    pub fn set(&mut self, index: u8, rule: Rule) {
+        if let Some(slot) = self.rules.get_mut(index as usize) {
+            *slot = Some(rule).filter(|rule| rule.days & 0x7f != 0);
+        }
+    }
+
+    /// The profile to make active, if a rule's time has come since the last
+    /// time this was called.
+    pub fn check(&mut self, time: WeekTime) -> Option<u8> {
+        let checked = self.checked.replace(time)?;
+        if checked.minute() == time.minute() && checked.day() == time.day() {
+            return None;
+        }
+        self.rules
+            .iter()
+            .flatten()
+            .find(|rule| rule.days & (1 << time.day()) != 0 && rule.minute as u32 == time.minute())
+            .map(|rule| rule.profile)
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        for (chunk, rule) in bytes.chunks_exact_mut(4).zip(self.rules.iter()) {
+            if let Some(rule) = rule {
+                let [minute_lo, minute_hi] = rule.minute.to_le_bytes();
+                chunk.copy_from_slice(&[rule.days, minute_lo, minute_hi, rule.profile]);
+            }
+        }
+        bytes
+    }
+
+    /// Parse rules produced by `to_bytes`, returning `None` if there are too
+    /// few bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE {
+            return None;
+        }
+        let mut schedule = Self::default();
+        for (index, chunk) in bytes.chunks_exact(4).enumerate() {
+            let rule = Rule {
+                days: chunk[0],
+                minute: u16::from_le_bytes([chunk[1], chunk[2]]),
+                profile: chunk[3],
+            };
+            schedule.set(index as u8, rule);
+        }
+        Some(schedule)
+    }
+}
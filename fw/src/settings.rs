@@ -0,0 +1,395 @@
+//! Runtime settings, and the named profiles that group them.
+
+use crate::remap::Remap;
+
+/// Everything about the keyboard's behavior that may change while it's running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// Time, in ms, that a key of the left half must be stable for before the
+    /// debouncer will accept another change in its state. It's in time rather
+    /// than scans so that it doesn't change with the scan rate. The debouncer
+    /// counts scans in a byte, so it's at most [`MAX_DEBOUNCE`], 127 ms at 2
+    /// kHz, and settings with more are rejected.
+    pub debounce: u8,
+    /// As `debounce`, but for the keys of the right half, which may have
+    /// switches that bounce differently.
+    pub debounce_right: u8,
+    /// Should the status LED be lit while these settings are active?
+    pub led: bool,
+    /// Index of the keymap used to translate key presses.
+    pub keymap: u8,
+    /// Time, in units of 10 ms, that a key must be held before its press is
+    /// accepted. 0 turns slow keys off.
+    pub slow_keys: u8,
+    /// Time, in units of 10 ms, after a key is released during which a new
+    /// press of it is ignored. 0 turns bounce keys off.
+    pub bounce_keys: u8,
+    /// Modifiers to remap as reports are built.
+    pub remap: Remap,
+    /// Do tapped modifiers latch? See [`crate::latch`].
+    pub latch_mods: bool,
+    /// Send media keys as consumer controls too, for macOS, which ignores
+    /// them as key codes, even if the host isn't detected as macOS. See
+    /// [`crate::host::Host::media_as_consumer`].
+    pub macos: bool,
+    /// Time, in units of 10 ms, that a key must be held before it repeats. 0
+    /// turns repeat off. See [`crate::repeat`].
+    pub repeat_delay: u8,
+    /// Time, in units of 10 ms, between repeats of a held key.
+    pub repeat_interval: u8,
+    /// Send newly pressed modifiers a report ahead of the keys pressed with
+    /// them, and release keys a report ahead of the modifiers released with
+    /// them, for hosts that race the two, at the cost of a frame of latency.
+    pub modifiers_first: bool,
+    /// Depth, in 256ths of the travel, past which an analog key is pressed.
+    /// See [`crate::analog`].
+    pub analog_actuation: u8,
+    /// Distance, in 256ths of the travel, that an analog key must rise or
+    /// fall to be released or pressed again. 0 turns rapid trigger off.
+    pub analog_rapid: u8,
+    /// Time, in minutes, of typing without a rest after which the LED
+    /// reminds the typist to take a break. 0 turns the reminder off. See
+    /// [`crate::rest`].
+    pub break_minutes: u8,
+    /// Should held keys do what they do in the active layer, changing as the
+    /// layer does, rather than what they did in the layer they were pressed
+    /// in? See [`crate::sticky`].
+    pub follow_layers: bool,
+}
+
+impl Settings {
+    /// Size of `Settings` when converted to bytes.
+    pub const SIZE: usize = 16;
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        [
+            self.debounce,
+            self.led as u8,
+            self.keymap,
+            self.slow_keys,
+            self.bounce_keys,
+            self.remap.0,
+            self.debounce_right,
+            self.latch_mods as u8,
+            self.macos as u8,
+            self.repeat_delay,
+            self.repeat_interval,
+            self.modifiers_first as u8,
+            self.analog_actuation,
+            self.analog_rapid,
+            self.break_minutes,
+            self.follow_layers as u8,
+        ]
+    }
+
+    /// Parse settings produced by `to_bytes`, returning `None` if they're
+    /// malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [debounce @ 0..=MAX_DEBOUNCE, led @ 0..=1, keymap, slow_keys, bounce_keys, remap, debounce_right @ 0..=MAX_DEBOUNCE, latch_mods @ 0..=1, macos @ 0..=1, repeat_delay, repeat_interval, modifiers_first @ 0..=1, analog_actuation, analog_rapid, break_minutes, follow_layers @ 0..=1, ..] => {
+                Some(Self {
+                    debounce: *debounce,
+                    debounce_right: *debounce_right,
+                    led: *led != 0,
+                    keymap: *keymap,
+                    slow_keys: *slow_keys,
+                    bounce_keys: *bounce_keys,
+                    remap: Remap(*remap),
+                    latch_mods: *latch_mods != 0,
+                    macos: *macos != 0,
+                    repeat_delay: *repeat_delay,
+                    repeat_interval: *repeat_interval,
+                    modifiers_first: *modifiers_first != 0,
+                    analog_actuation: *analog_actuation,
+                    analog_rapid: *analog_rapid,
+                    break_minutes: *break_minutes,
+                    follow_layers: *follow_layers != 0,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A named set of settings.
+pub struct Profile {
+    pub name: &'static str,
+    pub settings: Settings,
+}
+
+/// Index of the profile used at power on.
+pub const DEFAULT: usize = 0;
+
+/// The longest debounce time, in ms, that the debouncer can count at the scan
+/// rate.
+pub const MAX_DEBOUNCE: u8 = (u8::MAX as u32 / crate::SCAN_KHZ) as u8;
+
+/// Index of the profile that the `GamingMode` action toggles.
+pub const GAMING: usize = 1;
+
+/// Number of profiles.
+pub const COUNT: usize = 4;
+
+/// The profiles as they are before the host changes any of them.
+pub static PROFILES: [Profile; COUNT] = [
+    Profile {
+        name: "default",
+        settings: Settings {
+            debounce: 50,
+            debounce_right: 50,
+            led: false,
+            keymap: 0,
+            slow_keys: 0,
+            bounce_keys: 0,
+            remap: Remap(0),
+            latch_mods: false,
+            macos: false,
+            repeat_delay: 0,
+            repeat_interval: 5,
+            modifiers_first: false,
+            analog_actuation: 128,
+            analog_rapid: 0,
+            break_minutes: 0,
+            follow_layers: false,
+        },
+    },
+    // Games are played with fewer, more deliberate presses, and a long
+    // debounce limits how quickly a key may be tapped repeatedly. Rapid
+    // trigger lets analog keys be tapped as quickly as they move.
+    Profile {
+        name: "gaming",
+        settings: Settings {
+            debounce: 10,
+            debounce_right: 10,
+            led: true,
+            keymap: 0,
+            slow_keys: 0,
+            bounce_keys: 0,
+            remap: Remap(0),
+            latch_mods: false,
+            macos: false,
+            repeat_delay: 0,
+            repeat_interval: 5,
+            modifiers_first: false,
+            analog_actuation: 128,
+            analog_rapid: 32,
+            break_minutes: 0,
+            follow_layers: false,
+        },
+    },
+    Profile {
+        name: "user 1",
+        settings: Settings {
+            debounce: 50,
+            debounce_right: 50,
+            led: false,
+            keymap: 0,
+            slow_keys: 0,
+            bounce_keys: 0,
+            remap: Remap(0),
+            latch_mods: false,
+            macos: false,
+            repeat_delay: 0,
+            repeat_interval: 5,
+            modifiers_first: false,
+            analog_actuation: 128,
+            analog_rapid: 0,
+            break_minutes: 0,
+            follow_layers: false,
+        },
+    },
+    Profile {
+        name: "user 2",
+        settings: Settings {
+            debounce: 50,
+            debounce_right: 50,
+            led: false,
+            keymap: 0,
+            slow_keys: 0,
+            bounce_keys: 0,
+            remap: Remap(0),
+            latch_mods: false,
+            macos: false,
+            repeat_delay: 0,
+            repeat_interval: 5,
+            modifiers_first: false,
+            analog_actuation: 128,
+            analog_rapid: 0,
+            break_minutes: 0,
+            follow_layers: false,
+        },
+    },
+];
+
+/// Number of scans that the profiles must go unchanged before they're stored,
+/// about 2 seconds. Storing stalls the scan, and wears out the flash, so a
+/// burst of changes, like stepping through debounce times, is stored once.
+const COMMIT_SCANS: u32 = 4000;
+
+/// All profiles, and which of them is active.
+///
+/// Changes take effect immediately, but are only stored once they've stopped
+/// for a while; see [`Profiles::take_commit`].
+#[derive(Clone, PartialEq)]
+pub struct Profiles {
+    settings: [Settings; COUNT],
+    active: usize,
+    previous: usize,
+    /// Has anything changed since the profiles were last stored?
+    dirty: bool,
+    /// Has anything changed since the last call to `take_commit`?
+    changed: bool,
+    /// The time of the last change seen by `take_commit`
+    changed_at: u32,
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        let mut settings = [PROFILES[DEFAULT].settings; COUNT];
+        for (s, profile) in settings.iter_mut().zip(PROFILES.iter()) {
+            *s = profile.settings;
+        }
+        Self {
+            settings,
+            active: DEFAULT,
+            previous: DEFAULT,
+            dirty: false,
+            changed: false,
+            changed_at: 0,
+        }
+    }
+}
+
+impl Profiles {
+    /// Size of `Profiles` when converted to bytes.
+    pub const SIZE: usize = 1 + COUNT * Settings::SIZE;
+
+    /// The id of the active profile.
+    pub fn active_id(&self) -> usize {
+        self.active
+    }
+
+    /// The name of the active profile.
+    pub fn name(&self) -> &'static str {
+        PROFILES[self.active].name
+    }
+
+    /// The settings of the active profile.
+    pub fn settings(&self) -> Settings {
+        self.settings[self.active]
+    }
+
+    /// Make the profile with the given id active. Ids that don't name a
+    /// profile are ignored.
+    pub fn select(&mut self, id: usize) {
+        if id < COUNT && id != self.active {
+            self.previous = self.active;
+            self.active = id;
+            self.touch();
+        }
+    }
+
+    /// Make the profile with the given id active, or, if it's already active,
+    /// return to the profile that was active before it.
+    pub fn toggle(&mut self, id: usize) {
+        if id == self.active {
+            self.select(self.previous);
+        } else {
+            self.select(id);
+        }
+    }
+
+    /// Replace the settings of the profile with the given id. Ids that don't
+    /// name a profile are ignored.
+    pub fn set(&mut self, id: usize, settings: Settings) {
+        self.update_profile(id, |s| *s = settings);
+    }
+
+    /// Change the settings of the active profile.
+    pub fn update(&mut self, f: impl FnOnce(&mut Settings)) {
+        self.update_profile(self.active, f);
+    }
+
+    /// Change the settings of the profile with the given id. Ids that don't
+    /// name a profile are ignored.
+    pub fn update_profile(&mut self, id: usize, f: impl FnOnce(&mut Settings)) {
+        if let Some(s) = self.settings.get_mut(id) {
+            let old = *s;
+            f(s);
+            if *s != old {
+                self.touch();
+            }
+        }
+    }
+
+    fn touch(&mut self) {
+        self.dirty = true;
+        self.changed = true;
+    }
+
+    /// Returns true, once, when the profiles should be stored: after they've
+    /// changed, and then gone unchanged for `COMMIT_SCANS`.
+    ///
+    /// This must be called every scan, with the scan's timestamp.
+    pub fn take_commit(&mut self, now: u32) -> bool {
+        if core::mem::replace(&mut self.changed, false) {
+            self.changed_at = now;
+        }
+        if self.dirty && now.wrapping_sub(self.changed_at) >= COMMIT_SCANS {
+            self.dirty = false;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0] = self.active as u8;
+        for (chunk, settings) in bytes[1..]
+            .chunks_exact_mut(Settings::SIZE)
+            .zip(self.settings.iter())
+        {
+            chunk.copy_from_slice(&settings.to_bytes());
+        }
+        bytes
+    }
+
+    /// Parse profiles produced by `to_bytes`, returning `None` if they're
+    /// malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE || bytes[0] as usize >= COUNT {
+            return None;
+        }
+        let mut profiles = Self::default();
+        profiles.active = bytes[0] as usize;
+        profiles.previous = profiles.active;
+        for (chunk, settings) in bytes[1..Self::SIZE]
+            .chunks_exact(Settings::SIZE)
+            .zip(profiles.settings.iter_mut())
+        {
+            *settings = Settings::from_bytes(chunk)?;
+        }
+        Some(profiles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_debounce_longer_than_the_debouncer_counts() {
+        let mut bytes = PROFILES[0].settings.to_bytes();
+        bytes[0] = MAX_DEBOUNCE;
+        assert_eq!(
+            Settings::from_bytes(&bytes).map(|s| s.debounce),
+            Some(MAX_DEBOUNCE)
+        );
+        bytes[0] = MAX_DEBOUNCE + 1;
+        assert_eq!(Settings::from_bytes(&bytes), None);
+        bytes[0] = 50;
+        bytes[6] = u8::MAX;
+        assert_eq!(Settings::from_bytes(&bytes), None);
+    }
+}
@@ -0,0 +1,103 @@
+//! A snapshot of which keys are pressed that survives a reset.
+//!
+//! The snapshot lives in RAM that the runtime leaves uninitialized, so that
+//! after a reset that isn't caused by power-on, such as the watchdog, the keys
+//! that were held can start out held. Otherwise, every held key would start out
+//! released, and the debouncer would emit a press for each of them as soon as
+//! it's scanned.
+//!
+//! The same RAM counts the resets since power-on, for the host to see; see
+//! [`crate::counters`].
+
+use stm32f1xx_hal::pac;
+
+use crate::trigger::{Mode, QuickDraw};
+
+/// Number of columns in the matrix.
+const COLS: usize = 6;
+
+/// Marks the snapshot as written by this firmware, rather than left as the
+/// random contents of RAM after power-on.
+const MAGIC: u32 = 0x534e_4150;
+
+struct Snapshot {
+    magic: u32,
+    /// A bit per row for each column, set when that key is pressed.
+    pressed: [u16; COLS],
+    check: u32,
+}
+
+#[link_section = ".uninit.SNAPSHOT"]
+static mut SNAPSHOT: Snapshot = Snapshot {
+    magic: 0,
+    pressed: [0; COLS],
+    check: 0,
+};
+
+/// Number of resets since power-on, and its complement, to tell it from the
+/// random contents of RAM.
+#[link_section = ".uninit.RESETS"]
+static mut RESETS: [u32; 2] = [0; 2];
+
+fn checksum(pressed: &[u16; COLS]) -> u32 {
+    pressed
+        .iter()
+        .fold(MAGIC, |acc, &col| acc.rotate_left(5) ^ col as u32)
+}
+
+/// Record which keys are pressed.
+pub fn save<const R: usize>(triggers: &[[QuickDraw; R]; COLS]) {
+    // A key that's bouncing is held in either mode, so it's restored as held.
+    let pressed = crate::scan::held(triggers, Mode::Eager);
+    // Safety: the snapshot is only saved from the scan interrupt, and only
+    // restored before that interrupt is unmasked.
+    unsafe {
+        SNAPSHOT.magic = MAGIC;
+        SNAPSHOT.check = checksum(&pressed);
+        SNAPSHOT.pressed = pressed;
+    }
+}
+
+/// Start the triggers out as they were when the snapshot was taken, if the MCU
+/// was reset by something other than a power-on and the snapshot is intact.
+///
+/// Returns true if the triggers were restored.
+pub fn restore<const R: usize>(triggers: &mut [[QuickDraw; R]; COLS]) -> bool {
+    // Safety: The reset flags in CSR are only accessed here, and only read
+    // then cleared, so that they describe the next reset.
+    let power_on = unsafe {
+        let rcc = &*pac::RCC::ptr();
+        let power_on = rcc.csr.read().porrstf().bit_is_set();
+        rcc.csr.modify(|_, w| w.rmvf().set_bit());
+        power_on
+    };
+    // Safety: the count is only touched here, and read by `resets`, which is
+    // called from the same context.
+    unsafe {
+        let [count, check] = RESETS;
+        let count = if power_on || check != !count {
+            0
+        } else {
+            count.wrapping_add(1)
+        };
+        RESETS = [count, !count];
+    }
+    // Safety: as in `save`.
+    let (magic, pressed, check) = unsafe { (SNAPSHOT.magic, SNAPSHOT.pressed, SNAPSHOT.check) };
+    if power_on || magic != MAGIC || check != checksum(&pressed) {
+        return false;
+    }
+    for (bits, trigger_row) in pressed.iter().zip(triggers.iter_mut()) {
+        for (row, trigger) in trigger_row.iter_mut().enumerate() {
+            *trigger = QuickDraw::Stable(bits & (1 << row) != 0);
+        }
+    }
+    true
+}
+
+/// Number of resets since power-on, wrapping around, as of the last call to
+/// [`restore`].
+pub fn resets() -> u32 {
+    // Safety: as in `restore`.
+    unsafe { RESETS[0] }
+}
@@ -0,0 +1,65 @@
+//! A pattern on the status LED after power-on that shows how startup went.
+//!
+//! Before the LED shows anything else, it flashes once if the settings were
+//! loaded from flash, twice if the defaults are in use because nothing intact
+//! was stored, or three times if they are because the keyboard booted in safe
+//! mode; see [`crate::safe_mode`]. After a pause, it stays lit if the host has
+//! configured the keyboard, or flickers if it hasn't, so a bad cable or a port
+//! that doesn't enumerate the keyboard stands out. Both halves are scanned by the
+//! same MCU, so there's no link between them to report.
+
+/// Milliseconds that each flash lasts.
+const FLASH_MS: u32 = 150;
+
+/// Milliseconds from the start of one flash to the start of the next.
+const FLASH_PERIOD_MS: u32 = 400;
+
+/// Milliseconds between the last flash and showing the state of USB.
+const PAUSE_MS: u32 = 400;
+
+/// Milliseconds after power-on that the pattern ends.
+const END_MS: u32 = 2000;
+
+/// Milliseconds that the LED is on, then off, while flickering.
+const FLICKER_MS: u32 = 50;
+
+/// Where the settings in use at startup came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loaded {
+    /// They were loaded from flash.
+    Stored,
+    /// They're the defaults, as nothing intact was stored.
+    Defaults,
+    /// They're the defaults, as the keyboard booted in safe mode.
+    SafeMode,
+}
+
+/// The startup pattern.
+pub struct Startup {
+    /// Number of flashes, from 1 to 3, in the order of `Loaded`
+    flashes: u32,
+}
+
+impl Startup {
+    /// The pattern for where the settings came from.
+    pub fn new(loaded: Loaded) -> Self {
+        Self {
+            flashes: loaded as u32 + 1,
+        }
+    }
+
+    /// Whether the LED should be lit, `ms` milliseconds after power-on, or
+    /// `None` once the pattern has ended.
+    pub fn led(&self, ms: u32, configured: bool) -> Option<bool> {
+        let usb_ms = self.flashes * FLASH_PERIOD_MS + PAUSE_MS;
+        if ms >= END_MS {
+            None
+        } else if ms < self.flashes * FLASH_PERIOD_MS {
+            Some(ms % FLASH_PERIOD_MS < FLASH_MS)
+        } else if ms < usb_ms {
+            Some(false)
+        } else {
+            Some(configured || ms / FLICKER_MS % 2 == 0)
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Typing a line about the firmware and its settings, for checking a keyboard
+//! without any host tools.
+//!
+//! The `Status` action types something like
+//! `dmote-fw 0.1.0 profile 0 debounce 50/50ms `, as though each key were tapped
+//! in turn. Each key is reported for [`TAP_SCANS`], then nothing is for as
+//! long, so that the host sees a repeated character as two presses. The
+//! wiring test types the positions of keys the same way; see
+//! [`crate::wiring`].
+
+use core::fmt::{self, Write};
+
+use heapless::Vec;
+
+use crate::chord::TAP_SCANS;
+use crate::key_code::KeyCode::{self, *};
+use crate::settings::Profiles;
+
+const LETTERS: [KeyCode; 26] = [
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+];
+
+const DIGITS: [KeyCode; 10] = [Kb0, Kb1, Kb2, Kb3, Kb4, Kb5, Kb6, Kb7, Kb8, Kb9];
+
+/// The status line being typed, if any.
+#[derive(Default)]
+pub struct Status {
+    /// The keys of the line
+    keys: Vec<KeyCode, 64>,
+    /// Index of the key being typed
+    next: usize,
+    /// Number of scans since the key started being typed
+    scans: u32,
+}
+
+impl Status {
+    /// Start typing the status line, unless it's already being typed.
+    pub fn start(&mut self, profiles: &Profiles) {
+        if !self.keys.is_empty() {
+            return;
+        }
+        let settings = profiles.settings();
+        // The line fits, but if it ever doesn't, it's cut short.
+        let _ = write!(
+            self,
+            "dmote-fw {} profile {} debounce {}/{}ms ",
+            env!("CARGO_PKG_VERSION"),
+            profiles.active_id(),
+            settings.debounce,
+            settings.debounce_right,
+        );
+    }
+
+    /// The key to report in this scan, if any.
+    pub fn tap(&mut self) -> Option<KeyCode> {
+        let kc = *self.keys.get(self.next)?;
+        let pressed = self.scans < TAP_SCANS;
+        self.scans += 1;
+        if self.scans == 2 * TAP_SCANS {
+            self.scans = 0;
+            self.next += 1;
+            if self.next == self.keys.len() {
+                self.keys.clear();
+                self.next = 0;
+            }
+        }
+        if pressed {
+            Some(kc)
+        } else {
+            None
+        }
+    }
+}
+
+impl Write for Status {
+    /// Add the keys that type the string, skipping any character that isn't
+    /// a lower case letter, a digit, or one of " ./-".
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let kc = match c {
+                'a'..='z' => LETTERS[c as usize - 'a' as usize],
+                '0'..='9' => DIGITS[c as usize - '0' as usize],
+                ' ' => Space,
+                '.' => Dot,
+                '/' => Slash,
+                '-' => Minus,
+                _ => continue,
+            };
+            self.keys.push(kc).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! What held keys do when the layer changes under them.
+//!
+//! By default, a key does what it did in the layer that was active when it was
+//! pressed, for as long as it's held: letting go of a layer key before the keys
+//! pressed with it doesn't turn them into other keys, and pressing one doesn't
+//! release the keys already held. With `follow_layers` in the settings, held
+//! keys do what they do in the active layer instead, changing as soon as it
+//! does, which is what a held note wants when a layer key shifts the octave, or
+//! a held movement key when a layer key switches what it moves.
+
+use crate::action::Layout;
+
+/// The layer that each key was pressed in.
+pub struct Sticky<const R: usize, const C: usize> {
+    /// A layer for each row of each column
+    layers: [[u8; R]; C],
+}
+
+impl<const R: usize, const C: usize> Default for Sticky<R, C> {
+    fn default() -> Self {
+        Self {
+            layers: [[0; R]; C],
+        }
+    }
+}
+
+impl<const R: usize, const C: usize> Sticky<R, C> {
+    /// Note that the key at `(row, col)` was pressed in `layer`.
+    pub fn press(&mut self, row: usize, col: usize, layer: usize) {
+        if let Some(pressed_in) = self.layers.get_mut(col).and_then(|c| c.get_mut(row)) {
+            *pressed_in = layer as u8;
+        }
+    }
+
+    /// The layout that the key at `(row, col)` does what it does in, while
+    /// `active` is the active layer of `layers`.
+    pub fn layout<'a>(
+        &self,
+        layers: &[&'a Layout<R, C>],
+        active: usize,
+        follow: bool,
+        row: usize,
+        col: usize,
+    ) -> &'a Layout<R, C> {
+        let layer = match self.layers.get(col).and_then(|c| c.get(row)) {
+            Some(&pressed_in) if !follow => pressed_in as usize,
+            _ => active,
+        };
+        layers.get(layer).copied().unwrap_or(layers[0])
+    }
+
+    /// Build a layout holding what each key does, as by [`Sticky::layout`], for
+    /// building reports from. Only the held keys are worth looking up in it.
+    pub fn resolve(&self, layers: &[&Layout<R, C>], active: usize, follow: bool) -> Layout<R, C> {
+        let mut resolved = *self.layout(layers, active, true, 0, 0);
+        if !follow {
+            for (row, actions) in resolved.iter_mut().enumerate() {
+                for (col, act) in actions.iter_mut().enumerate() {
+                    *act = self.layout(layers, active, false, row, col)[row][col];
+                }
+            }
+        }
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Action::{self, KeyCode as K};
+    use crate::key_code::KeyCode::*;
+
+    /// A base layer with a layer key at (0, 1), for the layer above it.
+    const BASE: Layout<2, 2> = [[K(A), Action::Layer(1)], [K(B), K(C)]];
+    const ABOVE: Layout<2, 2> = [[K(Kb1), Action::Layer(1)], [K(Kb2), K(Kb3)]];
+
+    #[test]
+    fn held_keys_keep_the_layer_they_were_pressed_in() {
+        let layers = [&BASE, &ABOVE];
+        let mut sticky = Sticky::default();
+        // A is held while the layer key is pressed.
+        sticky.press(0, 0, 0);
+        sticky.press(0, 1, 0);
+        assert_eq!(sticky.resolve(&layers, 1, false)[0][0], K(A));
+        // 2 is held while the layer key is released.
+        sticky.press(1, 0, 1);
+        assert_eq!(sticky.resolve(&layers, 0, false)[1][0], K(Kb2));
+        assert_eq!(sticky.layout(&layers, 0, false, 1, 0)[1][0], K(Kb2));
+        // Pressed again, it's what it is in the active layer.
+        sticky.press(1, 0, 0);
+        assert_eq!(sticky.resolve(&layers, 0, false)[1][0], K(B));
+    }
+
+    #[test]
+    fn held_keys_follow_the_layer_with_follow_layers() {
+        let layers = [&BASE, &ABOVE];
+        let mut sticky = Sticky::default();
+        sticky.press(0, 0, 0);
+        sticky.press(0, 1, 0);
+        assert_eq!(sticky.resolve(&layers, 1, true)[0][0], K(Kb1));
+        sticky.press(1, 0, 1);
+        assert_eq!(sticky.resolve(&layers, 0, true)[1][0], K(B));
+        assert_eq!(sticky.layout(&layers, 0, true, 1, 0)[1][0], K(B));
+    }
+
+    #[test]
+    fn keys_pressed_in_a_missing_layer_are_in_the_base_layer() {
+        let layers = [&BASE, &ABOVE];
+        let mut sticky = Sticky::default();
+        sticky.press(1, 1, 5);
+        assert_eq!(sticky.resolve(&layers, 1, false)[1][1], K(C));
+    }
+}
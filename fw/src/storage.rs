@@ -0,0 +1,123 @@
+//! Persistent storage, in the last page of flash.
+//!
+//! `memory.x` leaves this page out of the FLASH region, so the linker will
+//! never place code or constants in it.
+
+use stm32f1xx_hal::flash::{self, FlashSize, FlashWriter, SectorSize};
+
+use crate::calibrate::Calibration;
+use crate::disable::Disabled;
+use crate::reroute::Reroutes;
+use crate::schedule::Schedule;
+use crate::settings::Profiles;
+
+/// Offset, from the start of flash, of the page reserved for settings.
+const SETTINGS_OFFSET: u32 = 63 * 1024;
+
+/// Size of a flash page; the smallest unit that may be erased.
+const PAGE_SIZE: usize = 1024;
+
+/// Marks the settings page as written by this firmware, rather than left
+/// erased or holding something else. The last byte is bumped whenever the
+/// layout of the record changes, so that an older record is ignored rather
+/// than misread. A value is never used again, not even the first, `DMTE`,
+/// which went on to `DMT1`: a record of one layout would pass for the other.
+const MAGIC: [u8; 4] = *b"DMTG";
+
+/// Length of the CRC that ends the record.
+const CRC_LEN: usize = 2;
+
+/// Length of the stored record. Flash is written 16 bits at a time, so this is
+/// rounded up to an even number of bytes.
+const RECORD_LEN: usize = (MAGIC.len() + BODY_LEN + CRC_LEN + 1) & !1;
+
+/// Length of the part of the record between the magic and the CRC: the
+/// profiles, then the reroutes, then the disabled keys, then the calibration of
+/// the analog keys, then the schedule.
+const BODY_LEN: usize =
+    Profiles::SIZE + Reroutes::SIZE + Disabled::SIZE + Calibration::SIZE + Schedule::SIZE;
+
+/// CRC-16/CCITT-FALSE of `bytes`.
+///
+/// A write that's interrupted, by a reset or losing power, leaves the record
+/// partly erased or partly written. The CRC catches that, so that the built in
+/// profiles are used instead of whatever the record happens to contain.
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Reads and writes settings stored in flash.
+pub struct Storage(flash::Parts);
+
+impl Storage {
+    pub fn new(flash: flash::Parts) -> Self {
+        Self(flash)
+    }
+
+    fn writer(&mut self) -> FlashWriter {
+        self.0.writer(SectorSize::Sz1K, FlashSize::Sz64K)
+    }
+
+    /// Load the profiles, reroutes, disabled keys, calibration and schedule
+    /// stored in flash, if there are any and they're intact.
+    pub fn load(&mut self) -> Option<(Profiles, Reroutes, Disabled, Calibration, Schedule)> {
+        let writer = self.writer();
+        let record = writer.read(SETTINGS_OFFSET, RECORD_LEN).ok()?;
+        let (checked, crc) = record.split_at(MAGIC.len() + BODY_LEN);
+        let (magic, body) = checked.split_at(MAGIC.len());
+        if magic != MAGIC || crc[..CRC_LEN] != crc16(checked).to_le_bytes() {
+            return None;
+        }
+        let (profiles, rest) = body.split_at(Profiles::SIZE);
+        let (reroutes, rest) = rest.split_at(Reroutes::SIZE);
+        let (disabled, rest) = rest.split_at(Disabled::SIZE);
+        let (calibration, schedule) = rest.split_at(Calibration::SIZE);
+        Some((
+            Profiles::from_bytes(profiles)?,
+            Reroutes::from_bytes(reroutes)?,
+            Disabled::from_bytes(disabled)?,
+            Calibration::from_bytes(calibration)?,
+            Schedule::from_bytes(schedule)?,
+        ))
+    }
+
+    /// Store the profiles, reroutes, disabled keys, calibration and schedule in
+    /// flash, replacing whatever was there.
+    ///
+    /// NOTE: This stalls the CPU while the page is erased and written, around
+    /// 20ms. The DMA keeps scanning during that time, but no scans are processed.
+    pub fn save(
+        &mut self,
+        profiles: &Profiles,
+        reroutes: &Reroutes,
+        disabled: &Disabled,
+        calibration: &Calibration,
+        schedule: &Schedule,
+    ) -> flash::Result<()> {
+        let mut record = [0; RECORD_LEN];
+        record[..MAGIC.len()].copy_from_slice(&MAGIC);
+        let reroutes_start = MAGIC.len() + Profiles::SIZE;
+        record[MAGIC.len()..reroutes_start].copy_from_slice(&profiles.to_bytes());
+        let disabled_start = reroutes_start + Reroutes::SIZE;
+        record[reroutes_start..disabled_start].copy_from_slice(&reroutes.to_bytes());
+        let calibration_start = disabled_start + Disabled::SIZE;
+        record[disabled_start..calibration_start].copy_from_slice(&disabled.to_bytes());
+        let schedule_start = calibration_start + Calibration::SIZE;
+        record[calibration_start..schedule_start].copy_from_slice(&calibration.to_bytes());
+        let checked = schedule_start + Schedule::SIZE;
+        record[schedule_start..checked].copy_from_slice(&schedule.to_bytes());
+        let crc = crc16(&record[..checked]);
+        record[checked..checked + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+        let mut writer = self.writer();
+        writer.erase(SETTINGS_OFFSET, PAGE_SIZE)?;
+        writer.write(SETTINGS_OFFSET, &record)
+    }
+}
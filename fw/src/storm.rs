@@ -0,0 +1,96 @@
+//! Protection against storms of presses from a failing switch or matrix.
+//!
+//! A switch that's failing, or a short in the matrix, may bounce for longer
+//! than any debounce timeout, and type hundreds of keys a second. When more
+//! presses than anyone types are seen within a second, presses are throttled:
+//! new presses are dropped, keys that were already held stay held, and the
+//! status LED blinks. Throttling stops after a second with few enough presses.
+
+use shared_types::{DebState, KeyState, PressRelease};
+
+use crate::scan::Log;
+
+/// Number of scans in the window that presses are counted over, 1 second.
+const WINDOW_SCANS: u32 = 2000;
+
+/// Number of presses in a window beyond which presses are throttled. Typing
+/// quickly is about 15 presses a second.
+const LIMIT: u32 = 100;
+
+/// Counts presses, and drops them once there are too many.
+pub struct Storm<const C: usize> {
+    /// The keys held at the last scan, with a bit per row for each column.
+    held: [u16; C],
+    /// The keys whose presses were dropped, until they're released.
+    dropped: [u16; C],
+    /// The time the current window started.
+    window_start: u32,
+    /// Number of presses in the current window.
+    presses: u32,
+    throttled: bool,
+    /// Number of times presses started being throttled, wrapping around.
+    throttles: u32,
+}
+
+impl<const C: usize> Storm<C> {
+    /// Start counting, taking the keys in `held` as already held.
+    pub fn new(held: &[u16; C], now: u32) -> Self {
+        Self {
+            held: *held,
+            dropped: [0; C],
+            window_start: now,
+            presses: 0,
+            throttled: false,
+            throttles: 0,
+        }
+    }
+
+    /// Are presses being throttled?
+    pub fn throttled(&self) -> bool {
+        self.throttled
+    }
+
+    /// Number of times presses started being throttled, wrapping around.
+    pub fn throttles(&self) -> u32 {
+        self.throttles
+    }
+
+    /// Filter the keys held according to the debouncer, returning them without
+    /// the keys pressed while throttling. The start of throttling is logged.
+    pub fn filter(&mut self, held: &[u16; C], now: u32, log: &mut Log) -> [u16; C] {
+        if now.wrapping_sub(self.window_start) >= WINDOW_SCANS {
+            self.throttled = self.presses > LIMIT;
+            self.presses = 0;
+            self.window_start = now;
+        }
+        let mut filtered = *held;
+        for (col, ((val, last), dropped)) in filtered
+            .iter_mut()
+            .zip(self.held.iter_mut())
+            .zip(self.dropped.iter_mut())
+            .enumerate()
+        {
+            let pressed = *val & !*last;
+            *last = *val;
+            self.presses += pressed.count_ones();
+            if !self.throttled && self.presses > LIMIT {
+                self.throttled = true;
+                self.throttles = self.throttles.wrapping_add(1);
+                log.log(KeyState {
+                    timestamp: now,
+                    row: pressed.trailing_zeros() as u8,
+                    col: col as u8,
+                    deb: DebState::StableD,
+                    event: PressRelease::Throttle,
+                });
+            }
+            if self.throttled {
+                *dropped |= pressed;
+            }
+            // Once a key is released, it may be pressed again.
+            *dropped &= *val;
+            *val &= !*dropped;
+        }
+        filtered
+    }
+}
@@ -0,0 +1,100 @@
+//! A diagnostic that finds how long each column needs to settle.
+//!
+//! Once a column is strobed, its rows take a while to settle, longer with more
+//! capacitance in the matrix. The rows are read 3/5 of the way through each
+//! column, which leaves plenty of time at the usual scan rate, but not
+//! necessarily at a faster one. The sweep shortens the settle time step by
+//! step, and at each step compares the rows read with the keys held, while
+//! some keys are held down. The shortest settle time at which a column, and
+//! every longer one, read without error is that column's minimum reliable
+//! settle time.
+//!
+//! Keys that are pressed or released during the sweep show up as errors, so
+//! the keys held must stay held until the sweep is done, which takes about 2
+//! seconds at the usual scan rate. The debouncer doesn't see the scans of the
+//! sweep, so nothing is typed while it runs.
+
+/// The longest settle time tried, in twentieths of a column.
+const LONGEST: u8 = 19;
+
+/// The shortest settle time tried. With the `deglitch` feature, the early row
+/// read is fixed at 4/5 of the column, so a column must be strobed before it.
+#[cfg(not(feature = "deglitch"))]
+const SHORTEST: u8 = 1;
+#[cfg(feature = "deglitch")]
+const SHORTEST: u8 = 4;
+
+/// Number of scans at each settle time.
+const STEP_SCANS: u32 = 200;
+
+/// Number of scans at the start of each step that aren't checked, as they may
+/// have been made, at least in part, with the previous settle time.
+const SKIP_SCANS: u32 = 2;
+
+/// The state of a sweep in progress.
+pub struct Sweep {
+    /// The settle time being tried.
+    settle: u8,
+    /// Number of scans made at this settle time.
+    scans: u32,
+    /// A bit for each column that read wrongly at this settle time.
+    errors: u8,
+    /// A bit for each column that read wrongly at any settle time so far.
+    failed: u8,
+    /// The shortest settle time at which each column read correctly so far,
+    /// or 0 if it never has.
+    reliable: [u8; 6],
+}
+
+impl Default for Sweep {
+    fn default() -> Self {
+        Self {
+            settle: LONGEST,
+            scans: 0,
+            errors: 0,
+            failed: 0,
+            reliable: [0; 6],
+        }
+    }
+}
+
+impl Sweep {
+    /// The settle time that scans should be made with.
+    pub fn settle(&self) -> u8 {
+        self.settle
+    }
+
+    /// Check the rows read in a scan against the keys held, which has a bit
+    /// per row for each column.
+    ///
+    /// Returns the minimum reliable settle time of each column, in twentieths
+    /// of a column, once the sweep is done. A column that didn't read correctly
+    /// even at the longest settle time is 0.
+    pub fn check(&mut self, scanout_half: &[u16; 6], held: &[u16; 6]) -> Option<[u8; 6]> {
+        if self.scans >= SKIP_SCANS {
+            for (col, (rows, held)) in scanout_half.iter().zip(held.iter()).enumerate() {
+                // The rows are read from PB3..=PB15.
+                if (rows >> 3) ^ held != 0 {
+                    self.errors |= 1 << col;
+                }
+            }
+        }
+        self.scans += 1;
+        if self.scans < STEP_SCANS {
+            return None;
+        }
+        self.failed |= self.errors;
+        for (col, reliable) in self.reliable.iter_mut().enumerate() {
+            if self.failed & (1 << col) == 0 {
+                *reliable = self.settle;
+            }
+        }
+        if self.settle <= SHORTEST {
+            return Some(self.reliable);
+        }
+        self.settle -= 1;
+        self.scans = 0;
+        self.errors = 0;
+        None
+    }
+}
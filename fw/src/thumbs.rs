@@ -0,0 +1,80 @@
+//! The DMOTE's thumb keys, by where they sit rather than how they're wired.
+//!
+//! The thumb clusters, and the four keys between them, share three rows of the
+//! matrix, PB8 to PB10, and each cluster zigzags across them, so writing their
+//! keys into those rows means looking up every one in the diagram. [`place`]
+//! does the looking up instead: a layout leaves those rows empty, and lists the
+//! thumb keys by cluster, column and height, as drawn:
+//!
+//! ```text
+//!  Thumb Cluster  Last Middle key  Thumb Cluster
+//!      +---+       +---+   +---+       +---+
+//!  +---+ m +---+   | 0 |   | 1 |   +---+ m +---+
+//!  | l +---+ r |   +---+   +---+   | l +---+ r |
+//!  +---+ m +---+                   +---+ m +---+
+//!  | l +---+ r |     Face keys     | l +---+ r |
+//!  +---+ m +---+   +---+   +---+   +---+ m +---+
+//!      +---+       | 0 |   | 1 |       +---+
+//!                  +---+   +---+
+//! ```
+//!
+//! Each column of a cluster lists its keys from the top down.
+
+use crate::action::{Action, Layout};
+
+/// Row of the layout that's wired to PB8.
+const PB8: usize = 5;
+
+/// Row of the layout that's wired to PB9.
+const PB9: usize = 6;
+
+/// Row of the layout that's wired to PB10.
+const PB10: usize = 7;
+
+/// The keys of a thumb cluster, by column, as drawn.
+pub struct Cluster {
+    pub left: [Action; 2],
+    pub middle: [Action; 3],
+    pub right: [Action; 2],
+}
+
+/// Every thumb key.
+pub struct Thumbs {
+    /// The cluster wired to columns 0 to 2, drawn on the left
+    pub right_thumb: Cluster,
+    /// The cluster wired to columns 3 to 5, drawn on the right
+    pub left_thumb: Cluster,
+    /// The keys between the clusters, at the top, from the left
+    pub middle: [Action; 2],
+    /// The keys between the clusters, at the bottom, from the left
+    pub face: [Action; 2],
+}
+
+/// Put a cluster into `layout`, on the given columns, from the one that's
+/// wired to its top key and first key of each side.
+const fn place_cluster(
+    mut layout: Layout<13, 6>,
+    cluster: Cluster,
+    cols: [usize; 3],
+) -> Layout<13, 6> {
+    let [first, second, third] = cols;
+    layout[PB9][first] = cluster.middle[0];
+    layout[PB9][second] = cluster.middle[1];
+    layout[PB9][third] = cluster.middle[2];
+    layout[PB10][first] = cluster.left[0];
+    layout[PB10][second] = cluster.left[1];
+    layout[PB8][first] = cluster.right[0];
+    layout[PB8][second] = cluster.right[1];
+    layout
+}
+
+/// Put the thumb keys into the rows of `layout` that they're wired to.
+pub const fn place(layout: Layout<13, 6>, thumbs: Thumbs) -> Layout<13, 6> {
+    let mut layout = place_cluster(layout, thumbs.right_thumb, [0, 1, 2]);
+    layout = place_cluster(layout, thumbs.left_thumb, [5, 4, 3]);
+    layout[PB8][2] = thumbs.middle[0];
+    layout[PB8][3] = thumbs.middle[1];
+    layout[PB10][2] = thumbs.face[0];
+    layout[PB10][3] = thumbs.face[1];
+    layout
+}
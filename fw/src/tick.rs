@@ -0,0 +1,314 @@
+//! The scan interrupt, which debounces each scan and hands it to the main loop.
+//!
+//! The interrupt does only what must happen on every scan, at a steady pace:
+//! filtering and debouncing the rows. The keys held after that are queued for
+//! the main loop, which resolves layouts and builds reports at a lower priority,
+//! however long those take as more features are added.
+//!
+//! Each entry of the queue holds every key that's held, rather than the keys
+//! that changed, so if the main loop falls behind and the queue fills, the scans
+//! that don't fit are dropped without losing a press or release.
+//!
+//! While no key has bounced for a while, the matrix is scanned at a quarter of
+//! the usual rate, to save power, and the first key that bounces brings it back
+//! up. The time is still counted in scans at the usual rate, so that timeouts
+//! last as long either way.
+//!
+//! The interrupt is the only writer of the debouncer's log. With `report-log`,
+//! the main loop queues the changes to the keyboard report for it to write.
+
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+
+#[cfg(feature = "report-log")]
+use heapless::spsc::Consumer;
+use heapless::spsc::{Producer, Queue};
+use shared_types::{DebState, KeyState, PressRelease};
+use stm32f1xx_hal::time::Hertz;
+
+#[cfg(feature = "encoders")]
+use crate::encoder::Encoders;
+use crate::grace::Grace;
+use crate::key_code::KbHidReport;
+use crate::rate::ScanRate;
+use crate::scan::{self, scan, Debounce, Log, MatrixScanner, ReportToken, DEFAULT_SETTLE, STROBES};
+use crate::snapshot;
+use crate::storm::Storm;
+use crate::sweep::Sweep;
+use crate::trigger::{Mode, QuickDraw};
+
+/// Capacity of the queue, plus one.
+pub const QUEUE_LEN: usize = 8;
+
+/// The queue from the scan interrupt to the main loop.
+pub type ScanQueue = Queue<Scanned, QUEUE_LEN>;
+
+/// Capacity of the queue of report changes, plus one.
+#[cfg(feature = "report-log")]
+pub const REPORT_LOG_LEN: usize = 32;
+
+/// The queue of report changes from the main loop to the scan interrupt, as
+/// they're written into the log.
+#[cfg(feature = "report-log")]
+pub type ReportLogQueue = Queue<KeyState, REPORT_LOG_LEN>;
+
+/// The main loop's end of the queue of report changes. Without `report-log`,
+/// it's empty, and the changes aren't logged.
+pub struct ReportLog {
+    #[cfg(feature = "report-log")]
+    pub queue: Producer<'static, KeyState, REPORT_LOG_LEN>,
+}
+
+impl ReportLog {
+    /// Queue the key codes that `report`, built in the scan at `built`, adds
+    /// to, or removes from, `previous`.
+    pub fn changes(&mut self, report: &KbHidReport, previous: &KbHidReport, built: u32) {
+        #[cfg(feature = "report-log")]
+        for (usage, added) in report.changes(previous) {
+            // If the queue is full, the interrupt has fallen far behind, and
+            // the change is left out of the log.
+            let _ = self.queue.enqueue(KeyState {
+                timestamp: built,
+                row: usage,
+                col: 0,
+                deb: DebState::StableU,
+                event: if added {
+                    PressRelease::ReportAdd
+                } else {
+                    PressRelease::ReportRemove
+                },
+            });
+        }
+        #[cfg(not(feature = "report-log"))]
+        let _ = (report, previous, built);
+    }
+}
+
+/// How many times slower the matrix is scanned while idle.
+const IDLE_DIVIDER: u32 = 4;
+
+/// Number of scans, at the usual rate, after the last key bounced before the
+/// matrix is scanned at the idle rate.
+const IDLE_SCANS: u32 = 1000;
+
+/// The debounce timeout of the active profile, in scans, set by the main loop.
+pub static DEBOUNCE: AtomicU8 = AtomicU8::new(0);
+
+/// The debounce timeout of the right half in the active profile, in scans, set
+/// by the main loop.
+pub static DEBOUNCE_RIGHT: AtomicU8 = AtomicU8::new(0);
+
+/// The disabled keys, with a bit per row for each column, set by the main
+/// loop; see [`crate::disable`].
+pub static DISABLED: [AtomicU16; 6] = [ENABLED, ENABLED, ENABLED, ENABLED, ENABLED, ENABLED];
+
+const ENABLED: AtomicU16 = AtomicU16::new(0);
+
+/// Is the debouncer deferring changes until keys are stable, rather than
+/// reporting them eagerly? Set by the main loop; see [`Mode`].
+pub static DEFERRED: AtomicBool = AtomicBool::new(false);
+
+/// Set by the main loop to write a marker, with the id in [`MARKER_ID`], into
+/// the log; see [`crate::action::Action::LogMarker`].
+pub static MARKER: AtomicBool = AtomicBool::new(false);
+
+/// The id of the marker to write into the log.
+pub static MARKER_ID: AtomicU8 = AtomicU8::new(0);
+
+/// Set by the main loop to start a settle time sweep; see [`crate::sweep`].
+pub static SWEEP: AtomicBool = AtomicBool::new(false);
+
+/// A scan, as debounced by the interrupt.
+pub struct Scanned {
+    /// The time of the scan, counted in scans at the usual rate.
+    pub now: u32,
+    /// The keys held, with a bit per row for each column.
+    pub held: [u16; 6],
+    /// Are presses being throttled? See [`crate::storm`].
+    pub throttled: bool,
+    /// Number of times presses started being throttled.
+    pub throttles: u32,
+    /// Number of scans dropped because the queue was full.
+    pub missed: u32,
+    /// The interval between scans, as of this scan.
+    pub rate: ScanRate,
+    /// The results of the last settle time sweep.
+    pub settle: [u8; 6],
+    pub token: ReportToken,
+}
+
+/// Everything the scan interrupt owns.
+pub struct ScanTask {
+    pub scanner: MatrixScanner,
+    pub grace: Grace<6>,
+    pub debouncer: [[QuickDraw; 13]; 6],
+    pub log: &'static mut Log,
+    pub storm: Storm<6>,
+    pub rate: ScanRate,
+    /// The keys of the right half; see `settings::Settings::debounce_right`.
+    pub right_half: &'static [u16; 6],
+    pub queue: Producer<'static, Scanned, QUEUE_LEN>,
+    /// Changes to the keyboard report, to write into the log.
+    #[cfg(feature = "report-log")]
+    pub report_log: Consumer<'static, KeyState, REPORT_LOG_LEN>,
+    /// The time, counted in scans at the usual rate.
+    pub now: u32,
+    /// The usual scan rate.
+    pub freq: Hertz,
+    /// Is the matrix being scanned at the idle rate?
+    pub idle: bool,
+    /// When a key last bounced.
+    pub active: u32,
+    /// The debounce mode of the last scan.
+    pub mode: Mode,
+    /// The settle time sweep in progress, if any.
+    pub sweep: Option<Sweep>,
+    /// The results of the last settle time sweep.
+    pub settle: [u8; 6],
+    /// Number of scans dropped because the queue was full, wrapping around.
+    pub missed: u32,
+    /// The rotary encoders in the matrix.
+    #[cfg(feature = "encoders")]
+    pub encoders: Encoders<2>,
+}
+
+impl ScanTask {
+    /// Debounce the scan that's completed, if any, and queue it for the main
+    /// loop.
+    pub fn run(&mut self) {
+        let scanout_half = match self.scanner.read() {
+            Some(scanout_half) => scanout_half,
+            None => return,
+        };
+        let elapsed = if self.idle { IDLE_DIVIDER } else { 1 };
+        self.now = self.now.wrapping_add(elapsed);
+        if !self.idle {
+            self.rate.tick();
+        }
+        #[cfg(feature = "report-log")]
+        while let Some(change) = self.report_log.dequeue() {
+            self.log.log(change);
+        }
+        if SWEEP.swap(false, Ordering::Relaxed) {
+            self.sweep = Some(Sweep::default());
+            self.set_idle(false);
+        }
+        if let Some(sweep) = self.sweep.as_mut() {
+            let held = scan::held(&self.debouncer, self.mode);
+            match sweep.check(&scanout_half, &held) {
+                Some(settle) => {
+                    self.settle = settle;
+                    self.sweep = None;
+                    self.scanner.set_settle(DEFAULT_SETTLE);
+                }
+                None => self.scanner.set_settle(sweep.settle()),
+            }
+            return;
+        }
+        let mode = if DEFERRED.load(Ordering::Relaxed) {
+            Mode::Deferred
+        } else {
+            Mode::Eager
+        };
+        if mode != self.mode {
+            self.mode = mode;
+            self.log.log(KeyState {
+                timestamp: self.now,
+                row: 0,
+                col: 0,
+                deb: DebState::StableU,
+                event: match mode {
+                    Mode::Eager => PressRelease::EagerDebounce,
+                    Mode::Deferred => PressRelease::DeferredDebounce,
+                },
+            });
+        }
+        if MARKER.swap(false, Ordering::Acquire) {
+            self.log.log(KeyState {
+                timestamp: self.now,
+                row: MARKER_ID.load(Ordering::Relaxed),
+                col: 0,
+                deb: DebState::StableU,
+                event: PressRelease::Marker,
+            });
+        }
+        let mut scanout_half = scanout_half;
+        #[cfg(feature = "encoders")]
+        if self.encoders.update(&mut scanout_half, self.now) {
+            // Quadrature is only decoded reliably at the usual rate.
+            self.active = self.now;
+            self.set_idle(false);
+        }
+        for (bits, disabled) in scanout_half.iter_mut().zip(DISABLED.iter()) {
+            *bits &= !(disabled.load(Ordering::Relaxed) << 3);
+        }
+        let scanout_half = self.grace.filter(&scanout_half);
+        let debounce = Debounce {
+            mode,
+            timeout: DEBOUNCE.load(Ordering::Relaxed),
+            right_timeout: DEBOUNCE_RIGHT.load(Ordering::Relaxed),
+            right_half: self.right_half,
+        };
+        let token = scan(
+            &scanout_half,
+            &mut self.debouncer,
+            self.log,
+            self.now,
+            &debounce,
+        );
+        snapshot::save(&self.debouncer);
+        self.adapt();
+        #[cfg_attr(not(feature = "encoders"), allow(unused_mut))]
+        let mut held = self
+            .storm
+            .filter(&scan::held(&self.debouncer, mode), self.now, self.log);
+        #[cfg(feature = "encoders")]
+        for (bits, tapped) in held.iter_mut().zip(self.encoders.held(self.now).iter()) {
+            *bits |= tapped;
+        }
+        // If the queue is full, this scan is dropped, and the keys held in it
+        // are seen in the next one that fits.
+        let queued = self.queue.enqueue(Scanned {
+            now: self.now,
+            held,
+            throttled: self.storm.throttled(),
+            throttles: self.storm.throttles(),
+            missed: self.missed,
+            rate: self.rate,
+            settle: self.settle,
+            token,
+        });
+        if queued.is_err() {
+            self.missed = self.missed.wrapping_add(1);
+        }
+    }
+
+    /// Scan at the idle rate once no key has bounced for a while, and at the
+    /// usual rate as soon as one does.
+    fn adapt(&mut self) {
+        let bouncing = self
+            .debouncer
+            .iter()
+            .flatten()
+            .any(|key| matches!(key, QuickDraw::Bouncing { .. }));
+        if bouncing {
+            self.active = self.now;
+            self.set_idle(false);
+        } else if self.now.wrapping_sub(self.active) >= IDLE_SCANS {
+            self.set_idle(true);
+        }
+    }
+
+    fn set_idle(&mut self, idle: bool) {
+        if idle == self.idle {
+            return;
+        }
+        self.idle = idle;
+        self.rate.restart();
+        let freq = if idle {
+            Hertz(self.freq.0 / IDLE_DIVIDER)
+        } else {
+            self.freq
+        };
+        self.scanner.reconfigure(STROBES, freq);
+    }
+}
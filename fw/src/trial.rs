@@ -0,0 +1,49 @@
+//! Trying out a keymap from `keymap-patch` before keeping it.
+//!
+//! `keymap-patch` stages the new layers beside the running ones, and the
+//! keyboard swaps them in on its next scan, keeping the old layers aside. The
+//! new keymap is kept only once the key that was Enter, in the base layer of
+//! the old keymap, is pressed within [`TRIAL_SCANS`]; if it isn't, the old
+//! keymap is put back. So a keymap that can't be typed on, with no usable keys
+//! or the wrong board's layout, undoes itself, while confirming takes the same
+//! finger as it always did. An old keymap without Enter can't confirm anything,
+//! so every trial from it is undone.
+
+use crate::action::{Action, Layout};
+use crate::key_code::KeyCode;
+
+/// Number of scans that a new keymap has to be confirmed in; 30 seconds.
+const TRIAL_SCANS: u32 = 30 * 2000;
+
+/// A new keymap on trial.
+pub struct Trial {
+    /// When the keymap was swapped in, in scans
+    started: u32,
+    /// The position, as (row, col), of the key that keeps it, if any
+    confirm: Option<(usize, usize)>,
+}
+
+impl Trial {
+    /// Start trying out a new keymap at `now`, in place of one with the given
+    /// base layer.
+    pub fn start<const R: usize, const C: usize>(old: &Layout<R, C>, now: u32) -> Self {
+        let enter = Action::KeyCode(KeyCode::Enter);
+        let confirm = (0..R)
+            .flat_map(|row| (0..C).map(move |col| (row, col)))
+            .find(|&(row, col)| old[row][col] == enter);
+        Self {
+            started: now,
+            confirm,
+        }
+    }
+
+    /// Whether pressing the key at `(row, col)` keeps the new keymap.
+    pub fn confirms(&self, row: usize, col: usize) -> bool {
+        self.confirm == Some((row, col))
+    }
+
+    /// Whether the trial is over, without the new keymap having been kept.
+    pub fn expired(&self, now: u32) -> bool {
+        now.wrapping_sub(self.started) >= TRIAL_SCANS
+    }
+}
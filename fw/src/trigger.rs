@@ -142,7 +142,7 @@ use shared_types::DebState;
 /// names. Since Stable only has one arugemnt, it's pretty clear how it should
 /// be used.
 #[derive(Clone, Copy, PartialEq)]
-pub enum QuickDraw<const T: u8> {
+pub enum QuickDraw {
     /// The key is stable at the contained state
     Stable(bool),
     /// The key is bouncing
@@ -156,13 +156,25 @@ pub enum QuickDraw<const T: u8> {
     },
 }
 
-impl<const T: u8> Default for QuickDraw<T> {
+/// How the state of a trigger decides whether its key is pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Report a change as soon as it's seen, then ignore the bounces that
+    /// follow, as described on [`QuickDraw`].
+    Eager,
+    /// Report a change only once the key has been stable for the timeout, as
+    /// a classic debouncer does. This uses the same states, but a key that's
+    /// bouncing stays as it was before the bouncing began.
+    Deferred,
+}
+
+impl Default for QuickDraw {
     fn default() -> Self {
         QuickDraw::Stable(false)
     }
 }
 
-impl<const T: u8> QuickDraw<T> {
+impl QuickDraw {
     pub fn state_name(&self) -> DebState {
         use DebState::*;
         match self {
@@ -199,10 +211,22 @@ impl<const T: u8> QuickDraw<T> {
         }
     }
 
+    /// Is this state associated with a pressed key, when debouncing in the
+    /// given mode?
+    pub fn is_pressed_in(&self, mode: Mode) -> bool {
+        match (mode, self) {
+            (Mode::Eager, _) => self.is_pressed(),
+            (Mode::Deferred, QuickDraw::Stable(pressed)) => *pressed,
+            (Mode::Deferred, QuickDraw::Bouncing { prior, .. }) => *prior,
+        }
+    }
+
     /// Step the state machine
     ///
     /// The state machine progresses as described  in the struct documentation.
-    pub fn step(&mut self, state: bool, now: u8) {
+    /// The stable timeout, S in the state diagrams, is reached once `timeout`
+    /// ticks of `now` have passed without a bounce.
+    pub fn step(&mut self, state: bool, now: u8, timeout: u8) {
         let next_state = match self {
             QuickDraw::Stable(prior) => {
                 if state != *prior {
@@ -232,7 +256,7 @@ impl<const T: u8> QuickDraw<T> {
                         current: state,
                         since: now,
                     }
-                } else if now.wrapping_sub(*since) < T {
+                } else if now.wrapping_sub(*since) < timeout {
                     // no bounce happened, and we are not yet stable. Nothing
                     // happens here.
                     //
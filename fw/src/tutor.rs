@@ -0,0 +1,51 @@
+//! A training mode that stops some key codes from being reported.
+//!
+//! Blocking the arrow keys, for example, forces the habit of using the arrows
+//! on a layer instead. The key codes that are blocked may be changed by the
+//! host, and are only blocked while the mode is on.
+
+use crate::key_code::KeyCode;
+
+/// The key codes to block, and whether they're being blocked.
+pub struct Tutor {
+    /// A bit for every key code, set when that key code is blocked.
+    blocked: [u8; 32],
+    active: bool,
+}
+
+impl Default for Tutor {
+    fn default() -> Self {
+        let mut tutor = Self {
+            blocked: [0; 32],
+            active: false,
+        };
+        for kc in [KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right] {
+            tutor.set_blocked(kc as u8, true);
+        }
+        tutor
+    }
+}
+
+impl Tutor {
+    /// Turn the mode on or off.
+    pub fn toggle(&mut self) {
+        self.active = !self.active;
+    }
+
+    /// Add the given key code to, or remove it from, the key codes to block.
+    pub fn set_blocked(&mut self, kc: u8, blocked: bool) {
+        let bit = 1 << (kc % 8);
+        let byte = &mut self.blocked[kc as usize / 8];
+        if blocked {
+            *byte |= bit;
+        } else {
+            *byte &= !bit;
+        }
+    }
+
+    /// Is the given key code kept out of reports?
+    pub fn blocks(&self, kc: KeyCode) -> bool {
+        let kc = kc as u8;
+        self.active && self.blocked[kc as usize / 8] & (1 << (kc % 8)) != 0
+    }
+}
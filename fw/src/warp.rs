@@ -0,0 +1,56 @@
+//! Moving the pointer by picking cells of a grid laid over the screen.
+//!
+//! The first `Warp` key pressed picks a cell of a grid over the whole screen,
+//! and every `Warp` key pressed while it's held picks a cell of a grid over the
+//! cell picked before it. With a 3 by 3 grid, a chord of two keys can reach
+//! any of 81 points on the screen, and three keys any of 729. The pointer stays
+//! at the center of the last cell picked until every `Warp` key is released.
+
+/// Number of columns, and of rows, in the grid.
+pub const GRID: u8 = 3;
+
+/// The largest coordinate of the absolute pointer, at the right or bottom
+/// edge of the screen.
+pub const MAX: u32 = 0x7fff;
+
+/// The cell that's been picked by the `Warp` keys that are held.
+#[derive(Default)]
+pub struct Warp {
+    /// The top left corner of the cell
+    x: u32,
+    y: u32,
+    /// The width and height of the cell
+    size: u32,
+    /// Number of `Warp` keys held
+    held: u8,
+}
+
+impl Warp {
+    /// Pick the cell at the given column and row of the grid over the cell
+    /// picked so far, or over the whole screen.
+    pub fn press(&mut self, col: u8, row: u8) {
+        if self.held == 0 {
+            self.x = 0;
+            self.y = 0;
+            self.size = MAX + 1;
+        }
+        self.held = self.held.saturating_add(1);
+        self.size /= GRID as u32;
+        self.x += col.min(GRID - 1) as u32 * self.size;
+        self.y += row.min(GRID - 1) as u32 * self.size;
+    }
+
+    /// Release a `Warp` key.
+    pub fn release(&mut self) {
+        self.held = self.held.saturating_sub(1);
+    }
+
+    /// The position of the pointer, if any `Warp` key is held.
+    pub fn position(&self) -> Option<(u16, u16)> {
+        if self.held == 0 {
+            return None;
+        }
+        let half = self.size / 2;
+        Some(((self.x + half) as u16, (self.y + half) as u16))
+    }
+}
@@ -0,0 +1,90 @@
+//! A test that types where each key is wired, for checking a keyboard from end
+//! to end in a text editor, without any host tools.
+//!
+//! Holding both Shifts and T together, found by their key codes in the base
+//! layer, turns the test on, and doing it again turns it off. While it's on, no
+//! key does what it usually does. Instead, each key types its position as it's
+//! released, such as `r08c03 `: the Port B pin of its row, then its column, as
+//! in the layouts in `main.rs`. The keys held to turn the test on or off type
+//! nothing.
+
+use crate::action::{action, Action, Layout};
+use crate::key_code::KeyCode::{self, *};
+
+/// The keys held to turn the test on or off, found by their key code in the
+/// base layer.
+const TOGGLE_KEYS: [KeyCode; 3] = [LShift, RShift, T];
+
+/// A bit for each of `TOGGLE_KEYS`.
+const ALL: u8 = 0b111;
+
+/// The state of the wiring test.
+#[derive(Default)]
+pub struct WiringTest {
+    active: bool,
+    /// A bit for each of `TOGGLE_KEYS` that's held
+    held: u8,
+    /// Were the held keys used to turn the test on or off?
+    toggled: bool,
+}
+
+/// Find which of `TOGGLE_KEYS` is at a position, by its key code in the base
+/// layer.
+fn toggle_bit<const R: usize, const C: usize>(
+    base: &Layout<R, C>,
+    row: usize,
+    col: usize,
+) -> Option<u8> {
+    match action(base, row, col) {
+        Some(Action::KeyCode(kc)) => TOGGLE_KEYS.iter().position(|k| k == kc).map(|i| 1 << i),
+        _ => None,
+    }
+}
+
+impl WiringTest {
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Handle the press of a key, returning true if the test takes it, rather
+    /// than the key doing what it usually does.
+    pub fn press<const R: usize, const C: usize>(
+        &mut self,
+        base: &Layout<R, C>,
+        row: usize,
+        col: usize,
+    ) -> bool {
+        let was_active = self.active;
+        if let Some(bit) = toggle_bit(base, row, col) {
+            self.held |= bit;
+            if self.held == ALL {
+                self.active = !self.active;
+                self.toggled = true;
+            }
+        }
+        was_active || self.active
+    }
+
+    /// Handle the release of a key, returning the position to type for it, as
+    /// the Port B pin of its row and its column, if the test is on.
+    pub fn release<const R: usize, const C: usize>(
+        &mut self,
+        base: &Layout<R, C>,
+        row: usize,
+        col: usize,
+    ) -> Option<(usize, usize)> {
+        let bit = toggle_bit(base, row, col);
+        let toggled = bit.is_some() && self.toggled;
+        if let Some(bit) = bit {
+            self.held &= !bit;
+            if self.held == 0 {
+                self.toggled = false;
+            }
+        }
+        if self.active && !toggled {
+            Some((row + 3, col))
+        } else {
+            None
+        }
+    }
+}
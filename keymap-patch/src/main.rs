@@ -0,0 +1,93 @@
+//! Overwrite the keymap of a running keyboard, built with `hot-keymap`, with the
+//! keymap of another build of the firmware, without flashing it.
+//!
+//! ```text
+//! keymap-patch <running firmware> <new firmware>
+//! ```
+//!
+//! The layers are copied from the `KEYMAPS` of the new firmware into the
+//! `HOT_STAGED` of the running one, through the probe. Both must be built with
+//! the same compiler and features, so that their layouts have the same
+//! representation, and the new keymap may not have more layers than the
+//! running one.
+//!
+//! The running firmware then tries the new keymap out: it's only kept if the
+//! key that was Enter is pressed within 30 seconds, and otherwise the old
+//! keymap comes back.
+
+use std::env;
+use std::fs;
+
+use object::{Object, ObjectSection, ObjectSymbol};
+
+use probe_rs::MemoryInterface;
+use probe_rs::Session;
+
+/// The address and size of the first symbol whose name passes `pred`.
+fn symbol(file: &object::File, pred: impl Fn(&str) -> bool) -> Option<(u64, u64)> {
+    file.symbols()
+        .find(|sym| sym.name().map_or(false, &pred))
+        .map(|sym| (sym.address(), sym.size()))
+}
+
+/// The `len` bytes at `address`, as loaded from the file.
+fn read<'a>(file: &'a object::File, address: u64, len: u64) -> &'a [u8] {
+    let section = file
+        .sections()
+        .find(|s| s.address() <= address && address + len <= s.address() + s.size())
+        .unwrap();
+    let data = section.data().unwrap();
+    let start = (address - section.address()) as usize;
+    &data[start..start + len as usize]
+}
+
+fn read_u32(file: &object::File, address: u64) -> u64 {
+    let mut word = [0; 4];
+    word.copy_from_slice(read(file, address, 4));
+    u32::from_le_bytes(word) as u64
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("usage: keymap-patch <running firmware> <new firmware>");
+        std::process::exit(2);
+    }
+    let running_data = fs::read(&args[0]).unwrap();
+    let running = object::File::parse(&running_data).unwrap();
+    let new_data = fs::read(&args[1]).unwrap();
+    let new = object::File::parse(&new_data).unwrap();
+
+    let (staged, staged_size) = symbol(&running, |name| name == "HOT_STAGED")
+        .expect("the running firmware isn't built with hot-keymap");
+    let (staged_len, _) = symbol(&running, |name| name == "HOT_STAGED_LEN").unwrap();
+    // `KEYMAPS` is an array of slices of references to layers, and only the
+    // first keymap is kept in RAM.
+    let (keymaps, _) = symbol(&new, |name| name.contains("7KEYMAPS")).unwrap();
+    let layers = read_u32(&new, keymaps);
+    let count = read_u32(&new, keymaps + 4);
+    let mut patch = Vec::new();
+    for i in 0..count {
+        let layer = read_u32(&new, layers + i * 4);
+        let size = new
+            .symbols()
+            .find(|sym| sym.address() == layer && sym.size() != 0)
+            .map(|sym| sym.size())
+            .unwrap();
+        patch.extend_from_slice(read(&new, layer, size));
+    }
+    assert!(
+        patch.len() as u64 <= staged_size,
+        "the new keymap has more layers than the running one has room for"
+    );
+
+    let mut sesh = Session::auto_attach("stm32f103c8").unwrap();
+    let mut core = sesh.core(0).unwrap();
+    core.write_8(staged as u32, &patch).unwrap();
+    // Only once every layer is there may the firmware swap them in.
+    core.write_8(staged_len as u32, &[count as u8]).unwrap();
+    println!(
+        "staged {} layers; press Enter, where it was, within 30 seconds to keep them",
+        count
+    );
+}
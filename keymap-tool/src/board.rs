@@ -0,0 +1,97 @@
+//! Where the keys of each board are, given their position in the matrix.
+
+/// The position of a key, in units of a key's width, from the top left of
+/// the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    /// The height of the key
+    pub h: f32,
+}
+
+/// Number of rows in the matrix, starting at PB3.
+pub const ROWS: usize = 13;
+
+/// Number of columns in the matrix.
+pub const COLS: usize = 6;
+
+/// Find the position of the key at the given row and column of the matrix on
+/// `board`, which is named by its cargo feature. Boards without a table of
+/// positions are drawn as the matrix itself.
+pub fn position(board: &str, row: usize, col: usize) -> Option<Position> {
+    match board {
+        "dmote" | "macropad" => dmote(row + 3, col),
+        "dactyl" => dactyl(row + 3, col),
+        _ => at(col as f32 + if row >= 8 { 1.0 } else { 0.0 }, row as f32),
+    }
+}
+
+fn at(x: f32, y: f32) -> Option<Position> {
+    Some(Position { x, y, h: 1.0 })
+}
+
+fn tall(x: f32, y: f32) -> Option<Position> {
+    Some(Position { x, y, h: 2.0 })
+}
+
+/// The DMOTE, from the wiring in `fw/src/main.rs`, given the Port B pin of the
+/// row.
+///
+/// The pinky columns are wired a row lower than the rest, so they're moved up
+/// one row. The thumb clusters follow the drawing above the thumb rows of the
+/// DMOTE layout.
+fn dmote(pin: usize, col: usize) -> Option<Position> {
+    let y = |top_pin: usize, shifted: bool| {
+        let y = pin as f32 - top_pin as f32 - if shifted { 1.0 } else { 0.0 };
+        if y >= 0.0 {
+            Some(y)
+        } else {
+            None
+        }
+    };
+    match (pin, col) {
+        // Left fingers, from the outer pinky column
+        (4..=7, _) => at(col as f32, y(4, col <= 1)?),
+        // Right fingers, from the inner index column
+        (12..=15, _) => at(8.0 + col as f32, y(12, col >= 4)?),
+        // Thumb cluster under the left hand
+        (9, 0..=2) => at(4.0, 5.0 + col as f32),
+        (10, 0..=1) => at(3.0, 5.5 + col as f32),
+        (8, 0..=1) => at(5.0, 5.5 + col as f32),
+        // The last middle keys, and the face keys below them
+        (8, 2..=3) => at(4.0 + col as f32, 5.0),
+        (10, 2..=3) => at(4.0 + col as f32, 7.0),
+        // Thumb cluster under the right hand
+        (9, 3..=5) => at(9.0, 10.0 - col as f32),
+        (10, 4..=5) => at(8.0, 0.5 + 10.0 - col as f32),
+        (8, 4..=5) => at(10.0, 0.5 + 10.0 - col as f32),
+        _ => None,
+    }
+}
+
+/// The Dactyl, from the wiring in `fw/src/main.rs`, given the Port B pin of
+/// the row. The thumb pads follow the drawings beside the thumb rows.
+fn dactyl(pin: usize, col: usize) -> Option<Position> {
+    match (pin, col) {
+        // Left fingers, from the outer column
+        (3..=7, _) => at(col as f32, (pin - 3) as f32),
+        // Left thumb pad
+        (8, 5) => at(7.0, 5.0),
+        (8, 4) => at(8.0, 5.0),
+        (8, 1) => tall(6.0, 6.0),
+        (8, 0) => tall(7.0, 6.0),
+        (8, 3) => at(8.0, 6.0),
+        (8, 2) => at(8.0, 7.0),
+        // Right fingers, from the inner column
+        (10..=14, _) => at(12.0 + col as f32, (pin - 10) as f32),
+        // Right thumb pad
+        (15, 1) => at(9.0, 5.0),
+        (15, 0) => at(10.0, 5.0),
+        (15, 2) => at(9.0, 6.0),
+        (15, 3) => at(9.0, 7.0),
+        (15, 4) => tall(10.0, 6.0),
+        (15, 5) => tall(11.0, 6.0),
+        _ => None,
+    }
+}
@@ -0,0 +1,94 @@
+//! Exporting keymaps to keyboard-layout-editor.com (KLE) JSON.
+//!
+//! KLE places keys in rows, each starting a key's height below the last, with
+//! every key placed a key's width right of the one before it, unless moved by
+//! the `x` and `y` properties of the object before it. Every key here is moved
+//! to its position on the board, so the rows are only there because KLE needs
+//! them.
+//!
+//! Each key's legend holds its label in every layer, one per line, so that the
+//! first layer is in the top left and the second in the bottom left.
+
+use std::fmt::Write;
+
+use crate::board::{self, Position, COLS, ROWS};
+use crate::parse::Layer;
+use crate::render::label;
+
+/// Maximum number of legends on a key in KLE.
+const LEGENDS: usize = 12;
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Export every layer of a keymap of `board`, leaving out positions that are
+/// empty in every layer.
+pub fn export(board: &str, layers: &[Layer]) -> String {
+    let mut keys: Vec<(Position, String)> = Vec::new();
+    for row in 0..ROWS {
+        for col in 0..COLS {
+            if layers.iter().all(|l| l.key(row, col) == "__") {
+                continue;
+            }
+            if let Some(pos) = board::position(board, row, col) {
+                let legend: Vec<String> =
+                    layers.iter().take(LEGENDS).map(|l| label(l.key(row, col))).collect();
+                keys.push((pos, legend.join("\n")));
+            }
+        }
+    }
+    keys.sort_by(|(a, _), (b, _)| {
+        (a.y, a.x)
+            .partial_cmp(&(b.y, b.x))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let mut json = String::from("[\n");
+    let mut next_y = 0.0;
+    let mut row_y = None;
+    let mut next_x = 0.0;
+    for (pos, legend) in keys {
+        let mut props = Vec::new();
+        if row_y != Some(pos.y) {
+            if row_y.is_some() {
+                json.push_str("],\n");
+            }
+            json.push_str("  [");
+            if pos.y != next_y {
+                props.push(format!("\"y\": {}", pos.y - next_y));
+            }
+            row_y = Some(pos.y);
+            next_y = pos.y + 1.0;
+            next_x = 0.0;
+        } else {
+            json.push_str(", ");
+        }
+        if pos.x != next_x {
+            props.push(format!("\"x\": {}", pos.x - next_x));
+        }
+        if pos.h != 1.0 {
+            props.push(format!("\"h\": {}", pos.h));
+        }
+        if !props.is_empty() {
+            let _ = write!(json, "{{{}}}, ", props.join(", "));
+        }
+        json.push_str(&quote(&legend));
+        next_x = pos.x + 1.0;
+    }
+    if row_y.is_some() {
+        json.push_str("]\n");
+    }
+    json.push_str("]\n");
+    json
+}
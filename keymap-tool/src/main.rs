@@ -0,0 +1,78 @@
+//! Tools for the keymaps written in the firmware's source.
+//!
+//! ```text
+//! keymap-tool html <path to fw/src/main.rs> <board feature> > keymap.html
+//! keymap-tool kle <path to fw/src/main.rs> <board feature> > keymap.json
+//! keymap-tool qmk <path to fw/src/main.rs> <board feature> <keymap.json>
+//! ```
+//!
+//! `html` draws every layer of the board's keymap, with the keys where they
+//! are on the board, so that pictures of a layout are generated from the
+//! layout itself, rather than drawn by hand and left to go stale.
+//!
+//! `kle` exports the same to keyboard-layout-editor.com, which can be used to
+//! share a layout, or to make a picture with a different style.
+//!
+//! `qmk` converts a keymap from QMK Configurator into `layout!` tables for the
+//! board, to be pasted into `fw/src/main.rs`.
+
+mod board;
+mod kle;
+mod parse;
+mod qmk;
+mod render;
+
+use std::env;
+use std::fs;
+use std::process::exit;
+
+const USAGE: &str = "usage: keymap-tool (html|kle) <path to fw/src/main.rs> <board feature>
+       keymap-tool qmk <path to fw/src/main.rs> <board feature> <keymap.json>";
+
+fn read(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("could not read {}: {}", path, e);
+            exit(1);
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, path, board, extra) = match args.as_slice() {
+        [command, path, board] => (command.as_str(), path, board, None),
+        [command, path, board, extra] => (command.as_str(), path, board, Some(extra)),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+    let source = read(path);
+    let layers = parse::layers(&source, board);
+    if layers.is_empty() {
+        eprintln!("no layouts found for the feature {:?}", board);
+        exit(1);
+    }
+    match (command, extra) {
+        ("html", None) => print!("{}", render::html(board, &layers)),
+        ("kle", None) => print!("{}", kle::export(board, &layers)),
+        ("qmk", Some(keymap)) => match qmk::import(&read(keymap), board, &layers) {
+            Ok((tables, warnings)) => {
+                for warning in warnings {
+                    eprintln!("warning: {}", warning);
+                }
+                print!("{}", tables);
+            }
+            Err(e) => {
+                eprintln!("could not import {}: {}", keymap, e);
+                exit(1);
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Reading the layouts out of the firmware's source.
+//!
+//! This only understands layouts written the way `fw/src/main.rs` writes them:
+//! a `static` built with `layout![`, under a `#[cfg(feature = "...")]`, with
+//! one row of the matrix per line.
+
+/// A layer of a keymap, as written in a `layout!` table.
+pub struct Layer {
+    /// The name of the static holding the layer, such as `LAYOUT_ALT`
+    pub name: String,
+    /// The doc comment on the static, if any
+    pub doc: String,
+    /// The keys of each row of the matrix, as written in the table. The first
+    /// row is the row on PB3.
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Layer {
+    /// The key at the given row and column, or `__` if there's none.
+    pub fn key(&self, row: usize, col: usize) -> &str {
+        self.rows
+            .get(row)
+            .and_then(|r| r.get(col))
+            .map(|k| k.as_str())
+            .unwrap_or("__")
+    }
+}
+
+/// Split a row of a `layout!` table into its keys.
+fn split_row(line: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    let mut key = String::new();
+    let mut depth = 0;
+    for c in line.chars().skip_while(|&c| c != '[').skip(1) {
+        match c {
+            '{' | '(' => depth += 1,
+            '}' | ')' => depth -= 1,
+            ',' | ']' if depth == 0 => {
+                if !key.trim().is_empty() {
+                    keys.push(key.trim().to_string());
+                }
+                key.clear();
+                if c == ']' {
+                    break;
+                }
+                continue;
+            }
+            _ => (),
+        }
+        key.push(c);
+    }
+    keys
+}
+
+/// Find the layers built with `feature`, in the order they're declared.
+pub fn layers(source: &str, feature: &str) -> Vec<Layer> {
+    let cfg = format!("#[cfg(feature = \"{}\")]", feature);
+    let mut layers = Vec::new();
+    let mut doc = String::new();
+    let mut enabled = false;
+    let mut current: Option<Layer> = None;
+    for line in source.lines() {
+        let line = line.trim();
+        if let Some(layer) = current.as_mut() {
+            if line.starts_with("];") {
+                layers.extend(current.take());
+            } else if line.starts_with('[') {
+                layer.rows.push(split_row(line));
+            }
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix("///") {
+            if !doc.is_empty() {
+                doc.push(' ');
+            }
+            doc.push_str(comment.trim());
+        } else if line.starts_with("#[") {
+            enabled |= line == cfg;
+        } else if line.contains(": Layout<") && line.contains("layout![") {
+            if enabled {
+                let name = line
+                    .split(':')
+                    .next()
+                    .and_then(|decl| decl.split_whitespace().last())
+                    .unwrap_or_default();
+                current = Some(Layer {
+                    name: name.to_string(),
+                    doc: doc.clone(),
+                    rows: Vec::new(),
+                });
+            }
+            doc.clear();
+            enabled = false;
+        } else {
+            doc.clear();
+            enabled = false;
+        }
+    }
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Layouts written as `fw/src/main.rs` writes them, for two boards.
+    const SOURCE: &str = r#"
+/// The base layer
+#[rustfmt::skip]
+#[cfg(feature = "ergodox")]
+pub static LAYOUT: Layout<13, 6> = layout![
+    [A, B,                  __, __, __, __], /* 3 */
+    [C, {Action::Layer(1)}, __, __, __, __], /* 4 */
+];
+
+#[rustfmt::skip]
+#[cfg(feature = "lily58")]
+pub static LAYOUT: Layout<13, 6> = layout![
+    [Q, W, E, R, T, Y], /* 3 */
+];
+
+/// Function keys
+/// and arrows
+#[rustfmt::skip]
+#[cfg(feature = "ergodox")]
+pub static LAYOUT_FN: Layout<13, 6> = layout![
+    [F1, F2, __, __, __, __], /* 3 */
+    [Up, {Action::Warp(Warp::Left, 1)}, __, __, __, __], /* 4 */
+];
+"#;
+
+    #[test]
+    fn split_row_keeps_braces_and_parens_whole() {
+        assert_eq!(
+            split_row("    [A, {Action::Warp(Warp::Left, 1)}, __], /* 3 */"),
+            vec!["A", "{Action::Warp(Warp::Left, 1)}", "__"]
+        );
+    }
+
+    #[test]
+    fn layers_of_one_feature_in_order() {
+        let layers = layers(SOURCE, "ergodox");
+        let names: Vec<_> = layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["LAYOUT", "LAYOUT_FN"]);
+        assert_eq!(layers[0].doc, "The base layer");
+        assert_eq!(layers[1].doc, "Function keys and arrows");
+        assert_eq!(
+            layers[0].rows[1],
+            ["C", "{Action::Layer(1)}", "__", "__", "__", "__"]
+        );
+        assert_eq!(layers[1].key(1, 1), "{Action::Warp(Warp::Left, 1)}");
+    }
+
+    #[test]
+    fn keys_past_the_table_are_empty() {
+        let layers = layers(SOURCE, "lily58");
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].key(0, 5), "Y");
+        assert_eq!(layers[0].key(1, 0), "__");
+        assert_eq!(layers[0].key(0, 6), "__");
+    }
+
+    #[test]
+    fn no_layers_for_an_unknown_feature() {
+        assert!(layers(SOURCE, "dactyl").is_empty());
+    }
+}
@@ -0,0 +1,583 @@
+//! Importing keymaps from QMK, as saved by QMK Configurator in `keymap.json`.
+//!
+//! A QMK keymap lists the keys of each layer in the order of the keyboard's
+//! `LAYOUT` macro, which doesn't say where the keys are. The keys are instead
+//! matched, in order, to the keys of the board's keymap in `fw/src/main.rs`,
+//! sorted top to bottom and then left to right by where they are on the board.
+//! That's the order of most `LAYOUT` macros of split keyboards, which list a
+//! row of the left half and then the same row of the right half.
+//!
+//! The firmware has no hold-tap keys, so mod-taps become their modifier and
+//! layer-taps become their layer, and anything else that can't be translated
+//! becomes `__`. Each is reported on stderr.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::board::{self, COLS, ROWS};
+use crate::parse::Layer;
+
+/// Just enough of JSON to read a `keymap.json`.
+#[derive(Debug)]
+enum Json {
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+    /// A number, `true`, `false` or `null`, none of which a keymap needs
+    Other,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected {:?}, found {:?}", expected, c)),
+            None => Err(format!("expected {:?}, found the end", expected)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('u') => {
+                        let hex: String = self.chars.by_ref().take(4).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .unwrap_or('\u{fffd}');
+                        string.push(c);
+                    }
+                    Some(c) => string.push(c),
+                    None => break,
+                },
+                Some(c) => string.push(c),
+                None => break,
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.chars.next();
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.chars.peek() == Some(&']') {
+                    self.chars.next();
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => (),
+                        Some(']') => return Ok(Json::Array(items)),
+                        _ => return Err("expected ',' or ']' in an array".to_string()),
+                    }
+                }
+            }
+            Some('{') => {
+                self.chars.next();
+                let mut members = BTreeMap::new();
+                self.skip_whitespace();
+                if self.chars.peek() == Some(&'}') {
+                    self.chars.next();
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    members.insert(key, self.value()?);
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some(',') => (),
+                        Some('}') => return Ok(Json::Object(members)),
+                        _ => return Err("expected ',' or '}' in an object".to_string()),
+                    }
+                }
+            }
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c.is_alphanumeric() || "+-.".contains(c) {
+                        word.push(c);
+                        self.chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match word.as_str() {
+                    "null" | "true" | "false" => Ok(Json::Other),
+                    _ => word
+                        .parse::<f64>()
+                        .map(|_| Json::Other)
+                        .map_err(|_| format!("unexpected {:?}", word)),
+                }
+            }
+            None => Err("unexpected end".to_string()),
+        }
+    }
+}
+
+/// The key code named by a basic QMK key code, such as `KC_A`.
+fn key_code(qmk: &str) -> Option<&'static str> {
+    let name = qmk.strip_prefix("KC_").unwrap_or(qmk);
+    let kc = match name {
+        "NO" | "XXXXXXX" => "__",
+        "A" => "A",
+        "B" => "B",
+        "C" => "C",
+        "D" => "D",
+        "E" => "E",
+        "F" => "F",
+        "G" => "G",
+        "H" => "H",
+        "I" => "I",
+        "J" => "J",
+        "K" => "K",
+        "L" => "L",
+        "M" => "M",
+        "N" => "N",
+        "O" => "O",
+        "P" => "P",
+        "Q" => "Q",
+        "R" => "R",
+        "S" => "S",
+        "T" => "T",
+        "U" => "U",
+        "V" => "V",
+        "W" => "W",
+        "X" => "X",
+        "Y" => "Y",
+        "Z" => "Z",
+        "1" => "Kb1",
+        "2" => "Kb2",
+        "3" => "Kb3",
+        "4" => "Kb4",
+        "5" => "Kb5",
+        "6" => "Kb6",
+        "7" => "Kb7",
+        "8" => "Kb8",
+        "9" => "Kb9",
+        "0" => "Kb0",
+        "ENT" | "ENTER" => "Enter",
+        "ESC" | "ESCAPE" => "Escape",
+        "BSPC" | "BSPACE" | "BACKSPACE" => "BSpace",
+        "TAB" => "Tab",
+        "SPC" | "SPACE" => "Space",
+        "MINS" | "MINUS" => "Minus",
+        "EQL" | "EQUAL" => "Equal",
+        "LBRC" | "LBRACKET" | "LEFT_BRACKET" => "LBracket",
+        "RBRC" | "RBRACKET" | "RIGHT_BRACKET" => "RBracket",
+        "BSLS" | "BSLASH" | "BACKSLASH" => "Bslash",
+        "NUHS" | "NONUS_HASH" => "NonUsHash",
+        "SCLN" | "SCOLON" | "SEMICOLON" => "SColon",
+        "QUOT" | "QUOTE" => "Quote",
+        "GRV" | "GRAVE" => "Grave",
+        "COMM" | "COMMA" => "Comma",
+        "DOT" => "Dot",
+        "SLSH" | "SLASH" => "Slash",
+        "CAPS" | "CAPSLOCK" | "CAPS_LOCK" => "CapsLock",
+        "F1" => "F1",
+        "F2" => "F2",
+        "F3" => "F3",
+        "F4" => "F4",
+        "F5" => "F5",
+        "F6" => "F6",
+        "F7" => "F7",
+        "F8" => "F8",
+        "F9" => "F9",
+        "F10" => "F10",
+        "F11" => "F11",
+        "F12" => "F12",
+        "F13" => "F13",
+        "F14" => "F14",
+        "F15" => "F15",
+        "F16" => "F16",
+        "F17" => "F17",
+        "F18" => "F18",
+        "F19" => "F19",
+        "F20" => "F20",
+        "F21" => "F21",
+        "F22" => "F22",
+        "F23" => "F23",
+        "F24" => "F24",
+        "PSCR" | "PSCREEN" | "PRINT_SCREEN" => "PScreen",
+        "SLCK" | "SCRL" | "SCROLLLOCK" | "SCROLL_LOCK" => "ScrollLock",
+        "PAUS" | "PAUSE" | "BRK" => "Pause",
+        "INS" | "INSERT" => "Insert",
+        "HOME" => "Home",
+        "PGUP" => "PgUp",
+        "DEL" | "DELETE" => "Delete",
+        "END" => "End",
+        "PGDN" | "PGDOWN" | "PAGE_DOWN" => "PgDown",
+        "RGHT" | "RIGHT" => "Right",
+        "LEFT" => "Left",
+        "DOWN" => "Down",
+        "UP" => "Up",
+        "NLCK" | "NUM" | "NUMLOCK" | "NUM_LOCK" => "NumLock",
+        "PSLS" | "KP_SLASH" => "KpSlash",
+        "PAST" | "KP_ASTERISK" => "KpAsterisk",
+        "PMNS" | "KP_MINUS" => "KpMinus",
+        "PPLS" | "KP_PLUS" => "KpPlus",
+        "PENT" | "KP_ENTER" => "KpEnter",
+        "P1" | "KP_1" => "Kp1",
+        "P2" | "KP_2" => "Kp2",
+        "P3" | "KP_3" => "Kp3",
+        "P4" | "KP_4" => "Kp4",
+        "P5" | "KP_5" => "Kp5",
+        "P6" | "KP_6" => "Kp6",
+        "P7" | "KP_7" => "Kp7",
+        "P8" | "KP_8" => "Kp8",
+        "P9" | "KP_9" => "Kp9",
+        "P0" | "KP_0" => "Kp0",
+        "PDOT" | "KP_DOT" => "KpDot",
+        "PEQL" | "KP_EQUAL" => "KpEqual",
+        "NUBS" | "NONUS_BSLASH" | "NONUS_BACKSLASH" => "NonUsBslash",
+        "APP" | "APPLICATION" => "Application",
+        "MUTE" | "AUDIO_MUTE" => "Mute",
+        "VOLU" | "AUDIO_VOL_UP" => "VolUp",
+        "VOLD" | "AUDIO_VOL_DOWN" => "VolDown",
+        "MPLY" | "MEDIA_PLAY_PAUSE" => "MediaPlayPause",
+        "MSTP" | "MEDIA_STOP" => "MediaStopCD",
+        "MPRV" | "MEDIA_PREV_TRACK" => "MediaPreviousSong",
+        "MNXT" | "MEDIA_NEXT_TRACK" => "MediaNextSong",
+        "LCTL" | "LCTRL" | "LEFT_CTRL" => "LCtrl",
+        "LSFT" | "LSHIFT" | "LEFT_SHIFT" => "LShift",
+        "LALT" | "LOPT" | "LEFT_ALT" => "LAlt",
+        "LGUI" | "LCMD" | "LWIN" | "LEFT_GUI" => "LGui",
+        "RCTL" | "RCTRL" | "RIGHT_CTRL" => "RCtrl",
+        "RSFT" | "RSHIFT" | "RIGHT_SHIFT" => "RShift",
+        "RALT" | "ROPT" | "ALGR" | "RIGHT_ALT" => "RAlt",
+        "RGUI" | "RCMD" | "RWIN" | "RIGHT_GUI" => "RGui",
+        _ => return None,
+    };
+    Some(kc)
+}
+
+/// The modifier of a mod-tap, such as `LCTL` in `LCTL_T(KC_A)`, or the first
+/// modifier of one written with `MT`, such as `MOD_LCTL | MOD_LSFT`.
+fn modifier(name: &str) -> Option<&'static str> {
+    let name = name.split('|').next()?.trim();
+    key_code(name.strip_prefix("MOD_").unwrap_or(name))
+}
+
+/// Split `NAME(ARGS)` into its name and arguments.
+fn call(qmk: &str) -> Option<(&str, Vec<&str>)> {
+    let (name, rest) = qmk.split_at(qmk.find('(')?);
+    let args = rest.strip_prefix('(')?.strip_suffix(')')?;
+    Some((name, args.split(',').map(str::trim).collect()))
+}
+
+/// Translate a QMK key code into a key of a `layout!` table, along with a
+/// warning if it's only partly translated. `None` is a transparent key.
+fn translate(qmk: &str) -> (Option<String>, Option<String>) {
+    let qmk = qmk.trim();
+    if qmk == "KC_TRNS" || qmk == "KC_TRANSPARENT" || qmk == "_______" {
+        return (None, None);
+    }
+    if let Some(kc) = key_code(qmk) {
+        return (Some(kc.to_string()), None);
+    }
+    let layer = |n: &str| {
+        n.parse::<u8>()
+            .ok()
+            .map(|n| format!("{{Action::Layer({})}}", n))
+    };
+    match call(qmk) {
+        Some(("MO", args)) => {
+            if let Some(key) = args.first().and_then(|n| layer(n)) {
+                return (Some(key), None);
+            }
+        }
+        Some(("LT", args)) if args.len() == 2 => {
+            if let Some(key) = layer(args[0]) {
+                let warning = format!("{} is only a layer key; {} is lost", qmk, args[1]);
+                return (Some(key), Some(warning));
+            }
+        }
+        Some(("MT", args)) if args.len() == 2 => {
+            if let Some(kc) = modifier(args[0]) {
+                let warning = format!("{} is only a modifier; {} is lost", qmk, args[1]);
+                return (Some(kc.to_string()), Some(warning));
+            }
+        }
+        Some((name, args)) if name.ends_with("_T") && args.len() == 1 => {
+            if let Some(kc) = modifier(name.trim_end_matches("_T")) {
+                let warning = format!("{} is only a modifier; {} is lost", qmk, args[0]);
+                return (Some(kc.to_string()), Some(warning));
+            }
+        }
+        _ => (),
+    }
+    (
+        Some("__".to_string()),
+        Some(format!("{} has no equivalent; left empty", qmk)),
+    )
+}
+
+/// Read the layers out of a `keymap.json`.
+fn qmk_layers(source: &str) -> Result<Vec<Vec<String>>, String> {
+    let json = Parser {
+        chars: source.chars().peekable(),
+    }
+    .value()?;
+    let layers = match json {
+        Json::Object(mut members) => members.remove("layers"),
+        _ => None,
+    };
+    let layers = match layers {
+        Some(Json::Array(layers)) => layers,
+        _ => return Err("no \"layers\" array in the keymap".to_string()),
+    };
+    layers
+        .into_iter()
+        .map(|layer| match layer {
+            Json::Array(keys) => keys
+                .into_iter()
+                .map(|key| match key {
+                    Json::String(key) => Ok(key),
+                    other => Err(format!("expected a key code, found {:?}", other)),
+                })
+                .collect(),
+            _ => Err("expected a layer to be an array of key codes".to_string()),
+        })
+        .collect()
+}
+
+/// Convert a QMK `keymap.json` into `layout!` tables for `board`, placing its
+/// keys where the keys of `layers`, the board's current keymap, are.
+///
+/// Warnings about keys that couldn't be fully translated are returned along
+/// with the tables.
+pub fn import(
+    source: &str,
+    board: &str,
+    layers: &[Layer],
+) -> Result<(String, Vec<String>), String> {
+    let qmk = qmk_layers(source)?;
+    let mut keys = Vec::new();
+    for row in 0..ROWS {
+        for col in 0..COLS {
+            if layers.iter().all(|l| l.key(row, col) == "__") {
+                continue;
+            }
+            if let Some(pos) = board::position(board, row, col) {
+                keys.push((pos, row, col));
+            }
+        }
+    }
+    keys.sort_by(|(a, ..), (b, ..)| {
+        (a.y, a.x)
+            .partial_cmp(&(b.y, b.x))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let mut warnings = Vec::new();
+    let mut tables: Vec<[[String; COLS]; ROWS]> = Vec::new();
+    for (index, layer) in qmk.iter().enumerate() {
+        if layer.len() != keys.len() {
+            return Err(format!(
+                "layer {} has {} keys, but the {} keymap has {}",
+                index,
+                layer.len(),
+                board,
+                keys.len()
+            ));
+        }
+        let mut table: [[String; COLS]; ROWS] = Default::default();
+        for row in table.iter_mut() {
+            for key in row.iter_mut() {
+                *key = "__".to_string();
+            }
+        }
+        for (qmk_key, &(_, row, col)) in layer.iter().zip(keys.iter()) {
+            let (key, warning) = translate(qmk_key);
+            if let Some(warning) = warning {
+                warnings.push(format!("layer {}: {}", index, warning));
+            }
+            // The firmware's layers aren't transparent, so a transparent key
+            // takes the key below it in the first layer.
+            table[row][col] = match (key, tables.first()) {
+                (Some(key), _) => key,
+                (None, Some(base)) => base[row][col].clone(),
+                (None, None) => "__".to_string(),
+            };
+        }
+        tables.push(table);
+    }
+
+    let mut out = String::new();
+    for (index, table) in tables.iter().enumerate() {
+        let name = match index {
+            0 => "LAYOUT".to_string(),
+            n => format!("LAYOUT_{}", n),
+        };
+        let widths: Vec<usize> = (0..COLS)
+            .map(|col| table.iter().map(|row| row[col].len()).max().unwrap_or(0))
+            .collect();
+        let _ = writeln!(out, "/// Layer {} of the QMK keymap", index);
+        let _ = writeln!(out, "#[rustfmt::skip]");
+        let _ = writeln!(out, "#[cfg(feature = \"{}\")]", board);
+        let _ = writeln!(out, "pub static {}: Layout<13, 6> = layout![", name);
+        for (row, keys) in table.iter().enumerate() {
+            let mut line = String::from("    [");
+            for (col, key) in keys.iter().enumerate() {
+                if col + 1 < COLS {
+                    let _ = write!(
+                        line,
+                        "{:width$} ",
+                        format!("{},", key),
+                        width = widths[col] + 1
+                    );
+                } else {
+                    let _ = write!(line, "{:width$}", key, width = widths[col]);
+                }
+            }
+            let _ = writeln!(out, "{}], /* {} */", line, row + 3);
+        }
+        let _ = writeln!(out, "];");
+        let _ = writeln!(out);
+    }
+    Ok((out, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /// A keymap of four keys, two on each of the first two rows.
+    const SOURCE: &str = r#"
+#[rustfmt::skip]
+#[cfg(feature = "ergodox")]
+pub static LAYOUT: Layout<13, 6> = layout![
+    [A, B,                  __, __, __, __], /* 3 */
+    [C, {Action::Layer(1)}, __, __, __, __], /* 4 */
+];
+"#;
+
+    fn key(qmk: &str) -> String {
+        translate(qmk).0.unwrap()
+    }
+
+    #[test]
+    fn basic_key_codes() {
+        assert_eq!(translate("KC_A"), (Some("A".to_string()), None));
+        assert_eq!(key("KC_1"), "Kb1");
+        assert_eq!(key("KC_ENT"), "Enter");
+        assert_eq!(key("KC_ENTER"), "Enter");
+        assert_eq!(key("KC_LSFT"), "LShift");
+        assert_eq!(key("KC_MPLY"), "MediaPlayPause");
+        assert_eq!(key("KC_NO"), "__");
+        assert_eq!(key("XXXXXXX"), "__");
+    }
+
+    #[test]
+    fn transparent_keys() {
+        assert_eq!(translate("KC_TRNS"), (None, None));
+        assert_eq!(translate("KC_TRANSPARENT"), (None, None));
+        assert_eq!(translate("_______"), (None, None));
+    }
+
+    #[test]
+    fn layer_keys() {
+        assert_eq!(
+            translate("MO(2)"),
+            (Some("{Action::Layer(2)}".to_string()), None)
+        );
+        let (key, warning) = translate("LT(1, KC_SPC)");
+        assert_eq!(key.as_deref(), Some("{Action::Layer(1)}"));
+        assert!(warning.unwrap().contains("KC_SPC is lost"));
+    }
+
+    #[test]
+    fn mod_taps_become_their_modifier() {
+        let (key, warning) = translate("LCTL_T(KC_A)");
+        assert_eq!(key.as_deref(), Some("LCtrl"));
+        assert!(warning.unwrap().contains("KC_A is lost"));
+        let (key, warning) = translate("MT(MOD_LSFT | MOD_LCTL, KC_B)");
+        assert_eq!(key.as_deref(), Some("LShift"));
+        assert!(warning.unwrap().contains("KC_B is lost"));
+    }
+
+    #[test]
+    fn unknown_key_codes_are_left_empty_with_a_warning() {
+        for qmk in ["KC_FOO", "MO(x)", "LT(1)", "RGB_TOG", "FOO_T(KC_A)"] {
+            let (key, warning) = translate(qmk);
+            assert_eq!(key.as_deref(), Some("__"), "{}", qmk);
+            assert_eq!(
+                warning,
+                Some(format!("{} has no equivalent; left empty", qmk))
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_keymaps() {
+        assert!(qmk_layers("{\"keyboard\": \"x\"}").is_err());
+        assert!(qmk_layers("{\"layers\": [[\"KC_A\", 1]]}").is_err());
+        assert!(qmk_layers("{\"layers\": [\"KC_A\"]}").is_err());
+        assert!(qmk_layers("{\"layers\": [[\"KC_A\"]").is_err());
+    }
+
+    #[test]
+    fn import_places_keys_in_board_order() {
+        let layers = parse::layers(SOURCE, "ergodox");
+        let keymap = r#"{
+            "keyboard": "test",
+            "version": 1,
+            "layers": [
+                ["KC_Q", "LCTL_T(KC_W)", "MO(1)", "KC_TRNS"],
+                ["KC_1", "_______", "KC_NO", "RGB_TOG"]
+            ]
+        }"#;
+        let (tables, warnings) = import(keymap, "ergodox", &layers).unwrap();
+        assert_eq!(
+            warnings,
+            [
+                "layer 0: LCTL_T(KC_W) is only a modifier; KC_W is lost",
+                "layer 1: RGB_TOG has no equivalent; left empty",
+            ]
+        );
+        let imported = parse::layers(&tables, "ergodox");
+        let names: Vec<_> = imported.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["LAYOUT", "LAYOUT_1"]);
+        assert_eq!(imported[0].rows.len(), ROWS);
+        assert_eq!(imported[0].rows[0][..2], ["Q", "LCtrl"]);
+        assert_eq!(imported[0].rows[1][..2], ["{Action::Layer(1)}", "__"]);
+        // A transparent key takes the key below it in the first layer.
+        assert_eq!(imported[1].rows[0][..2], ["Kb1", "LCtrl"]);
+        assert_eq!(imported[1].rows[1][..2], ["__", "__"]);
+    }
+
+    #[test]
+    fn import_needs_a_key_for_every_key_of_the_board() {
+        let layers = parse::layers(SOURCE, "ergodox");
+        let keymap = r#"{"layers": [["KC_A", "KC_B", "KC_C"]]}"#;
+        assert_eq!(
+            import(keymap, "ergodox", &layers).unwrap_err(),
+            "layer 0 has 3 keys, but the ergodox keymap has 4"
+        );
+    }
+}
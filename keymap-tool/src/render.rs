@@ -0,0 +1,125 @@
+//! Drawing keymaps as SVG, within an HTML page.
+
+use std::fmt::Write;
+
+use crate::board::{self, COLS, ROWS};
+use crate::parse::Layer;
+
+/// Size of a key, in pixels, including the gap around it.
+const UNIT: f32 = 54.0;
+
+/// Size of the gap between keys, in pixels.
+const GAP: f32 = 4.0;
+
+/// A short label for a key, as written in a `layout!` table.
+pub fn label(key: &str) -> String {
+    if let Some(action) = key.strip_prefix('{').and_then(|k| k.strip_suffix('}')) {
+        return action.trim().trim_start_matches("Action::").to_string();
+    }
+    let label = match key {
+        "__" => "",
+        "Kb0" => "0",
+        "Kb1" => "1",
+        "Kb2" => "2",
+        "Kb3" => "3",
+        "Kb4" => "4",
+        "Kb5" => "5",
+        "Kb6" => "6",
+        "Kb7" => "7",
+        "Kb8" => "8",
+        "Kb9" => "9",
+        "Minus" => "-",
+        "Equal" => "=",
+        "LBracket" => "[",
+        "RBracket" => "]",
+        "Bslash" | "NonUsBslash" => "\\",
+        "NonUsHash" => "#",
+        "SColon" => ";",
+        "Quote" => "'",
+        "Grave" => "`",
+        "Comma" => ",",
+        "Dot" => ".",
+        "Slash" => "/",
+        "BSpace" => "Bksp",
+        "Escape" => "Esc",
+        "Delete" => "Del",
+        "Insert" => "Ins",
+        "PgUp" => "PgUp",
+        "PgDown" => "PgDn",
+        "Application" => "Menu",
+        key => return key.strip_prefix("Kp").map_or(key.to_string(), |k| format!("Kp {}", k)),
+    };
+    label.to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Draw a layer of `board`, leaving out positions that are empty in every
+/// layer of the keymap, `all`.
+pub fn svg(board: &str, layer: &Layer, all: &[Layer]) -> String {
+    let mut keys = Vec::new();
+    for row in 0..ROWS {
+        for col in 0..COLS {
+            if all.iter().all(|l| l.key(row, col) == "__") {
+                continue;
+            }
+            if let Some(pos) = board::position(board, row, col) {
+                keys.push((pos, label(layer.key(row, col))));
+            }
+        }
+    }
+    let width = keys.iter().map(|(p, _)| p.x + 1.0).fold(0.0, f32::max) * UNIT;
+    let height = keys.iter().map(|(p, _)| p.y + p.h).fold(0.0, f32::max) * UNIT;
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">"#,
+        width, height
+    );
+    for (pos, label) in keys {
+        let (x, y) = (pos.x * UNIT, pos.y * UNIT);
+        let _ = writeln!(
+            svg,
+            r##"  <rect x="{}" y="{}" width="{}" height="{}" rx="4" fill="#fafafa" stroke="#444"/>"##,
+            x + GAP / 2.0,
+            y + GAP / 2.0,
+            UNIT - GAP,
+            pos.h * UNIT - GAP
+        );
+        let font = if label.chars().count() > 4 { 9 } else { 14 };
+        let _ = writeln!(
+            svg,
+            r#"  <text x="{}" y="{}" font-size="{}" text-anchor="middle" dominant-baseline="middle" font-family="sans-serif">{}</text>"#,
+            x + UNIT / 2.0,
+            y + pos.h * UNIT / 2.0,
+            font,
+            escape(&label)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Draw every layer of a keymap into an HTML page.
+pub fn html(board: &str, layers: &[Layer]) -> String {
+    let mut html = String::new();
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{} keymap</title></head>\n<body>",
+        escape(board)
+    );
+    for (index, layer) in layers.iter().enumerate() {
+        let _ = writeln!(html, "<h2>Layer {}: {}</h2>", index, escape(&layer.name));
+        if !layer.doc.is_empty() {
+            let _ = writeln!(html, "<p>{}</p>", escape(&layer.doc));
+        }
+        html.push_str(&svg(board, layer, layers));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
@@ -0,0 +1,392 @@
+//! Key code definitions.
+//!
+//! A key code's usage ID is its discriminant, and its name, as in the keymaps,
+//! is its `Debug` output. With the `std` feature, [`describe_report`] renders
+//! a keyboard report with those names, for host tools and test failures.
+
+#[cfg(feature = "std")]
+use std::string::String;
+
+#[allow(missing_docs)]
+/// Define a key code according to the HID specification.  Their names
+/// correspond to the american QWERTY layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum KeyCode {
+    /// The "no" key, a placeholder to express nothing.
+    __ = 0x00,
+    /// Error if too much keys are pressed at the same time.
+    ErrorRollOver,
+    /// The POST fail error.
+    PostFail,
+    /// An undefined error occured.
+    ErrorUndefined,
+    /// `a` and `A`.
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M, // 0x10
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    /// `1` and `!`.
+    Kb1,
+    /// `2` and `@`.
+    Kb2,
+    /// `3` and `#`.
+    Kb3, // 0x20
+    /// `4` and `$`.
+    Kb4,
+    /// `5` and `%`.
+    Kb5,
+    /// `6` and `^`.
+    Kb6,
+    /// `7` and `&`.
+    Kb7,
+    /// `8` and `*`.
+    Kb8,
+    /// `9` and `(`.
+    Kb9,
+    /// `0` and `)`.
+    Kb0,
+    Enter,
+    Escape,
+    BSpace,
+    Tab,
+    Space,
+    /// `-` and `_`.
+    Minus,
+    /// `=` and `+`.
+    Equal,
+    /// `[` and `{`.
+    LBracket,
+    /// `]` and `}`.
+    RBracket, // 0x30
+    /// `\` and `|`.
+    Bslash,
+    /// Non-US `#` and `~` (Typically near the Enter key).
+    NonUsHash,
+    /// `;` and `:`.
+    SColon,
+    /// `'` and `"`.
+    Quote,
+    // How to have ` as code?
+    /// \` and `~`.
+    Grave,
+    /// `,` and `<`.
+    Comma,
+    /// `.` and `>`.
+    Dot,
+    /// `/` and `?`.
+    Slash,
+    CapsLock,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7, // 0x40
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    PScreen,
+    ScrollLock,
+    Pause,
+    Insert,
+    Home,
+    PgUp,
+    Delete,
+    End,
+    PgDown,
+    Right,
+    Left, // 0x50
+    Down,
+    Up,
+    NumLock,
+    /// Keypad `/`
+    KpSlash,
+    /// Keypad `*`
+    KpAsterisk,
+    /// Keypad `-`.
+    KpMinus,
+    /// Keypad `+`.
+    KpPlus,
+    /// Keypad enter.
+    KpEnter,
+    /// Keypad 1.
+    Kp1,
+    Kp2,
+    Kp3,
+    Kp4,
+    Kp5,
+    Kp6,
+    Kp7,
+    Kp8, // 0x60
+    Kp9,
+    Kp0,
+    KpDot,
+    /// Non-US `\` and `|` (Typically near the Left-Shift key)
+    NonUsBslash,
+    Application, // 0x65
+    /// not a key, used for errors
+    Power,
+    /// Keypad `=`.
+    KpEqual,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21, // 0x70
+    F22,
+    F23,
+    F24,
+    Execute,
+    Help,
+    Menu,
+    Select,
+    Stop,
+    Again,
+    Undo,
+    Cut,
+    Copy,
+    Paste,
+    Find,
+    Mute,
+    VolUp, // 0x80
+    VolDown,
+    /// Deprecated.
+    LockingCapsLock,
+    /// Deprecated.
+    LockingNumLock,
+    /// Deprecated.
+    LockingScrollLock,
+    /// Keypad `,`, also used for the brazilian keypad period (.) key.
+    KpComma,
+    /// Used on AS/400 keyboard
+    KpEqualSign,
+    Intl1,
+    Intl2,
+    Intl3,
+    Intl4,
+    Intl5,
+    Intl6,
+    Intl7,
+    Intl8,
+    Intl9,
+    Lang1, // 0x90
+    Lang2,
+    Lang3,
+    Lang4,
+    Lang5,
+    Lang6,
+    Lang7,
+    Lang8,
+    Lang9,
+    AltErase,
+    SysReq,
+    Cancel,
+    Clear,
+    Prior,
+    Return,
+    Separator,
+    Out, // 0xA0
+    Oper,
+    ClearAgain,
+    CrSel,
+    ExSel,
+
+    // According to QMK, 0xA5-0xDF are not usable on modern keyboards
+
+    // Modifiers
+    /// Left Control.
+    LCtrl = 0xE0,
+    /// Left Shift.
+    LShift,
+    /// Left Alt.
+    LAlt,
+    /// Left GUI (the Windows key).
+    LGui,
+    /// Right Control.
+    RCtrl,
+    /// Right Shift.
+    RShift,
+    /// Right Alt (or Alt Gr).
+    RAlt,
+    /// Right GUI (the Windows key).
+    RGui, // 0xE7
+
+    // Unofficial
+    MediaPlayPause = 0xE8,
+    MediaStopCD,
+    MediaPreviousSong,
+    MediaNextSong,
+    MediaEjectCD,
+    MediaVolUp,
+    MediaVolDown,
+    MediaMute,
+    MediaWWW, // 0xF0
+    MediaBack,
+    MediaForward,
+    MediaStop,
+    MediaFind,
+    MediaScrollUp,
+    MediaScrollDown,
+    MediaEdit,
+    MediaSleep,
+    MediaCoffee,
+    MediaRefresh,
+    MediaCalc, // 0xFB
+}
+
+impl KeyCode {
+    /// The key code with the given usage ID, in the keyboard usage page, if
+    /// there is one.
+    pub fn from_usage(usage: u8) -> Option<Self> {
+        let defined = usage <= KeyCode::ExSel as u8
+            || (KeyCode::LCtrl as u8..=KeyCode::MediaCalc as u8).contains(&usage);
+        // Safety: `KeyCode` is `repr(u8)`, and its discriminants run without
+        // gaps up to `ExSel`, and again from `LCtrl` to the last, `MediaCalc`.
+        defined.then(|| unsafe { core::mem::transmute::<u8, KeyCode>(usage) })
+    }
+
+    /// The usage ID of the key code, in the keyboard usage page, as sent in
+    /// the keyboard report.
+    pub fn usage(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns `true` if the key code corresponds to a modifier (sent
+    /// separately on the USB HID report).
+    pub fn is_modifier(self) -> bool {
+        KeyCode::LCtrl <= self && self <= KeyCode::RGui
+    }
+
+    /// Returns the byte with the bit corresponding to the USB HID
+    /// modifier bitfield set.
+    pub fn as_modifier_bit(self) -> u8 {
+        if self.is_modifier() {
+            1 << (self as u8 - KeyCode::LCtrl as u8)
+        } else {
+            0
+        }
+    }
+
+    /// The consumer control usage that does the same as the key code, for
+    /// hosts that ignore media key codes.
+    pub fn consumer_usage(self) -> Option<u16> {
+        match self {
+            KeyCode::MediaPlayPause => Some(0xCD),
+            KeyCode::MediaStopCD | KeyCode::MediaStop => Some(0xB7),
+            KeyCode::MediaPreviousSong => Some(0xB6),
+            KeyCode::MediaNextSong => Some(0xB5),
+            KeyCode::MediaEjectCD => Some(0xB8),
+            KeyCode::MediaVolUp | KeyCode::VolUp => Some(0xE9),
+            KeyCode::MediaVolDown | KeyCode::VolDown => Some(0xEA),
+            KeyCode::MediaMute | KeyCode::Mute => Some(0xE2),
+            _ => None,
+        }
+    }
+}
+
+/// Render the keys held in a keyboard report, such as `LShift+A`, or `none`.
+///
+/// The report is the 8 byte boot keyboard report: modifiers, a reserved byte,
+/// and then up to 6 key codes. A usage that isn't a `KeyCode` is rendered in
+/// hex.
+#[cfg(feature = "std")]
+pub fn describe_report(report: &[u8]) -> String {
+    use std::format;
+    use std::vec::Vec;
+
+    let modifiers = report.first().copied().unwrap_or(0);
+    let mut names: Vec<String> = (0..8)
+        .filter(|bit| modifiers & (1 << bit) != 0)
+        .filter_map(|bit| KeyCode::from_usage(KeyCode::LCtrl as u8 + bit))
+        .map(|kc| format!("{:?}", kc))
+        .collect();
+    for &usage in report.iter().skip(2).filter(|&&usage| usage != 0) {
+        names.push(match KeyCode::from_usage(usage) {
+            Some(kc) => format!("{:?}", kc),
+            None => format!("{:#04x}", usage),
+        });
+    }
+    if names.is_empty() {
+        String::from("none")
+    } else {
+        names.join("+")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usages_round_trip() {
+        for usage in 0..=u8::MAX {
+            if let Some(kc) = KeyCode::from_usage(usage) {
+                assert_eq!(kc as u8, usage);
+                assert_eq!(kc.usage(), usage);
+            }
+        }
+        assert_eq!(KeyCode::from_usage(KeyCode::A as u8), Some(KeyCode::A));
+        assert_eq!(
+            KeyCode::from_usage(KeyCode::MediaCalc as u8),
+            Some(KeyCode::MediaCalc)
+        );
+    }
+
+    #[test]
+    fn unknown_usages_have_no_key_code() {
+        assert_eq!(KeyCode::from_usage(KeyCode::ExSel as u8 + 1), None);
+        assert_eq!(KeyCode::from_usage(KeyCode::LCtrl as u8 - 1), None);
+        assert_eq!(KeyCode::from_usage(KeyCode::MediaCalc as u8 + 1), None);
+        assert_eq!(KeyCode::from_usage(u8::MAX), None);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn describes_reports() {
+        let shift = KeyCode::LShift.as_modifier_bit();
+        assert_eq!(
+            describe_report(&[shift, 0, KeyCode::A as u8, 0, 0, 0, 0, 0]),
+            "LShift+A"
+        );
+        assert_eq!(describe_report(&[0; 8]), "none");
+        assert_eq!(describe_report(&[]), "none");
+        let rollover = KeyCode::ErrorRollOver as u8;
+        assert_eq!(
+            describe_report(&[0, 0, rollover, rollover, rollover, rollover, rollover, rollover]),
+            "ErrorRollOver+ErrorRollOver+ErrorRollOver+ErrorRollOver+ErrorRollOver+ErrorRollOver"
+        );
+        assert_eq!(describe_report(&[0, 0, 0xa5, 0, 0, 0, 0, 0]), "0xa5");
+    }
+}
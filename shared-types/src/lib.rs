@@ -1,5 +1,19 @@
 #![no_std]
+//! Types shared between the firmware and the host tools.
+//!
+//! The `serde` feature derives `Serialize` and `Deserialize` for each type, for
+//! host tools, and the `defmt` feature derives `defmt::Format`, for logging
+//! them from firmware. The `std` feature adds helpers that allocate, for host
+//! tools.
+
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod key_code;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum DebState {
     StableU,
@@ -11,16 +25,75 @@ pub enum DebState {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum PressRelease {
     None,
     Press,
     Release,
+    /// Too many presses were seen, and presses are being dropped from now on.
+    /// The row and column are those of the press that was one too many.
+    Throttle,
+    /// The debouncer switched to reporting changes as soon as they're seen.
+    /// The row and column are both 0.
+    EagerDebounce,
+    /// The debouncer switched to reporting changes once keys are stable. The
+    /// row and column are both 0.
+    DeferredDebounce,
+    /// A `LogMarker` key was pressed, to mark a point in the log that a capture
+    /// may start from. The row is the id of the marker, and the column is 0.
+    Marker,
+    /// A key code was added to the keyboard report sent to the host. The row
+    /// is its usage, and the column is 0.
+    ReportAdd,
+    /// A key code was removed from the keyboard report sent to the host. The
+    /// row is its usage, and the column is 0.
+    ReportRemove,
+}
+
+impl DebState {
+    /// Every state, in the order of their discriminants.
+    const ALL: [Self; 6] = [
+        Self::StableU,
+        Self::BouncingUD,
+        Self::BouncingUU,
+        Self::StableD,
+        Self::BouncingDD,
+        Self::BouncingDU,
+    ];
+
+    /// The state with the given discriminant, if there is one.
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        Self::ALL.get(byte as usize).copied()
+    }
+}
+
+impl PressRelease {
+    /// Every event, in the order of their discriminants.
+    const ALL: [Self; 9] = [
+        Self::None,
+        Self::Press,
+        Self::Release,
+        Self::Throttle,
+        Self::EagerDebounce,
+        Self::DeferredDebounce,
+        Self::Marker,
+        Self::ReportAdd,
+        Self::ReportRemove,
+    ];
+
+    /// The event with the given discriminant, if there is one.
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        Self::ALL.get(byte as usize).copied()
+    }
 }
 
 /// A packed representation of any debounce event used for observing the state
 /// of debouncing with a debugger.
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(C)]
 pub struct KeyState {
     /// The Time that this state change happened
@@ -46,3 +119,65 @@ impl Default for KeyState {
         }
     }
 }
+
+impl KeyState {
+    /// Size of a `KeyState`, in the firmware's memory and as bytes.
+    pub const SIZE: usize = 8;
+
+    /// Parse a `KeyState` from its representation in the firmware's memory,
+    /// which is little endian, returning `None` if either enum is out of range,
+    /// as it may be if the firmware was built with a different `KeyState`.
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Option<Self> {
+        let [t0, t1, t2, t3, row, col, deb, event] = bytes;
+        Some(Self {
+            timestamp: u32::from_le_bytes([t0, t1, t2, t3]),
+            row,
+            col,
+            deb: DebState::from_u8(deb)?,
+            event: PressRelease::from_u8(event)?,
+        })
+    }
+
+    /// The representation of a `KeyState` in the firmware's memory, as parsed
+    /// by `from_bytes`.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..4].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[4..].copy_from_slice(&[self.row, self.col, self.deb as u8, self.event as u8]);
+        bytes
+    }
+}
+
+const _: () = assert!(core::mem::size_of::<KeyState>() == KeyState::SIZE);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryFrom;
+
+    #[test]
+    fn key_states_round_trip_through_bytes() {
+        let state = KeyState {
+            timestamp: 0x1234_5678,
+            row: 12,
+            col: 5,
+            deb: DebState::BouncingDU,
+            event: PressRelease::ReportRemove,
+        };
+        assert_eq!(state.to_bytes()[..4], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(KeyState::from_bytes(state.to_bytes()), Some(state));
+    }
+
+    #[test]
+    fn short_or_invalid_bytes_are_not_key_states() {
+        let bytes = KeyState::default().to_bytes();
+        let short = <[u8; KeyState::SIZE]>::try_from(&bytes[..KeyState::SIZE - 1]).ok();
+        assert_eq!(short.and_then(KeyState::from_bytes), None);
+        let mut bad_deb = bytes;
+        bad_deb[6] = DebState::ALL.len() as u8;
+        assert_eq!(KeyState::from_bytes(bad_deb), None);
+        let mut bad_event = bytes;
+        bad_event[7] = PressRelease::ALL.len() as u8;
+        assert_eq!(KeyState::from_bytes(bad_event), None);
+    }
+}
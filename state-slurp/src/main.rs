@@ -1,17 +1,203 @@
 use core::mem::size_of;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::env;
+use std::thread;
 
 use ddbug_parser::{File, FileHash};
 
 use probe_rs::MemoryInterface;
-use probe_rs::Session;
+use probe_rs::{Core, Probe, Session};
 
 use shared_types::{KeyState, DebState, PressRelease};
+use shared_types::key_code::KeyCode;
 
 fn event_at(buf: &[u32], i: usize) -> KeyState {
-    let event = [buf[i * 2], buf[i * 2 + 1]];
-    unsafe { core::mem::transmute(event) }
+    let mut bytes = [0; KeyState::SIZE];
+    bytes[..4].copy_from_slice(&buf[i * 2].to_le_bytes());
+    bytes[4..].copy_from_slice(&buf[i * 2 + 1].to_le_bytes());
+    KeyState::from_bytes(bytes)
+        .expect("the log doesn't match shared-types; rebuild state-slurp with the firmware")
+}
+
+/// Nanoseconds since the firmware started scanning, at the given timestamp.
+fn ns(timestamp: u32) -> u64 {
+    (timestamp as u64) * (1_000_000_000 / 2_000)
+}
+
+/// What starts a capture.
+enum Trigger {
+    /// The key at (row, col) starts bouncing.
+    Key(u8, u8),
+    /// A `LogMarker` key, of any id, is pressed.
+    Marker,
+}
+
+impl Trigger {
+    fn fires(&self, event: &KeyState) -> bool {
+        match self {
+            Trigger::Key(row, col) => {
+                event.row == *row
+                    && event.col == *col
+                    && event.deb != DebState::StableU
+                    && event.deb != DebState::StableD
+            }
+            Trigger::Marker => event.event == PressRelease::Marker,
+        }
+    }
+}
+
+/// Read the records from `from` up to, but not including, `to` out of the log,
+/// wrapping around its end.
+fn read_records(core: &mut Core, body: u64, size: u64, from: u64, to: u64) -> Vec<KeyState> {
+    let words = KeyState::SIZE / size_of::<u32>();
+    let mut records = Vec::new();
+    let ranges = if from <= to {
+        [(from, to), (0, 0)]
+    } else {
+        [(from, size), (0, to)]
+    };
+    for &(start, end) in ranges.iter().filter(|(start, end)| start < end) {
+        let mut buf = vec![0; (end - start) as usize * words];
+        let address = body + start * KeyState::SIZE as u64;
+        core.read_32(address as u32, &mut buf).unwrap();
+        records.extend((0..(end - start) as usize).map(|i| event_at(&buf, i)));
+    }
+    records
+}
+
+/// Print the header of the output, with the time of the first record.
+fn print_header(start_time: u64) {
+    println!(r#"{{
+        "title": "keyboard debouncing",
+        "start": [0, {}],
+        "states": {{
+            "stable-release": {{ "value": 0, "color": "white"}},
+            "bouncing-rel-to-pre": {{ "value": 1,  "color": "blue"}},
+            "bouncing-rel-to-rel": {{ "value": 2, "color": "brown" }},
+            "emit-release": {{ "value" : 3, "color": "white" }},
+            "stable-press": {{ "value": 4, "color": "grey" }},
+            "bouncing-pre-to-pre": {{ "value": 5, "color": "yellow" }},
+            "bouncing-pre-to-rel": {{ "value": 6, "color": "orange" }},
+            "emit-press": {{ "value" : 7, "color": "black" }},
+            "throttle": {{ "value" : 8, "color": "red" }},
+            "eager-debounce": {{ "value" : 9, "color": "green" }},
+            "deferred-debounce": {{ "value" : 10, "color": "purple" }},
+            "marker": {{ "value" : 11, "color": "cyan" }},
+            "report-held": {{ "value" : 12, "color": "black" }},
+            "report-released": {{ "value" : 13, "color": "white" }}
+        }}
+    }}"#, start_time);
+}
+
+/// The name of the key code with the given usage, or the usage in hex.
+fn usage_name(usage: u8) -> String {
+    KeyCode::from_usage(usage)
+        .map(|kc| format!("{:?}", kc))
+        .unwrap_or_else(|| format!("{:#04x}", usage))
+}
+
+/// Print a record, timed relative to the first record.
+fn print_event(event: &KeyState, start_time: u64) {
+    let ns_time = ns(event.timestamp) - start_time;
+    let (entity, state) = match event.event {
+        PressRelease::EagerDebounce => ("debounce-mode", 9),
+        PressRelease::DeferredDebounce => ("debounce-mode", 10),
+        PressRelease::Marker => {
+            println!(r#"{{
+                "entity": "marker",
+                "time": "{}",
+                "state": 11,
+                "tag": "{}"
+            }}"#, ns_time, event.row);
+            return;
+        }
+        PressRelease::ReportAdd | PressRelease::ReportRemove => {
+            let state = if event.event == PressRelease::ReportAdd { 12 } else { 13 };
+            println!(r#"{{
+                "entity": "report-{}",
+                "time": "{}",
+                "state": {},
+                "tag": null
+            }}"#, usage_name(event.row), ns_time, state);
+            return;
+        }
+        _ => ("", 0),
+    };
+    if !entity.is_empty() {
+        println!(r#"{{
+            "entity": "{}",
+            "time": "{}",
+            "state": {},
+            "tag": null
+        }}"#, entity, ns_time, state);
+        return;
+    }
+    println!(r#"{{
+        "entity": "{}-{}-debouncer",
+        "time": "{}",
+        "state": {},
+        "tag": null
+    }}"#, event.row, event.col, ns_time, match event.deb {
+        DebState::StableU    => 0,
+        DebState::BouncingUD => 1,
+        DebState::BouncingUU => 2,
+        DebState::StableD    => 4,
+        DebState::BouncingDD => 5,
+        DebState::BouncingDU => 6,
+    });
+    if event.event != PressRelease::None {
+        println!(r#"{{
+            "entity": "{}-{}-trigger",
+            "time": "{}",
+            "state": {},
+            "tag": null
+        }}"#, event.row, event.col, ns_time, match event.event {
+            PressRelease::Press   => 7,
+            PressRelease::Release => 3,
+            PressRelease::Throttle => 8,
+            PressRelease::None
+            | PressRelease::EagerDebounce
+            | PressRelease::DeferredDebounce
+            | PressRelease::Marker
+            | PressRelease::ReportAdd
+            | PressRelease::ReportRemove => unreachable!(),
+        });
+    }
+}
+
+/// Poll the log until the trigger fires, then print the `count` records from
+/// the one that fired it on.
+///
+/// Only the records logged since the last poll are read, so this keeps up for
+/// as long as the firmware logs fewer records between polls than fit in the
+/// log; see the estimate above.
+fn capture(core: &mut Core, head: u64, body: u64, size: u64, trigger: &Trigger, count: usize) {
+    let mut last = core.read_word_32(head as u32).unwrap() as u64;
+    let mut start_time = None;
+    let mut printed = 0;
+    eprintln!("Waiting for the trigger...");
+    while printed < count {
+        let now = core.read_word_32(head as u32).unwrap() as u64;
+        if now == last {
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+        for event in read_records(core, body, size, last, now) {
+            if start_time.is_none() && trigger.fires(&event) {
+                start_time = Some(ns(event.timestamp));
+                print_header(ns(event.timestamp));
+            }
+            if let Some(start_time) = start_time {
+                print_event(&event, start_time);
+                printed += 1;
+                if printed == count {
+                    break;
+                }
+            }
+        }
+        last = now;
+    }
+    eprintln!("Captured {} records", printed);
 }
 
 // The debugger reads 20480 bytes in 800ms (it's very stable too), or 25.6kbps.
@@ -32,11 +218,52 @@ fn event_at(buf: &[u32], i: usize) -> KeyState {
 // second for longer than about 1/3 of a second, it will overflow and you will
 // lose events. Don't type that fast.
 
+/// Attach to the keyboard on the probe with the given serial number, or, with
+/// `None`, on the only probe that's plugged in. If that's not exactly one
+/// probe, list the probes and exit, so that one may be picked with `--probe`.
+fn attach(serial: Option<&str>) -> Session {
+    let probes = Probe::list_all();
+    let matching: Vec<_> = probes
+        .iter()
+        .filter(|probe| serial.map_or(true, |s| probe.serial_number.as_deref() == Some(s)))
+        .collect();
+    if let [probe] = matching.as_slice() {
+        return probe.open().unwrap().attach("stm32f103c8").unwrap();
+    }
+    eprintln!("{} probes match; pick one with --probe <serial>:", matching.len());
+    for probe in &probes {
+        eprintln!(
+            "  {} ({})",
+            probe.serial_number.as_deref().unwrap_or("no serial number"),
+            probe.identifier
+        );
+    }
+    std::process::exit(1);
+}
+
 fn main() {
     let mut head_address = None;
     let mut body_address = None;
     let mut body_size = None;
-    for path in env::args().skip(1) {
+    let mut serial = None;
+    let mut trigger = None;
+    let mut count = None;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--probe" => serial = args.next(),
+            "--trigger-key" => {
+                let key = args.next().expect("--trigger-key takes <row>,<col>");
+                let (row, col) = key.split_once(',').expect("--trigger-key takes <row>,<col>");
+                trigger = Some(Trigger::Key(row.parse().unwrap(), col.parse().unwrap()));
+            }
+            "--trigger-marker" => trigger = Some(Trigger::Marker),
+            "--records" => count = args.next().map(|n| n.parse::<usize>().unwrap()),
+            _ => paths.push(arg),
+        }
+    }
+    for path in paths {
         File::parse(&path, |file| {
             let hash = FileHash::new(file);
             for unit in file.units() {
@@ -55,7 +282,7 @@ fn main() {
                                         |a| a.wrapping_add(member.bit_offset() / 8)
                                     );
                                     body_size = member.bit_size(&hash).map(
-                                        |s| s / ((size_of::<KeyState>() * 8) as u64)
+                                        |s| s / ((KeyState::SIZE * 8) as u64)
                                     );
                                 }
                             }
@@ -69,57 +296,22 @@ fn main() {
     let head = head_address.unwrap();
     let body = body_address.unwrap();
     let size = body_size.unwrap();
-    let mut sesh = Session::auto_attach("stm32f103c8").unwrap();
+    let mut sesh = attach(serial.as_deref());
     let mut core = sesh.core(0).unwrap();
+    if let Some(trigger) = trigger {
+        capture(&mut core, head, body, size, &trigger, count.unwrap_or(size as usize));
+        return;
+    }
     let head_val = core.read_word_32(head as u32).unwrap() as u64;
     assert!((head_val as u64) < size);
-    let mut buf = vec![0; size as usize * (size_of::<KeyState>() / size_of::<u32>())];
+    let mut buf = vec![0; size as usize * (KeyState::SIZE / size_of::<u32>())];
     let before = Instant::now();
     core.read_32(body as u32, &mut buf).unwrap();
     let duration = before.elapsed();
-    let start_time = (event_at(&buf, head_val as usize).timestamp as u64) * (1_000_000_000 / 2_000);
-    println!(r#"{{
-        "title": "keyboard debouncing",
-        "start": [0, {}],
-        "states": {{
-            "stable-release": {{ "value": 0, "color": "white"}},
-            "bouncing-rel-to-pre": {{ "value": 1,  "color": "blue"}},
-            "bouncing-rel-to-rel": {{ "value": 2, "color": "brown" }},
-            "emit-release": {{ "value" : 3, "color": "white" }},
-            "stable-press": {{ "value": 4, "color": "grey" }},
-            "bouncing-pre-to-pre": {{ "value": 5, "color": "yellow" }},
-            "bouncing-pre-to-rel": {{ "value": 6, "color": "orange" }},
-            "emit-press": {{ "value" : 7, "color": "black" }}
-        }}
-    }}"#, start_time);
+    let start_time = ns(event_at(&buf, head_val as usize).timestamp);
+    print_header(start_time);
     for i in (head_val..size).chain(0..head_val) {
-        let event = event_at(&buf, i as usize);
-        let ns_time = ((event.timestamp as u64) * (1_000_000_000 / 2_000)) - start_time;
-        println!(r#"{{
-            "entity": "{}-{}-debouncer",
-            "time": "{}",
-            "state": {},
-            "tag": null
-        }}"#, event.row, event.col, ns_time, match event.deb {
-            DebState::StableU    => 0,
-            DebState::BouncingUD => 1,
-            DebState::BouncingUU => 2,
-            DebState::StableD    => 4,
-            DebState::BouncingDD => 5,
-            DebState::BouncingDU => 6,
-        });
-        if event.event != PressRelease::None {
-            println!(r#"{{
-                "entity": "{}-{}-trigger",
-                "time": "{}",
-                "state": {},
-                "tag": null
-            }}"#, event.row, event.col, ns_time, match event.event {
-                PressRelease::Press   => 7,
-                PressRelease::Release => 3,
-                PressRelease::None    => unreachable!(),
-            });
-        }
+        print_event(&event_at(&buf, i as usize), start_time);
     }
     eprintln!("Slurped {} records in {:?}", size, duration);
 }